@@ -3,9 +3,12 @@ use std::fmt;
 use std::ops::{Add, AddAssign, Div, Mul, SubAssign};
 
 use crate::geometry::ErrorKind::OutOfBounds;
+use crate::Float;
 
 use self::Quadrant::*;
 
+pub mod sum;
+pub mod three;
 pub mod util;
 
 // Point /////////////////////////////////////////////////////////////////////
@@ -14,8 +17,8 @@ pub mod util;
 
 #[derive(Clone, PartialEq, Debug)]
 pub struct Point {
-    pub x: f32,
-    pub y: f32,
+    pub x: Float,
+    pub y: Float,
 }
 
 impl Add for Point {
@@ -42,18 +45,18 @@ impl SubAssign for Point {
     }
 }
 
-impl<'a> Mul<f32> for &'a Point {
+impl Mul<Float> for &Point {
     type Output = Point;
 
-    fn mul(self, rhs: f32) -> Self::Output {
+    fn mul(self, rhs: Float) -> Self::Output {
         Point::new(self.x * rhs, self.y * rhs)
     }
 }
 
-impl<'a> Div<f32> for &'a Point {
+impl Div<Float> for &Point {
     type Output = Point;
 
-    fn div(self, rhs: f32) -> Self::Output {
+    fn div(self, rhs: Float) -> Self::Output {
         Point::new(
             self.x / rhs,
             self.y / rhs
@@ -68,7 +71,7 @@ impl From<Vector> for Point {
 }
 
 impl Point {
-    pub fn new(x: f32, y: f32) -> Point {
+    pub fn new(x: Float, y: Float) -> Point {
         Point { x, y }
     }
 
@@ -80,7 +83,7 @@ impl Point {
         self == &Point::zero()
     }
 
-    pub fn distance_to(&self, other: &Point) -> f32 {
+    pub fn distance_to(&self, other: &Point) -> Float {
         let difference = Vector::difference(self, other);
         difference.magnitude()
     }
@@ -92,8 +95,8 @@ impl Point {
 
 #[derive(Clone, Debug)]
 pub struct Vector {
-    pub dx: f32,
-    pub dy: f32,
+    pub dx: Float,
+    pub dy: Float,
 }
 
 impl PartialEq for Vector {
@@ -123,10 +126,10 @@ impl AddAssign for Vector {
     }
 }
 
-impl<'a> Mul<f32> for &'a Vector {
+impl Mul<Float> for &Vector {
     type Output = Vector;
 
-    fn mul(self, scalar: f32) -> Self::Output {
+    fn mul(self, scalar: Float) -> Self::Output {
         Vector {
             dx: self.dx * scalar,
             dy: self.dy * scalar,
@@ -134,10 +137,10 @@ impl<'a> Mul<f32> for &'a Vector {
     }
 }
 
-impl<'a> Div<f32> for &'a Vector {
+impl Div<Float> for &Vector {
     type Output = Vector;
 
-    fn div(self, scalar: f32) -> Self::Output {
+    fn div(self, scalar: Float) -> Self::Output {
         Vector {
             dx: self.dx / scalar,
             dy: self.dy / scalar,
@@ -145,8 +148,8 @@ impl<'a> Div<f32> for &'a Vector {
     }
 }
 
-impl<'a> Mul for &'a Vector {
-    type Output = f32;
+impl Mul for &Vector {
+    type Output = Float;
 
     fn mul(self, rhs: &Vector) -> Self::Output {
         self.dx * rhs.dx + self.dy * rhs.dy
@@ -154,7 +157,7 @@ impl<'a> Mul for &'a Vector {
 }
 
 impl Vector {
-    pub fn new(dx: f32, dy: f32) -> Vector {
+    pub fn new(dx: Float, dy: Float) -> Vector {
         Vector { dx, dy }
     }
 
@@ -169,7 +172,7 @@ impl Vector {
         }
     }
 
-    pub fn magnitude(&self) -> f32 {
+    pub fn magnitude(&self) -> Float {
         (self.dx * self.dx + self.dy * self.dy).sqrt()
     }
 
@@ -218,7 +221,7 @@ pub struct Square {
 
 impl Square {
     /// Creates a new Square with size = 2^exponent.
-    pub fn new(x: f32, y: f32, exponent: u32) -> Square {
+    pub fn new(x: Float, y: Float, exponent: u32) -> Square {
         Square {
             origin: Point::new(x,y),
             size: u32::pow(2, exponent),
@@ -231,8 +234,8 @@ impl Square {
     }
 
     /// Returns the length of the hypotenuse.
-    pub fn diameter(&self) -> f32 {
-        let x = (self.size as f32).powi(2);
+    pub fn diameter(&self) -> Float {
+        let x = (self.size as Float).powi(2);
         (2.0 * x).sqrt()
     }
 
@@ -242,6 +245,28 @@ impl Square {
             point.x <= self.upper_bound().x && point.y <= self.upper_bound().y
     }
 
+    /// Returns the distance from the given point to the nearest point of
+    /// self, which is zero if the point is contained.
+    pub fn distance_to(&self, point: &Point) -> Float {
+        let upper = self.upper_bound();
+        let dx = (self.origin.x - point.x).max(point.x - upper.x).max(0.0);
+        let dy = (self.origin.y - point.y).max(point.y - upper.y).max(0.0);
+        (dx * dx + dy * dy).sqrt()
+    }
+
+    /// Returns true if self and the given square overlap. Squares sharing
+    /// only an edge or a corner overlap, as both contain their boundary.
+    pub fn intersects(&self, other: &Square) -> bool {
+        let (upper, other_upper) = (self.upper_bound(), other.upper_bound());
+        self.origin.x <= other_upper.x && other.origin.x <= upper.x &&
+            self.origin.y <= other_upper.y && other.origin.y <= upper.y
+    }
+
+    /// Returns true if the given square lies within self.
+    pub fn encloses(&self, other: &Square) -> bool {
+        self.contains(&other.origin) && self.contains(&other.upper_bound())
+    }
+
     /// Returns a partition of self in the order northeast, northwest,
     /// southeast, southwest. If the width and height of self are even,
     /// then the four quadrants are of equal size. Otherwise the boundaries
@@ -254,9 +279,9 @@ impl Square {
         let size = self.size >> 1;
 
         let sw = Square { origin: Point::new(x, y), size };
-        let se = Square { origin: Point::new(x + size as f32, y), size };
-        let nw = Square { origin: Point::new(x, y + size as f32), size };
-        let ne = Square { origin: Point::new(x + size as f32, y + size as f32), size };
+        let se = Square { origin: Point::new(x + size as Float, y), size };
+        let nw = Square { origin: Point::new(x, y + size as Float), size };
+        let ne = Square { origin: Point::new(x + size as Float, y + size as Float), size };
         (NW(nw), NE(ne), SW(sw), SE(se))
     }
 
@@ -271,10 +296,27 @@ impl Square {
                         else                        { Err(Error(OutOfBounds)) }
     }
 
+    /// Returns the position of the quadrant containing the given point in
+    /// the order northwest, northeast, southwest, southeast. Points on the
+    /// boundary between quadrants are resolved in the same way as in
+    /// `quadrant`. The point is assumed to be contained by self.
+    pub fn child_offset(&self, point: &Point) -> usize {
+        let half = (self.size >> 1) as Float;
+        let west = point.x <= self.origin.x + half;
+        let north = point.y >= self.origin.y + half;
+
+        match (north, west) {
+            (true, true) => 0,
+            (true, false) => 1,
+            (false, true) => 2,
+            (false, false) => 3,
+        }
+    }
+
     fn upper_bound(&self) -> Point {
         Point {
-            x: self.origin.x + self.size as f32,
-            y: self.origin.y + self.size as f32,
+            x: self.origin.x + self.size as Float,
+            y: self.origin.y + self.size as Float,
         }
     }
 }
@@ -454,7 +496,43 @@ mod tests {
         let result = sut.diameter();
 
         // then
-        assert_eq!(5.65685424949, result);
+        assert_close!(5.656_854, result);
+    }
+
+    #[test]
+    fn rect_distance_to_point() {
+        // given
+        let sut = Square::new(0.0, 0.0, 2);
+
+        // then
+        assert_eq!(0.0, sut.distance_to(&Point::new(1.0, 4.0)));
+        assert_eq!(2.0, sut.distance_to(&Point::new(2.0, -2.0)));
+        assert_eq!(5.0, sut.distance_to(&Point::new(7.0, 8.0)));
+    }
+
+    #[test]
+    fn rect_encloses_rect() {
+        // given
+        let sut = Square::new(0.0, 0.0, 2);
+
+        // then
+        assert!(sut.encloses(&sut));
+        assert!(sut.encloses(&Square::new(2.0, 2.0, 1)));
+        assert!(!sut.encloses(&Square::new(3.0, 3.0, 1)));
+        assert!(!sut.encloses(&Square::new(-2.0, -2.0, 3)));
+    }
+
+    #[test]
+    fn rect_intersects_rect() {
+        // given
+        let sut = Square::new(0.0, 0.0, 2);
+
+        // then
+        assert!(sut.intersects(&Square::new(1.0, 1.0, 1)));
+        assert!(sut.intersects(&Square::new(-2.0, -2.0, 3)));
+        assert!(sut.intersects(&Square::new(4.0, 4.0, 0)));
+        assert!(!sut.intersects(&Square::new(5.0, 0.0, 1)));
+        assert!(!sut.intersects(&Square::new(-1.0, -2.0, 0)));
     }
 
     #[test]
@@ -497,6 +575,26 @@ mod tests {
         assert!(!sut.contains(&Point::new(1.0, 40.01)));
     }
 
+    #[test]
+    fn rect_child_offset_matches_quadrant() {
+        // given
+        let sut = Square::new(0.0, 0.0, 3);
+        let (nw, ne, sw, se) = sut.quadrants();
+        let quadrants = [nw, ne, sw, se];
+
+        let points = [
+            Point::new(0.0, 4.0), Point::new(4.0, 4.0), Point::new(4.0, 0.0),
+            Point::new(5.0, 4.0), Point::new(4.0, 3.9), Point::new(1.0, 6.0),
+            Point::new(6.3, 6.8), Point::new(2.0, 1.0), Point::new(8.0, 0.0),
+        ];
+
+        // then
+        for point in points.iter() {
+            let expected = sut.quadrant(point).unwrap();
+            assert_eq!(expected, quadrants[sut.child_offset(point)]);
+        }
+    }
+
     #[test]
     fn rect_which_quadrant() {
         // given
@@ -0,0 +1,175 @@
+use std::ops::AddAssign;
+
+use crate::geometry::three::Vector3;
+use crate::geometry::Vector;
+use crate::Float;
+
+// Compensated Summation /////////////////////////////////////////////////////
+//
+// Adding many terms to a running sum loses the low order bits of each term
+// that are below the precision of the sum. Neumaier's variant of Kahan
+// summation keeps these lost bits in a separate compensation term, which
+// is added back when the sum is read. The error of the result is then
+// independent of the number of terms, at the cost of a few extra flops per
+// term.
+//
+// The compiler doesn't reorder floating point arithmetic, so the
+// compensation isn't optimised away.
+
+/// Adds x to the sum, keeping the rounding error in the compensation. The
+/// branch compiles to a select, so loops calling this can be vectorised.
+#[inline]
+pub fn add(sum: &mut Float, compensation: &mut Float, x: Float) {
+    let total = *sum + x;
+    *compensation += if sum.abs() >= x.abs() {
+        (*sum - total) + x
+    } else {
+        (x - total) + *sum
+    };
+    *sum = total;
+}
+
+// Sum ///////////////////////////////////////////////////////////////////////
+
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub struct Sum {
+    sum: Float,
+    compensation: Float,
+}
+
+impl AddAssign<Float> for Sum {
+    fn add_assign(&mut self, rhs: Float) {
+        add(&mut self.sum, &mut self.compensation, rhs);
+    }
+}
+
+impl Sum {
+    pub fn new() -> Sum {
+        Self::default()
+    }
+
+    /// Returns the compensated sum.
+    pub fn value(&self) -> Float {
+        self.sum + self.compensation
+    }
+}
+
+// VectorSum /////////////////////////////////////////////////////////////////
+
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub struct VectorSum {
+    dx: Sum,
+    dy: Sum,
+}
+
+impl AddAssign<Vector> for VectorSum {
+    fn add_assign(&mut self, rhs: Vector) {
+        self.dx += rhs.dx;
+        self.dy += rhs.dy;
+    }
+}
+
+impl VectorSum {
+    pub fn new() -> VectorSum {
+        Self::default()
+    }
+
+    /// Returns the compensated sum.
+    pub fn value(&self) -> Vector {
+        Vector::new(self.dx.value(), self.dy.value())
+    }
+}
+
+// VectorSum3 ////////////////////////////////////////////////////////////////
+
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub struct VectorSum3 {
+    dx: Sum,
+    dy: Sum,
+    dz: Sum,
+}
+
+impl AddAssign<Vector3> for VectorSum3 {
+    fn add_assign(&mut self, rhs: Vector3) {
+        self.dx += rhs.dx;
+        self.dy += rhs.dy;
+        self.dz += rhs.dz;
+    }
+}
+
+impl VectorSum3 {
+    pub fn new() -> VectorSum3 {
+        Self::default()
+    }
+
+    /// Returns the compensated sum.
+    pub fn value(&self) -> Vector3 {
+        Vector3::new(self.dx.value(), self.dy.value(), self.dz.value())
+    }
+}
+
+// Tests /////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sum_keeps_small_terms() {
+        // given a large term that absorbs the small ones
+        let big: Float = 1.0 / Float::EPSILON;
+        let mut sut = Sum::new();
+        let mut naive = 0.0;
+
+        // when
+        for x in [big, 0.25, 0.25, 0.25, 0.25, -big].iter() {
+            sut += *x;
+            naive += *x;
+        }
+
+        // then
+        assert_eq!(1.0, sut.value());
+        assert_eq!(0.0, naive);
+    }
+
+    #[test]
+    fn sum_compensates_smaller_sum() {
+        // given a small sum followed by a large term
+        let big: Float = 1.0 / Float::EPSILON;
+        let mut sut = Sum::new();
+
+        // when
+        for x in [0.25, big, 0.25, -big].iter() {
+            sut += *x;
+        }
+
+        // then
+        assert_eq!(0.5, sut.value());
+    }
+
+    #[test]
+    fn vector_sum_adds_components() {
+        // given
+        let mut sut = VectorSum::new();
+
+        // when
+        sut += Vector::new(1.5, -2.0);
+        sut += Vector::new(0.5, 4.0);
+
+        // then
+        assert_eq!(Vector::new(2.0, 2.0), sut.value());
+    }
+
+    #[test]
+    fn vector_sum3_adds_components() {
+        // given
+        let mut sut = VectorSum3::new();
+
+        // when
+        sut += Vector3::new(1.5, -2.0, 0.25);
+        sut += Vector3::new(0.5, 4.0, 0.75);
+
+        // then
+        assert_eq!(Vector3::new(2.0, 2.0, 1.0), sut.value());
+    }
+}
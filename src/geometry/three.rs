@@ -0,0 +1,319 @@
+use std::ops::{Add, AddAssign, Div, Mul, SubAssign};
+
+use crate::Float;
+
+// Point3 ////////////////////////////////////////////////////////////////////
+//
+// Coordinates in 3D space.
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Point3 {
+    pub x: Float,
+    pub y: Float,
+    pub z: Float,
+}
+
+impl Add for Point3 {
+    type Output = Point3;
+
+    fn add(self, rhs: Point3) -> Self::Output {
+        Point3::new(self.x + rhs.x, self.y + rhs.y, self.z + rhs.z)
+    }
+}
+
+impl AddAssign for Point3 {
+    fn add_assign(&mut self, rhs: Point3) {
+        self.x += rhs.x;
+        self.y += rhs.y;
+        self.z += rhs.z;
+    }
+}
+
+impl SubAssign for Point3 {
+    fn sub_assign(&mut self, rhs: Point3) {
+        self.x -= rhs.x;
+        self.y -= rhs.y;
+        self.z -= rhs.z;
+    }
+}
+
+impl Mul<Float> for &Point3 {
+    type Output = Point3;
+
+    fn mul(self, rhs: Float) -> Self::Output {
+        Point3::new(self.x * rhs, self.y * rhs, self.z * rhs)
+    }
+}
+
+impl Div<Float> for &Point3 {
+    type Output = Point3;
+
+    fn div(self, rhs: Float) -> Self::Output {
+        Point3::new(self.x / rhs, self.y / rhs, self.z / rhs)
+    }
+}
+
+impl From<Vector3> for Point3 {
+    fn from(v: Vector3) -> Self {
+        Point3::new(v.dx, v.dy, v.dz)
+    }
+}
+
+impl Point3 {
+    pub fn new(x: Float, y: Float, z: Float) -> Point3 {
+        Point3 { x, y, z }
+    }
+
+    pub fn zero() -> Point3 {
+        Point3::new(0.0, 0.0, 0.0)
+    }
+
+    pub fn distance_to(&self, other: &Point3) -> Float {
+        Vector3::difference(self, other).magnitude()
+    }
+}
+
+// Vector3 ///////////////////////////////////////////////////////////////////
+//
+// Change of coordinates in 3D space.
+
+#[derive(Clone, Debug)]
+pub struct Vector3 {
+    pub dx: Float,
+    pub dy: Float,
+    pub dz: Float,
+}
+
+impl PartialEq for Vector3 {
+    fn eq(&self, other: &'_ Vector3) -> bool {
+        let e = 0.000_000_1;
+        (self.dx - other.dx).abs() < e &&
+            (self.dy - other.dy).abs() < e &&
+            (self.dz - other.dz).abs() < e
+    }
+}
+
+impl Add for Vector3 {
+    type Output = Vector3;
+
+    fn add(self, rhs: Vector3) -> Self::Output {
+        Vector3::new(self.dx + rhs.dx, self.dy + rhs.dy, self.dz + rhs.dz)
+    }
+}
+
+impl AddAssign for Vector3 {
+    fn add_assign(&mut self, rhs: Vector3) {
+        self.dx += rhs.dx;
+        self.dy += rhs.dy;
+        self.dz += rhs.dz;
+    }
+}
+
+impl Mul<Float> for &Vector3 {
+    type Output = Vector3;
+
+    fn mul(self, scalar: Float) -> Self::Output {
+        Vector3::new(self.dx * scalar, self.dy * scalar, self.dz * scalar)
+    }
+}
+
+impl Div<Float> for &Vector3 {
+    type Output = Vector3;
+
+    fn div(self, scalar: Float) -> Self::Output {
+        Vector3::new(self.dx / scalar, self.dy / scalar, self.dz / scalar)
+    }
+}
+
+impl Mul for &Vector3 {
+    type Output = Float;
+
+    fn mul(self, rhs: &Vector3) -> Self::Output {
+        self.dx * rhs.dx + self.dy * rhs.dy + self.dz * rhs.dz
+    }
+}
+
+impl Vector3 {
+    pub fn new(dx: Float, dy: Float, dz: Float) -> Vector3 {
+        Vector3 { dx, dy, dz }
+    }
+
+    pub fn zero() -> Vector3 {
+        Vector3::new(0.0, 0.0, 0.0)
+    }
+
+    pub fn difference(lhs: &Point3, rhs: &Point3) -> Vector3 {
+        Vector3::new(lhs.x - rhs.x, lhs.y - rhs.y, lhs.z - rhs.z)
+    }
+
+    pub fn magnitude(&self) -> Float {
+        (self * self).sqrt()
+    }
+
+    pub fn normalized(&self) -> Option<Vector3> {
+        if self == &Vector3::zero() { return None; }
+        Some(self / self.magnitude())
+    }
+}
+
+// Cube //////////////////////////////////////////////////////////////////////
+//
+// A cube whose origin denotes the position of the bottom left near corner.
+// Its eight octants are ordered as the quadrants of a square, first the
+// lower half along z, then the upper half.
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Cube {
+    pub origin: Point3,
+    pub size: u32,
+}
+
+impl Cube {
+    /// Creates a new Cube with size = 2^exponent.
+    pub fn new(x: Float, y: Float, z: Float, exponent: u32) -> Cube {
+        Cube {
+            origin: Point3::new(x, y, z),
+            size: u32::pow(2, exponent),
+        }
+    }
+
+    pub fn is_unit_cube(&self) -> bool {
+        self.size == 1
+    }
+
+    /// Returns the length of the space diagonal.
+    pub fn diameter(&self) -> Float {
+        let x = (self.size as Float).powi(2);
+        (3.0 * x).sqrt()
+    }
+
+    /// Returns true if the given point is contained by self.
+    pub fn contains(&self, point: &Point3) -> bool {
+        let upper = self.upper_bound();
+        point.x >= self.origin.x && point.y >= self.origin.y && point.z >= self.origin.z &&
+            point.x <= upper.x && point.y <= upper.y && point.z <= upper.z
+    }
+
+    /// Returns the distance from the given point to the nearest point of
+    /// self, which is zero if the point is contained.
+    pub fn distance_to(&self, point: &Point3) -> Float {
+        let upper = self.upper_bound();
+        let dx = (self.origin.x - point.x).max(point.x - upper.x).max(0.0);
+        let dy = (self.origin.y - point.y).max(point.y - upper.y).max(0.0);
+        let dz = (self.origin.z - point.z).max(point.z - upper.z).max(0.0);
+        (dx * dx + dy * dy + dz * dz).sqrt()
+    }
+
+    /// Returns the octant at the given position.
+    pub fn octant(&self, offset: usize) -> Cube {
+        assert!(!self.is_unit_cube(), "Cannot split cube with minimal dimension.");
+
+        let size = self.size >> 1;
+        let half = size as Float;
+        let x = if offset & 1 == 1 { half } else { 0.0 };
+        let y = if offset & 2 == 0 { half } else { 0.0 };
+        let z = if offset & 4 == 4 { half } else { 0.0 };
+
+        Cube { origin: self.origin.clone() + Point3::new(x, y, z), size }
+    }
+
+    /// Returns the position of the octant containing the given point. Points
+    /// on the boundary between octants are resolved as in
+    /// `Square::child_offset`, and towards the lower half along z.
+    pub fn child_offset(&self, point: &Point3) -> usize {
+        let half = (self.size >> 1) as Float;
+        let east = point.x > self.origin.x + half;
+        let south = point.y < self.origin.y + half;
+        let top = point.z > self.origin.z + half;
+        east as usize | (south as usize) << 1 | (top as usize) << 2
+    }
+
+    fn upper_bound(&self) -> Point3 {
+        let size = self.size as Float;
+        Point3::new(self.origin.x + size, self.origin.y + size, self.origin.z + size)
+    }
+}
+
+// Tests /////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn point3_distance() {
+        // given
+        let p1 = Point3::new(1.0, 2.0, 3.0);
+        let p2 = Point3::new(3.0, 5.0, 9.0);
+
+        // when
+        let result = p1.distance_to(&p2);
+
+        // then
+        assert_eq!(7.0, result);
+    }
+
+    #[test]
+    fn vector3_normalizes() {
+        // given
+        let sut = Vector3::new(0.0, 3.0, -4.0);
+
+        // then
+        assert_eq!(Some(Vector3::new(0.0, 0.6, -0.8)), sut.normalized());
+        assert_eq!(None, Vector3::zero().normalized());
+    }
+
+    #[test]
+    fn cube_diameter() {
+        // given
+        let sut = Cube::new(0.0, 0.0, 0.0, 1);
+
+        // then
+        assert_close!(3.464_101_6, sut.diameter());
+    }
+
+    #[test]
+    fn cube_contains() {
+        // given
+        let sut = Cube::new(-2.0, -2.0, -2.0, 2);
+
+        // then
+        assert!(sut.contains(&Point3::new(-2.0, 0.0, 2.0)));
+        assert!(!sut.contains(&Point3::new(0.0, 0.0, 2.5)));
+    }
+
+    #[test]
+    fn cube_child_offset_matches_octant() {
+        // given
+        let sut = Cube::new(0.0, 0.0, 0.0, 2);
+        let points = [
+            Point3::new(1.0, 3.0, 1.0),
+            Point3::new(3.0, 3.0, 1.0),
+            Point3::new(1.0, 1.0, 1.0),
+            Point3::new(3.0, 1.0, 1.0),
+            Point3::new(1.0, 3.0, 3.0),
+            Point3::new(3.0, 3.0, 3.0),
+            Point3::new(1.0, 1.0, 3.0),
+            Point3::new(3.0, 1.0, 3.0),
+        ];
+
+        for (offset, point) in points.iter().enumerate() {
+            // when
+            let result = sut.child_offset(point);
+
+            // then
+            assert_eq!(offset, result);
+            assert!(sut.octant(offset).contains(point));
+        }
+    }
+
+    #[test]
+    fn cube_distance_to_point() {
+        // given
+        let sut = Cube::new(0.0, 0.0, 0.0, 1);
+
+        // then
+        assert_eq!(0.0, sut.distance_to(&Point3::new(1.0, 1.0, 1.0)));
+        assert_eq!(3.0, sut.distance_to(&Point3::new(5.0, 2.0, 2.0)));
+    }
+}
@@ -1,6 +1,8 @@
 use std::ops::Mul;
 
+use crate::geometry::three::{Point3, Vector3};
 use crate::geometry::{Point, Vector};
+use crate::Float;
 
 // Transformation ////////////////////////////////////////////////////////////
 //
@@ -9,14 +11,14 @@ use crate::geometry::{Point, Vector};
 
 pub struct Transformation(Vector, Vector);
 
-impl<'a> Mul<Vector> for &'a Transformation {
+impl Mul<Vector> for &Transformation {
     type Output = Vector;
     fn mul(self, rhs: Vector) -> Self::Output {
         &self.0 * rhs.dx + &self.1 * rhs.dy
     }
 }
 
-impl<'a> Mul<Point> for &'a Transformation {
+impl Mul<Point> for &Transformation {
     type Output = Point;
     fn mul(self, rhs: Point) -> Self::Output {
         Point::from(&self.0 * rhs.x + &self.1 * rhs.y)
@@ -24,17 +26,78 @@ impl<'a> Mul<Point> for &'a Transformation {
 }
 
 impl Transformation {
-    pub fn rotation(radians: f32) -> Transformation {
+    pub fn rotation(radians: Float) -> Transformation {
         let (sin, cos) = radians.sin_cos();
         Transformation(Vector { dx: cos, dy: sin }, Vector { dx: -sin, dy: cos })
     }
 }
 
+// Transformation3 ///////////////////////////////////////////////////////////
+//
+// A 3D transformation matrix represented as a triple of transformed basis
+// vectors.
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Transformation3(Vector3, Vector3, Vector3);
+
+impl Mul<Vector3> for &Transformation3 {
+    type Output = Vector3;
+    fn mul(self, rhs: Vector3) -> Self::Output {
+        &self.0 * rhs.dx + &self.1 * rhs.dy + &self.2 * rhs.dz
+    }
+}
+
+impl Mul<Point3> for &Transformation3 {
+    type Output = Point3;
+    fn mul(self, rhs: Point3) -> Self::Output {
+        Point3::from(&self.0 * rhs.x + &self.1 * rhs.y + &self.2 * rhs.z)
+    }
+}
+
+impl Mul for &Transformation3 {
+    type Output = Transformation3;
+    fn mul(self, rhs: &Transformation3) -> Self::Output {
+        Transformation3(
+            self * rhs.0.clone(),
+            self * rhs.1.clone(),
+            self * rhs.2.clone(),
+        )
+    }
+}
+
+impl Transformation3 {
+    pub fn identity() -> Transformation3 {
+        Transformation3(
+            Vector3::new(1.0, 0.0, 0.0),
+            Vector3::new(0.0, 1.0, 0.0),
+            Vector3::new(0.0, 0.0, 1.0),
+        )
+    }
+
+    /// Returns a counterclockwise rotation about the given axis, which need
+    /// not be normalized. A zero axis gives the identity.
+    pub fn rotation(axis: &Vector3, radians: Float) -> Transformation3 {
+        let axis = match axis.normalized() {
+            Some(axis) => axis,
+            None => return Transformation3::identity(),
+        };
+        let (sin, cos) = radians.sin_cos();
+        let (x, y, z) = (axis.dx, axis.dy, axis.dz);
+        let c = 1.0 - cos;
+
+        Transformation3(
+            Vector3::new(cos + x * x * c, y * x * c + z * sin, z * x * c - y * sin),
+            Vector3::new(x * y * c - z * sin, cos + y * y * c, z * y * c + x * sin),
+            Vector3::new(x * z * c + y * sin, y * z * c - x * sin, cos + z * z * c),
+        )
+    }
+}
+
 // Tests /////////////////////////////////////////////////////////////////////
 
 #[cfg(test)]
 mod tests {
-    use std::f32::consts::FRAC_PI_2;
+    use crate::consts::{FRAC_PI_2, FRAC_PI_3};
 
     use crate::geometry::Vector;
 
@@ -63,4 +126,34 @@ mod tests {
         // then
         assert_eq!(result, Vector { dx: 0.0, dy: 1.0 });
     }
+
+    #[test]
+    fn it_rotates_a_vector_about_an_axis() {
+        // given
+        let sut = Transformation3::rotation(&Vector3::new(1.0, 1.0, 1.0), 2.0 * FRAC_PI_3);
+
+        // when
+        let result = &sut * Vector3::new(1.0, 0.0, 0.0);
+
+        // then
+        assert_close!(0.0, result.dx);
+        assert_close!(1.0, result.dy);
+        assert_close!(0.0, result.dz);
+    }
+
+    #[test]
+    fn it_composes_rotations() {
+        // given
+        let z = Transformation3::rotation(&Vector3::new(0.0, 0.0, 1.0), FRAC_PI_2);
+        let x = Transformation3::rotation(&Vector3::new(1.0, 0.0, 0.0), FRAC_PI_2);
+
+        // when
+        let result = &(&x * &z) * Vector3::new(1.0, 0.0, 0.0);
+
+        // then
+        assert_eq!(result, &x * (&z * Vector3::new(1.0, 0.0, 0.0)));
+        assert_close!(0.0, result.dx);
+        assert_close!(0.0, result.dy);
+        assert_close!(1.0, result.dz);
+    }
 }
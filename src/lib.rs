@@ -1,3 +1,31 @@
+/// Asserts that two floats agree to single precision, so that tests with
+/// literal expectations hold in both the f32 and the f64 build.
+#[cfg(test)]
+macro_rules! assert_close {
+    ($expected:expr, $actual:expr) => {{
+        let (expected, actual): (crate::Float, crate::Float) = ($expected, $actual);
+        assert!((expected - actual).abs() <= 1e-6 * expected.abs().max(1.0),
+                "Expected {:?}, got {:?}", expected, actual);
+    }};
+}
+
 pub mod geometry;
 pub mod physics;
 pub mod util;
+
+// Float /////////////////////////////////////////////////////////////////////
+//
+// The floating point type used throughout the simulation. Single precision
+// by default, double precision when built with the `f64` feature.
+
+#[cfg(not(feature = "f64"))]
+pub type Float = f32;
+
+#[cfg(feature = "f64")]
+pub type Float = f64;
+
+#[cfg(not(feature = "f64"))]
+pub use std::f32::consts;
+
+#[cfg(feature = "f64")]
+pub use std::f64::consts;
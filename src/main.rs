@@ -1,12 +1,31 @@
+use std::io;
+use std::path::Path;
+use std::process;
+use std::sync::Arc;
 use std::time::Instant;
 
-use clap::{App, load_yaml, value_t};
+use clap::{App, ArgMatches, load_yaml, value_t, values_t};
 use pbr::ProgressBar;
 
-use newton::physics::Environment;
+use newton::geometry::Point;
+use newton::physics::accuracy::{AccuracyReport, Summary};
+use newton::physics::barneshut::{DEFAULT_LEAF_SIZE, DEFAULT_THETA};
+use newton::physics::{Body, Environment, TIME_STEP};
 use newton::physics::field::*;
+use newton::physics::three::{Body3, BruteForceField3, Environment3, Field3, OctreeField};
+use newton::util::binary;
 use newton::util::distribution::Loader;
-use newton::util::write::DataWriter;
+use newton::util::gadget::{Gadget, GadgetWriter};
+use newton::util::map::{DEFAULT_MAP_SIZE, MapFormat, MapWriter};
+use newton::util::image::Viewport;
+use newton::util::npy::{NpyWriter, NpzWriter};
+use newton::util::render::{DEFAULT_RENDER_SIZE, RenderWriter};
+use newton::util::sink::{Sink, Sink3};
+use newton::util::tipsy::{Tipsy, TipsyWriter};
+use newton::util::trajectory::{self, TrajectoryWriter};
+use newton::util::vtk::VtkWriter;
+use newton::util::write::{Column, DataWriter, StateWriter};
+use newton::Float;
 
 // TODO: Option for environment size (S, M, L, or exp)
 
@@ -20,6 +39,60 @@ fn main() {
     let output = matches.value_of("OUTPUT").unwrap();
     let frames = value_t!(matches, "FRAMES", u32).unwrap();
     let brute_force = matches.is_present("BRUTEFORCE");
+    let tree_pm = matches.is_present("TREEPM");
+    let refit = matches.is_present("REFIT");
+    let three_d = matches.is_present("THREE_D");
+    let stats = matches.is_present("STATS");
+    let input_format = matches.value_of("INPUT_FORMAT").unwrap();
+    let theta = if matches.is_present("THETA") {
+        value_t!(matches, "THETA", Float).unwrap_or_else(|e| e.exit())
+    } else {
+        DEFAULT_THETA
+    };
+    let leaf_size = if matches.is_present("LEAF_SIZE") {
+        value_t!(matches, "LEAF_SIZE", usize).unwrap_or_else(|e| e.exit())
+    } else {
+        DEFAULT_LEAF_SIZE
+    };
+    if leaf_size == 0 {
+        eprintln!("The leaf size must be at least 1.");
+        process::exit(1);
+    }
+
+    // Configure thread pool
+    if matches.is_present("THREADS") {
+        let threads = value_t!(matches, "THREADS", usize).unwrap_or_else(|e| e.exit());
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build_global()
+            .expect("Couldn't configure thread pool.");
+    }
+
+    // Compare against brute force instead of simulating
+    if matches.is_present("ACCURACY") {
+        let (bodies, _) = load(path, input_format);
+        let field = BHField::new().with_theta(theta).with_leaf_size(leaf_size);
+        println!("{} bodies, theta {}, leaf size {}", bodies.len(), theta, leaf_size);
+        println!("{}", AccuracyReport::new(&field, &bodies));
+        return;
+    }
+
+    // Convert output formats instead of simulating
+    if matches.is_present("CONVERT") {
+        let result = if Path::new(path).is_dir() {
+            trajectory::from_frames(path, output, TIME_STEP)
+        } else {
+            trajectory::to_frames(path, output, false)
+        };
+        match result {
+            Ok(frames) => println!("Converted {} frames.", frames),
+            Err(e) => {
+                eprintln!("Error converting {}. {}", path, e);
+                process::exit(1);
+            }
+        }
+        return;
+    }
 
     // Configure progress bar
     let mut progress = ProgressBar::new(u64::from(frames));
@@ -27,30 +100,145 @@ fn main() {
     progress.format("|▌▌-|");
 
     // Configure the environment
-    let mut fields: Vec<Box<dyn Field>> = vec![];
-    let writer = DataWriter::new(output);
+    let mut run: Box<dyn FnMut(Step) -> io::Result<()>> = if three_d {
+        let mut fields: Vec<Box<dyn Field3>> = vec![];
+
+        if brute_force {
+            fields.push(Box::from(BruteForceField3::new()));
+        } else {
+            fields.push(Box::from(OctreeField::new().with_theta(theta)));
+        }
+
+        let mut sinks: Vec<Box<dyn Sink3>> = vec![];
+        if matches.is_present("GADGET") {
+            sinks.push(Box::new(GadgetWriter::new(output)));
+        }
+        if matches.is_present("TIPSY") {
+            sinks.push(Box::new(TipsyWriter::new(output)));
+        }
+        if sinks.is_empty() {
+            sinks.push(Box::new(DataWriter::new(output)));
+        }
+        let mut env = Environment3::new(fields, sinks);
+        env.bodies = load3(path, input_format);
+        Box::new(move |step| match step {
+            Step::Update => env.update(),
+            Step::Finish => env.finish(),
+        })
 
-    if brute_force {
-        fields.push(Box::from(BruteForceField::new()));
     } else {
-        fields.push(Box::from(BHField::new()));
-    }
+        let mut fields: Vec<Box<dyn Field>> = vec![];
+        // the tree field is shared so that it can be inspected after each frame
+        let mut tree_field = None;
 
-    let mut env = Environment::new(fields, writer);
+        if brute_force {
+            fields.push(Box::from(BruteForceField::new()));
+        } else if tree_pm {
+            fields.push(Box::from(TreePMField::default().with_theta(theta)));
+        } else {
+            let field = if refit { BHField::incremental() } else { BHField::new() };
+            let field = Arc::new(field.with_theta(theta).with_leaf_size(leaf_size));
+            fields.push(Box::from(Arc::clone(&field)));
+            tree_field = Some(field);
+        }
 
-    {
-        let mut loader = Loader::new();
-        env.bodies = loader.load_from_path(path).unwrap();
-    }
+        let mut map = if matches.is_present("MAP") {
+            let every = value_t!(matches, "MAP", usize).unwrap_or_else(|e| e.exit());
+            let columns = if matches.is_present("MAP_SIZE") {
+                value_t!(matches, "MAP_SIZE", usize).unwrap_or_else(|e| e.exit())
+            } else {
+                DEFAULT_MAP_SIZE
+            };
+            if every == 0 {
+                eprintln!("Maps must be written at least every frame.");
+                process::exit(1);
+            }
+            if columns == 0 {
+                eprintln!("A map must have at least one column.");
+                process::exit(1);
+            }
+            let formats = match matches.values_of("MAP_FORMAT") {
+                Some(values) => values.map(|value| value.parse().unwrap()).collect(),
+                None => vec![MapFormat::Png],
+            };
+            let map = MapWriter::new(output, every).expect("Couldn't create map directory.");
+            Some(map.with_columns(columns).with_formats(formats))
+        } else {
+            None
+        };
+
+        // text frames are written unless the run goes to other files
+        let mut sinks: Vec<Box<dyn Sink>> = vec![];
+        if let Some(file) = matches.value_of("TRAJECTORY") {
+            let writer = created(file, TrajectoryWriter::create(file, TIME_STEP));
+            sinks.push(Box::new(writer.with_metadata(&format!("configuration: {}", path))));
+        }
+        if let Some(file) = matches.value_of("NPY") {
+            sinks.push(Box::new(created(file, NpyWriter::create(file))));
+        }
+        if let Some(file) = matches.value_of("NPZ") {
+            sinks.push(Box::new(created(file, NpzWriter::create(file))));
+        }
+        if matches.is_present("GADGET") {
+            sinks.push(Box::new(GadgetWriter::new(output)));
+        }
+        if matches.is_present("TIPSY") {
+            sinks.push(Box::new(TipsyWriter::new(output)));
+        }
+        if matches.is_present("RENDER") {
+            sinks.push(Box::new(render_writer(&matches, output)));
+        }
+        if let Some(format) = matches.value_of("VTK") {
+            sinks.push(Box::new(VtkWriter::new(output).with_format(format.parse().unwrap())));
+        }
+        if let Some(values) = matches.values_of("COLUMNS") {
+            let columns: Vec<Column> = values.map(|value| value.parse().unwrap()).collect();
+            sinks.push(Box::new(StateWriter::new(output).with_columns(columns)));
+        } else if matches.is_present("STATE") {
+            sinks.push(Box::new(StateWriter::new(output)));
+        } else if sinks.is_empty() {
+            sinks.push(Box::new(DataWriter::new(output)));
+        }
+
+        let mut env = Environment::new(fields, sinks);
+        let (bodies, groups) = load(path, input_format);
+        env.bodies = bodies;
+        env.groups = groups;
+        Box::new(move |step| match step {
+            Step::Update => {
+                env.update()?;
+                if let Some(map) = &mut map {
+                    map.update(&env.fields, &env.bodies)?;
+                }
+                if let (true, Some(field)) = (stats, &tree_field) {
+                    print_stats(field);
+                }
+                Ok(())
+            }
+            Step::Finish => env.finish(),
+        })
+    };
 
     // Run the simulation
     // --------------------------------------------------------------------
 
     let stop_watch = StopWatch::start();
 
-    for _ in 1..=frames {
+    let mut result = Ok(());
+    for _ in 0..frames {
         progress.inc();
-        env.update();
+        result = run(Step::Update);
+        if result.is_err() {
+            break;
+        }
+    }
+
+    // the sinks are finished even after an error, so that the frames
+    // written so far can be read
+    let finished = run(Step::Finish);
+    if let Err(e) = result.and(finished) {
+        eprintln!("Error writing output. {}", e);
+        process::exit(1);
     }
 
     let (secs, millis) = stop_watch.stop();
@@ -58,6 +246,102 @@ fn main() {
     println!("Total: {}.{} seconds.", secs, millis);
 }
 
+/// A step of the simulation. The sinks are finished after the last update.
+enum Step {
+    Update,
+    Finish,
+}
+
+/// Configures rendered frames from the given arguments.
+fn render_writer(matches: &ArgMatches, output: &str) -> RenderWriter {
+    let width = if matches.is_present("RENDER_SIZE") {
+        value_t!(matches, "RENDER_SIZE", usize).unwrap_or_else(|e| e.exit())
+    } else {
+        DEFAULT_RENDER_SIZE
+    };
+    if width == 0 {
+        eprintln!("A rendered image must be at least one pixel wide.");
+        process::exit(1);
+    }
+    let mut writer = RenderWriter::new(output)
+        .with_width(width)
+        .with_blending(matches.is_present("BLEND"));
+
+    if matches.is_present("POINT_SIZE") {
+        let point_size = value_t!(matches, "POINT_SIZE", usize).unwrap_or_else(|e| e.exit());
+        if point_size == 0 {
+            eprintln!("Rendered bodies must be at least one pixel wide.");
+            process::exit(1);
+        }
+        writer = writer.with_point_size(point_size);
+    }
+    if let Some(coloring) = matches.value_of("COLOR_BY") {
+        writer = writer.with_coloring(coloring.parse().unwrap());
+    }
+
+    if matches.is_present("RENDER_VIEWPORT") {
+        let bounds = values_t!(matches, "RENDER_VIEWPORT", Float).unwrap_or_else(|e| e.exit());
+        if bounds[0] >= bounds[2] || bounds[1] >= bounds[3] {
+            eprintln!("The viewport must be given as min x, min y, max x, max y, with min < max.");
+            process::exit(1);
+        }
+        let viewport = Viewport::new(Point::new(bounds[0], bounds[1]), Point::new(bounds[2], bounds[3]));
+        writer = writer.with_viewport(viewport);
+    }
+    writer
+}
+
+/// Loads the bodies of the given input and their groups. Snapshots have
+/// no groups.
+fn load(path: &str, format: &str) -> (Vec<Body>, Vec<usize>) {
+    match format {
+        "gadget" => (loaded(path, binary::load_from_path::<Gadget>(path)), vec![]),
+        "tipsy" => (loaded(path, binary::load_from_path::<Tipsy>(path)), vec![]),
+        _ => {
+            let mut loader = Loader::new();
+            let bodies = loader.load_from_path(path).unwrap();
+            (bodies, loader.groups().to_vec())
+        }
+    }
+}
+
+fn load3(path: &str, format: &str) -> Vec<Body3> {
+    match format {
+        "gadget" => loaded(path, binary::load_from_path3::<Gadget>(path)),
+        "tipsy" => loaded(path, binary::load_from_path3::<Tipsy>(path)),
+        _ => Loader::new_3d().load_from_path(path).unwrap(),
+    }
+}
+
+/// Returns the loaded input, or exits if the given file couldn't be read.
+fn loaded<T>(file: &str, result: io::Result<T>) -> T {
+    result.unwrap_or_else(|e| {
+        eprintln!("Couldn't read {}. {}", file, e);
+        process::exit(1);
+    })
+}
+
+/// Returns the created output, or exits if the given file couldn't be
+/// created.
+fn created<T>(file: &str, result: io::Result<T>) -> T {
+    result.unwrap_or_else(|e| {
+        eprintln!("Couldn't create {}. {}", file, e);
+        process::exit(1);
+    })
+}
+
+/// Prints the tree and interactions of the last frame of the given field.
+fn print_stats(field: &BHField) {
+    if let Some(tree) = field.stats() {
+        let interactions = field.interactions().iter().map(|n| *n as Float).collect();
+        let interactions = Summary::new(interactions);
+        println!();
+        println!("Tree: {}", tree);
+        println!("Interactions: mean {:.1}, median {}, max {}",
+                 interactions.mean, interactions.median, interactions.max);
+    }
+}
+
 // STOPWATCH /////////////////////////////////////////////////////////////////
 
 type SecsMillis = (u64, u32);
@@ -0,0 +1,175 @@
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use crate::geometry::Vector;
+use crate::Float;
+
+use super::field::{BHField, BruteForceField, Field};
+use super::Body;
+
+// AccuracyReport ////////////////////////////////////////////////////////////
+//
+// Compares the forces of a Barnes-Hut field against the brute force ground
+// truth for a single configuration of bodies. The relative error of a body
+// is the magnitude of the difference of its forces divided by the magnitude
+// of its brute force. Bodies without brute force, such as a lone body, have
+// no relative error and are left out of the errors.
+
+#[derive(Clone, Debug)]
+pub struct AccuracyReport {
+    /// The relative force errors of the bodies.
+    pub errors: Summary,
+    /// The number of interactions of each body in the Barnes-Hut field.
+    pub interactions: Summary,
+    /// The time taken by the Barnes-Hut field, including building its tree.
+    pub tree_time: Duration,
+    /// The time taken by the brute force field.
+    pub brute_force_time: Duration,
+}
+
+impl fmt::Display for AccuracyReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        writeln!(f, "Relative error: {}", self.errors)?;
+        let n = &self.interactions;
+        writeln!(f, "Interactions:   mean {:.1}, median {}, p99 {}, max {}", n.mean, n.median, n.p99, n.max)?;
+        writeln!(f, "Barnes-Hut:     {:?}", self.tree_time)?;
+        write!(f, "Brute force:    {:?}", self.brute_force_time)
+    }
+}
+
+impl AccuracyReport {
+    /// Computes the forces on the given bodies with both fields and
+    /// compares them.
+    pub fn new(field: &BHField, bodies: &[Body]) -> AccuracyReport {
+        let (forces, tree_time) = timed(|| field.forces(bodies));
+        let (expected, brute_force_time) = timed(|| BruteForceField::new().forces(bodies));
+
+        let errors = forces.iter().zip(expected.iter())
+            .filter(|(_, expected)| expected.magnitude() > 0.0)
+            .map(|(force, expected)| {
+                let difference = Vector::new(force.dx - expected.dx, force.dy - expected.dy);
+                difference.magnitude() / expected.magnitude()
+            })
+            .collect();
+
+        let interactions = field.interactions().iter().map(|n| *n as Float).collect();
+
+        AccuracyReport {
+            errors: Summary::new(errors),
+            interactions: Summary::new(interactions),
+            tree_time,
+            brute_force_time,
+        }
+    }
+}
+
+fn timed<T, F: FnOnce() -> T>(f: F) -> (T, Duration) {
+    let start = Instant::now();
+    let result = f();
+    (result, start.elapsed())
+}
+
+// Summary ///////////////////////////////////////////////////////////////////
+//
+// The distribution of a list of values. Percentiles use the nearest rank, so
+// they are always one of the values. All statistics are zero for an empty
+// list.
+
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct Summary {
+    pub mean: Float,
+    pub median: Float,
+    pub p99: Float,
+    pub max: Float,
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(f, "mean {:.3e}, median {:.3e}, p99 {:.3e}, max {:.3e}",
+               self.mean, self.median, self.p99, self.max)
+    }
+}
+
+impl Summary {
+    pub fn new(mut values: Vec<Float>) -> Summary {
+        if values.is_empty() {
+            return Summary::default();
+        }
+
+        values.sort_by(|a, b| a.partial_cmp(b).expect("Values must not be NaN."));
+        let percentile = |p: Float| {
+            let rank = (p * values.len() as Float).ceil() as usize;
+            values[rank.clamp(1, values.len()) - 1]
+        };
+
+        Summary {
+            mean: values.iter().sum::<Float>() / values.len() as Float,
+            median: percentile(0.5),
+            p99: percentile(0.99),
+            max: values[values.len() - 1],
+        }
+    }
+}
+
+// Tests /////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use crate::geometry::Point;
+
+    use super::*;
+
+    fn spiral(n: usize) -> Vec<Body> {
+        (0..n).map(|i| {
+            let angle = i as Float * 0.7;
+            let radius = 5.0 + 3.0 * i as Float;
+            Body::new(1.0, Point::new(radius * angle.cos(), radius * angle.sin()), Vector::zero())
+        }).collect()
+    }
+
+    #[test]
+    fn summary_of_values() {
+        // given
+        let values = (1..=200).rev().map(|n| n as Float).collect();
+
+        // when
+        let result = Summary::new(values);
+
+        // then
+        assert_eq!(Summary { mean: 100.5, median: 100.0, p99: 198.0, max: 200.0 }, result);
+    }
+
+    #[test]
+    fn summary_of_no_values() {
+        // then
+        assert_eq!(Summary::default(), Summary::new(vec![]));
+    }
+
+    #[test]
+    fn report_with_zero_theta_has_no_error() {
+        // given
+        let bodies = spiral(100);
+
+        // when
+        let result = AccuracyReport::new(&BHField::new().with_theta(0.0), &bodies);
+
+        // then
+        assert!(result.errors.max < 1e-4, "Got {}", result.errors);
+        assert_eq!(99.0, result.interactions.max);
+        assert_eq!(99.0, result.interactions.mean);
+    }
+
+    #[test]
+    fn report_error_grows_with_theta() {
+        // given
+        let bodies = spiral(300);
+
+        // when
+        let tight = AccuracyReport::new(&BHField::new().with_theta(0.5), &bodies);
+        let loose = AccuracyReport::new(&BHField::new().with_theta(2.0), &bodies);
+
+        // then
+        assert!(tight.errors.mean < loose.errors.mean);
+        assert!(tight.interactions.mean > loose.interactions.mean);
+    }
+}
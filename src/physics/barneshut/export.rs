@@ -0,0 +1,188 @@
+use std::fmt;
+
+use crate::physics::Body;
+use crate::Float;
+
+use super::BHTree;
+
+// Export ////////////////////////////////////////////////////////////////////
+//
+// Renders a tree as an SVG image or as a Graphviz DOT graph, to illustrate
+// how space is partitioned. Only non empty nodes are drawn, and each node
+// is described by its `Display` data. Given a body, the nodes accepted by
+// its walk, that is the nodes whose virtual bodies act on it, are
+// highlighted.
+
+/// The width and height of SVG images in pixels.
+const SVG_SIZE: Float = 1024.0;
+
+const HIGHLIGHT: &str = "#e4572e";
+
+impl BHTree {
+    /// Writes the tree as an SVG image. Each node is drawn as the outline
+    /// of its square and each leaf as a dot at its center of mass. The
+    /// description of a node is shown when hovering over it.
+    pub fn write_svg<W: fmt::Write>(&self, out: &mut W, body: Option<&Body>) -> fmt::Result {
+        let accepted = self.accepted(body);
+        let scale = SVG_SIZE / self.space.size as Float;
+
+        // the y axis of the image points down
+        let x = |x: Float| (x - self.space.origin.x) * scale;
+        let y = |y: Float| (self.space.origin.y + self.space.size as Float - y) * scale;
+
+        writeln!(out, r#"<svg xmlns="http://www.w3.org/2000/svg" width="{0}" height="{0}" viewBox="0 0 {0} {0}">"#, SVG_SIZE)?;
+        writeln!(out, r#"<rect width="100%" height="100%" fill="white"/>"#)?;
+
+        for node in self.preorder().filter(|node| !node.is_empty()) {
+            let size = node.space.size as Float * scale;
+            let fill = if accepted[node.id] { HIGHLIGHT } else { "none" };
+            writeln!(out, r#"<g><title>{}</title>"#, node)?;
+            writeln!(out, r#"<rect x="{}" y="{}" width="{}" height="{}" fill="{}" fill-opacity="0.3" stroke="gray" stroke-width="0.5"/>"#,
+                     x(node.space.origin.x), y(node.space.origin.y) - size, size, size, fill)?;
+
+            if node.is_leaf() || accepted[node.id] {
+                let center = node.body.centered().position;
+                let color = if accepted[node.id] { HIGHLIGHT } else { "black" };
+                writeln!(out, r#"<circle cx="{}" cy="{}" r="2" fill="{}"/>"#, x(center.x), y(center.y), color)?;
+            }
+            writeln!(out, "</g>")?;
+        }
+
+        if let Some(body) = body {
+            writeln!(out, r#"<circle cx="{}" cy="{}" r="4" fill="royalblue"/>"#,
+                     x(body.position.x), y(body.position.y))?;
+        }
+
+        writeln!(out, "</svg>")
+    }
+
+    /// Writes the tree as a Graphviz DOT graph, with an edge from each node
+    /// to its non empty children. Leaves are drawn as ellipses.
+    pub fn write_dot<W: fmt::Write>(&self, out: &mut W, body: Option<&Body>) -> fmt::Result {
+        let accepted = self.accepted(body);
+
+        writeln!(out, "digraph BHTree {{")?;
+        writeln!(out, "    node [shape=box, fontname=monospace];")?;
+
+        for node in self.preorder().filter(|node| !node.is_empty()) {
+            let label = node.to_string().replace('\t', " ");
+            let shape = if node.is_leaf() { ", shape=ellipse" } else { "" };
+            let style = if accepted[node.id] {
+                format!(", style=filled, fillcolor=\"{}\"", HIGHLIGHT)
+            } else {
+                String::new()
+            };
+            writeln!(out, "    n{} [label=\"{}\"{}{}];", node.id, label, shape, style)?;
+
+            if let Some(first) = node.children {
+                for child in (first..first + 4).filter(|idx| !self.nodes[*idx].is_empty()) {
+                    writeln!(out, "    n{} -> n{};", node.id, child)?;
+                }
+            }
+        }
+
+        writeln!(out, "}}")
+    }
+
+    /// Returns whether each node of the arena is accepted by the walk of
+    /// the given body.
+    fn accepted(&self, body: Option<&Body>) -> Vec<bool> {
+        let mut accepted = vec![false; self.nodes.len()];
+        if let Some(body) = body {
+            self.walk_nodes(&body.position, Some(body), |idx, _, _| accepted[idx] = true);
+        }
+        accepted
+    }
+}
+
+// Tests /////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use crate::geometry::{Point, Square, Vector};
+
+    use super::*;
+
+    /// The tree of `small_tree` in the tests of the tree.
+    fn small_tree() -> (BHTree, Vec<Body>) {
+        let bodies = vec![
+            Body::new(2.0, Point::new(6.0, 7.0), Vector::zero()),
+            Body::new(3.6, Point::new(1.0, 2.0), Vector::zero()),
+            Body::new(1.5, Point::new(-4.0, -4.0), Vector::zero()),
+        ];
+        let mut tree = BHTree::new(Square::new(-8.0, -8.0, 4));
+        for body in bodies.iter() {
+            tree.add(body);
+        }
+        (tree, bodies)
+    }
+
+    #[test]
+    fn tree_writes_dot() {
+        // given
+        let (sut, _) = small_tree();
+        let mut result = String::new();
+
+        // when
+        sut.write_dot(&mut result, None).unwrap();
+
+        // then
+        let label = |idx: usize| sut.nodes[idx].to_string().replace('\t', " ");
+        let expected = format!("\
+digraph BHTree {{
+    node [shape=box, fontname=monospace];
+    n0 [label=\"{}\"];
+    n0 -> n2;
+    n0 -> n3;
+    n2 [label=\"{}\"];
+    n2 -> n6;
+    n2 -> n7;
+    n6 [label=\"#6 (4, 4, 4) 2.0, (6.0, 7.0)\", shape=ellipse];
+    n7 [label=\"#7 (0, 0, 4) 3.6, (1.0, 2.0)\", shape=ellipse];
+    n3 [label=\"#3 (-8, -8, 8) 1.5, (-4.0, -4.0)\", shape=ellipse];
+}}
+", label(0), label(2));
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn tree_highlights_accepted_nodes() {
+        // given
+        let (sut, bodies) = small_tree();
+        let mut dot = String::new();
+        let mut svg = String::new();
+
+        // when
+        sut.write_dot(&mut dot, Some(&bodies[2])).unwrap();
+        sut.write_svg(&mut svg, Some(&bodies[2])).unwrap();
+
+        // then the walk of C accepts A and B as a single virtual body
+        let filled: Vec<&str> = dot.lines().filter(|line| line.contains("filled")).collect();
+        assert_eq!(1, filled.len());
+        assert!(filled[0].starts_with("    n2 "));
+
+        // its square and its center
+        assert_eq!(2, svg.matches(&format!(r#"fill="{}""#, HIGHLIGHT)).count());
+        assert!(svg.contains("royalblue"));
+    }
+
+    #[test]
+    fn tree_writes_svg() {
+        // given
+        let (sut, _) = small_tree();
+        let mut result = String::new();
+
+        // when
+        sut.write_svg(&mut result, None).unwrap();
+
+        // then
+        assert!(result.starts_with("<svg "));
+        assert!(result.ends_with("</svg>\n"));
+        assert_eq!(5, result.matches("<title>").count());
+        assert_eq!(5 + 1, result.matches("<rect ").count());
+        assert_eq!(3, result.matches("<circle ").count());
+
+        // C is drawn in the lower left quarter
+        assert!(result.contains(r#"<circle cx="256" cy="768" r="2" fill="black"/>"#));
+    }
+}
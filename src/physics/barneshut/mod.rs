@@ -1,41 +1,20 @@
-use std::collections::HashMap;
 use std::fmt;
-use std::ops::Range;
+use std::mem;
 
-use crate::geometry::Point;
-use crate::geometry::Quadrant;
-use crate::geometry::Quadrant::*;
-use crate::geometry::Square;
+use rayon::prelude::*;
+
+use crate::geometry::{Point, Square, Vector};
+use crate::Float;
 
 use super::Body;
 
 use self::virtual_body::VirtualBody;
 
+mod export;
+pub mod morton;
+mod query;
 mod virtual_body;
 
-
-
-// Action ////////////////////////////////////////////////////////////////////
-//
-// Represents an action to take on a `BHTree`. Either insert the given node
-// or internalize the node at the given index, before considering the
-// pending state.
-
-enum Action {
-    Insert(Node),
-    Internalize(Index, Pending),
-}
-
-// Pending ///////////////////////////////////////////////////////////////////
-//
-// Represents an intermediate state of the insertion algorithm. When adding
-// a body to the tree it is inspected top down. It could happen that a node
-// in the path needs to be internalized before the body can be inserted, in
-// which case, the current index and the body need to be stored temporarily
-// so that the insertion algorithm can continue at a later time.
-
-struct Pending(Index, Body);
-
 // BHTree ////////////////////////////////////////////////////////////////////
 //
 // The Barnes-Hut Tree is a quadtree used to efficiently calculate forces
@@ -49,30 +28,196 @@ struct Pending(Index, Body);
 // bodies and are used to calculate the force vectors. The body's final force
 // vector is simply the sum of all intermediate force vectors at each virtual
 // body.
+//
+// The nodes are stored contiguously in an arena, with the root at index 0.
+// When a node is split, its four children (NW, NE, SW, SE) are appended to
+// the arena together, so a node only needs to know the index of its first
+// child.
+//
+// A tree can be grown one body at a time with `add`, or built at once with
+// `from_bodies`. The latter sorts the bodies by morton key and builds
+// disjoint subtrees in parallel, from the leaves up. It also remembers the
+// leaf of each body, so that the tree can later be refitted to the moved
+// bodies instead of being rebuilt.
+//
+// Besides their virtual body, leaves keep the index, mass and position of
+// each of their bodies, which allows spatial queries. Bodies are identified
+// by their index in the bodies the tree was built from, or by the order in
+// which they were added.
+//
+// A leaf holds up to a fixed number of bodies, see `with_leaf_size`, and is
+// split once it overflows. Bodies within the same leaf always act directly on
+// each other, rather than through the leaf's virtual body.
 
-type Index = u32;
+type Index = usize;
+
+/// Subtrees spanning fewer bodies than this are built serially.
+const PARALLEL_THRESHOLD: usize = 2048;
+
+/// A refit is refused if more than this fraction of bodies changed leaves.
+const REFIT_MAX_MOVED: Float = 0.1;
+
+/// A refit is refused if the arena has grown by more than this factor since
+/// the tree was built, as the tree is then littered with stale nodes.
+const REFIT_MAX_GROWTH: Float = 1.5;
+
+/// The default opening parameter of tree walks. A node is approximated by its
+/// virtual body if its diameter is less than theta times its distance.
+pub const DEFAULT_THETA: Float = 2.0;
+
+/// The default number of bodies a leaf holds before it is split.
+pub const DEFAULT_LEAF_SIZE: usize = 1;
 
 #[derive(Debug)]
 pub struct BHTree {
     space: Square,
-    nodes: HashMap<Index, Node>,
+    nodes: Vec<Node>,
+    locations: Vec<Option<Index>>,
+    built_size: usize,
+    added: usize,
+    theta: Float,
+    leaf_size: usize,
 }
 
 impl BHTree {
     /// Initialized tree with a root node spanning the given space.
     pub fn new(space: Square) -> BHTree {
-        let mut nodes: HashMap<Index, Node> = HashMap::new();
-        let root = Node::new(0, space.clone(), VirtualBody::zero());
-        nodes.insert(root.id, root);
-        BHTree { space, nodes }
+        let root = Node::new(0, space.clone());
+        BHTree {
+            space,
+            nodes: vec![root],
+            locations: vec![],
+            built_size: 1,
+            added: 0,
+            theta: DEFAULT_THETA,
+            leaf_size: DEFAULT_LEAF_SIZE,
+        }
+    }
+
+    /// Builds a tree containing the given bodies. Bodies outside of the space
+    /// are ignored. The resulting tree has the same shape as one obtained by
+    /// adding the bodies one at a time, but its nodes are laid out in morton
+    /// order.
+    pub fn from_bodies(space: Square, bodies: &[Body]) -> BHTree {
+        let keys = morton::sorted_keys(&space, bodies);
+        BHTree::from_keys(space, bodies, &keys)
+    }
+
+    /// Builds a tree containing the bodies referenced by the given keys,
+    /// which must be obtained from `morton::sorted_keys` for the same space.
+    pub fn from_keys(space: Square, bodies: &[Body], keys: &[(u64, usize)]) -> BHTree {
+        BHTree::new(space).build(bodies, keys)
+    }
+
+    /// Like `from_keys`, but keeps the settings of self, which must be
+    /// empty. Panics if bodies have been added.
+    pub fn build(self, bodies: &[Body], keys: &[(u64, usize)]) -> BHTree {
+        assert!(self.nodes.len() == 1 && self.nodes[0].is_empty(), "Can only build an empty tree.");
+
+        let builder = Builder { bodies, levels: morton::levels(&self.space), leaf_size: self.leaf_size };
+        let (nodes, leaves) = builder.subtree(self.space.clone(), keys, 0);
+
+        let mut locations = vec![None; bodies.len()];
+        for (body, leaf) in leaves {
+            locations[body] = Some(leaf);
+        }
+
+        BHTree { built_size: nodes.len(), nodes, locations, added: bodies.len(), ..self }
+    }
+
+    /// Sets the opening parameter of walks. Larger values approximate more
+    /// nodes, which is faster but less accurate. Zero opens every node, so
+    /// that walks visit each body. Panics if theta is negative.
+    pub fn with_theta(self, theta: Float) -> BHTree {
+        if theta.is_nan() || theta < 0.0 {
+            panic!("The opening parameter must not be negative. Got {}", theta);
+        }
+        BHTree { theta, ..self }
+    }
+
+    /// Sets the number of bodies a leaf holds before it is split. Leaves
+    /// spanning a unit square are never split and hold any number of
+    /// bodies. Larger leaves make the tree shallower, at the cost of more
+    /// direct interactions. Panics if the size is zero or if bodies have
+    /// been added.
+    pub fn with_leaf_size(self, size: usize) -> BHTree {
+        assert!(size > 0, "Leaves must hold at least one body.");
+        assert!(self.nodes.len() == 1 && self.nodes[0].is_empty(),
+                "The leaf size must be set before adding bodies.");
+        BHTree { leaf_size: size, ..self }
     }
 
-    /// Inserts the given body into the tree.
-    pub fn add(&mut self, body: Body) {
+    /// Refits the tree to the current state of the bodies it was built from.
+    /// Bodies still inside their leaf are accumulated in place, the others
+    /// are re-inserted from the root, and the virtual bodies of the internal
+    /// nodes are then recomputed from the leaves up.
+    ///
+    /// Returns false, leaving the tree untouched, if the bodies are not the
+    /// ones the tree was built from, if too many bodies changed leaves, or
+    /// if previous refits have degraded the tree. The tree should then be
+    /// rebuilt.
+    pub fn refit(&mut self, bodies: &[Body]) -> bool {
+        if self.locations.len() != bodies.len() ||
+            self.nodes.len() as Float > REFIT_MAX_GROWTH * self.built_size as Float {
+            return false;
+        }
+
+        let moved: Vec<usize> = bodies.iter().enumerate().filter(|(idx, body)| {
+            match self.locations[*idx] {
+                Some(leaf) => !self.nodes[leaf].space.contains(&body.position),
+                None => self.space.contains(&body.position),
+            }
+        }).map(|(idx, _)| idx).collect();
+
+        if moved.len() as Float > REFIT_MAX_MOVED * bodies.len() as Float {
+            return false;
+        }
+
+        for node in self.nodes.iter_mut() {
+            node.body = VirtualBody::zero();
+            node.bodies = 0;
+            node.members.clear();
+        }
+
+        for (idx, body) in bodies.iter().enumerate() {
+            if let Some(leaf) = self.locations[idx] {
+                if self.nodes[leaf].space.contains(&body.position) {
+                    self.nodes[leaf].push(Member::new(idx, body));
+                }
+            }
+        }
+
+        for idx in moved {
+            self.locations[idx] = if self.space.contains(&bodies[idx].position) {
+                Some(self.reinsert(Member::new(idx, &bodies[idx])))
+            } else {
+                None
+            };
+        }
+
+        // children are always stored after their parent
+        for idx in (0..self.nodes.len()).rev() {
+            if let Some(first) = self.nodes[idx].children {
+                let (body, bodies) = Builder::sum(&self.nodes[first..first + 4]);
+                self.nodes[idx].body = body;
+                self.nodes[idx].bodies = bodies;
+            }
+        }
+
+        true
+    }
+
+    /// Inserts the given body into the tree. The body is identified by the
+    /// number of bodies added before it, including ignored ones, following
+    /// the bodies the tree was built from.
+    pub fn add(&mut self, body: &Body) {
+        let index = self.added;
+        self.added += 1;
+
         // TODO: this is just temporary workaround. We should properly
         // handle the case when bodies leave the root space
         if self.space.contains(&body.position) {
-            self.insert(Pending(0, body));
+            self.insert(index, body);
         }
     }
 
@@ -80,213 +225,589 @@ impl BHTree {
     /// body is potentially an abstraction of a group of real bodes. The
     /// degree of abstraction is determined by a ratio between the distance
     /// of the virtual body to the given body, and the diameter of the space
-    /// containing the virtual body. The diameter may not be more than twice
-    /// the distance.
+    /// containing the virtual body. The diameter must be less than theta
+    /// times the distance, see `with_theta`.
     ///
-    /// The virtual bodies are collected as follows. 1) Starting at the root,
-    /// we dive depth first until a node that fulfills the condition
-    /// mentioned above is reached, or the a leaf is reached. In both cases,
-    /// the virtual body at that node is collected. Then we move the cursor
-    /// to the next sibling if any, or to the next node. Then the next virtual
-    /// body is found by repeating this process.
+    /// See `walk` for the order in which the virtual bodies are collected.
     ///
     pub fn virtual_bodies(&self, body: &Body) -> Vec<Body> {
         let mut result = vec![];
-        let mut traverser = self.preorder();
+        self.walk(body, |mass, position| {
+            result.push(Body::new(mass, position.clone(), Vector::zero()));
+        });
+        result
+    }
 
-        let condition = |node: &Node| {
-            let pos = node.body.centered().position;
-            let dist = body.position.distance_to(&pos);
-            node.space.diameter() / dist < 2.0
-        };
+    /// Calls `visit` with the mass and center of each virtual body acting on
+    /// the given body, without allocating.
+    ///
+    /// Starting at the root, we dive depth first until a node whose diameter
+    /// is less than theta times its distance to the body is reached, in which
+    /// case the virtual body at that node is visited and its children are
+    /// skipped, or a leaf is reached, in which case each of its bodies is
+    /// visited. Nodes containing the body are always opened, and the body
+    /// itself is skipped.
+    pub fn walk<F>(&self, body: &Body, mut visit: F) where F: FnMut(Float, &Point) {
+        self.walk_nodes(&body.position, Some(body), |_, mass, position| visit(mass, position));
+    }
 
-        loop {
-            match traverser.next() {
-                None => break,
-                Some(node) => {
-                    if condition(&node) {
-                        traverser.skip_children();
-                        result.push(node.body.centered().to_body());
-
-                    } else if self.is_leaf(node) {
-                        // subtract the body form the virtual body
-                        let mut virtual_body = node.body.clone();
-                        virtual_body.mass -= body.mass.value();
-                        virtual_body.position -= &body.position * body.mass.value();
-
-                        if virtual_body.mass <= 0.0 {
-                            // this is reachable when there are two
-                            // adjacent points in separate quadrants.
-                        } else {
-                            result.push(virtual_body.centered().to_body());
-                        }
+    /// Like `walk`, for a point that is not a body. All bodies of the tree
+    /// are visited, including those at the point itself.
+    pub fn walk_point<F>(&self, point: &Point, mut visit: F) where F: FnMut(Float, &Point) {
+        self.walk_nodes(point, None, |_, mass, position| visit(mass, position));
+    }
+
+    /// Like `walk`, from the given point, skipping the given body if any.
+    /// Also passes the index of the node of each virtual body.
+    fn walk_nodes<F>(&self, point: &Point, body: Option<&Body>, mut visit: F)
+        where F: FnMut(Index, Float, &Point) {
+        let mut stack = WalkStack::new();
+        stack.push(0);
+
+        while let Some(idx) = stack.pop() {
+            let node = &self.nodes[idx];
+            let center = node.body.centered();
+            let dist = point.distance_to(&center.position);
+
+            // a node containing the point is always opened, as its virtual
+            // body may include the body itself
+            if !node.space.contains(point) && node.space.diameter() / dist < self.theta {
+                visit(idx, center.mass, &center.position);
+
+            } else if let Some(first) = node.children {
+                stack.push_children(&self.nodes, first);
+
+            } else {
+                // the bodies of a leaf act directly, except for the body
+                // itself
+                let mut own = body.filter(|_| node.space.contains(point));
+                for member in node.members.iter() {
+                    if own.is_some_and(|body| member.is(body)) {
+                        own = None;
+                    } else {
+                        visit(idx, member.mass, &member.position);
                     }
-                },
+                }
             }
         }
-
-        result
     }
 
-    /// Borrows the node for the given index, if it exists.
-    fn node(&self, idx: Index) -> Option<&Node> {
-        self.nodes.get(&idx)
-    }
+    /// Partitions the bodies the tree was built from into groups of nearby
+    /// bodies. Each group holds the bodies of the largest nodes spanning at
+    /// most `size` bodies. Bodies the tree doesn't know of are not grouped.
+    pub fn groups(&self, size: usize) -> Vec<Group> {
+        let mut counts = vec![0; self.nodes.len()];
+        for leaf in self.locations.iter().flatten() {
+            counts[*leaf] += 1;
+        }
 
-    /// Returns true if the given node is a leaf.
-    fn is_leaf(&self, node: &Node) -> bool {
-        node.children().all(|n| self.node(n).is_none())
-    }
+        // children always come after their parent in the arena
+        for idx in (0..self.nodes.len()).rev() {
+            if let Some(first) = self.nodes[idx].children {
+                counts[idx] = counts[first..first + 4].iter().sum();
+            }
+        }
 
-    /// Inserts the given body into the tree at the given node.
-    fn insert(&mut self, pending: Pending) {
-        let action: Action;
-        {
-            // inspect the tree to find the necessary action
-            let node = self.node(pending.0).expect("Expected a node");
-            let body = pending.1;
-            action = self.action(node, body);
+        let mut groups = vec![];
+        let mut group_of: Vec<Option<usize>> = vec![None; self.nodes.len()];
+        for idx in 0..self.nodes.len() {
+            if group_of[idx].is_none() && counts[idx] > 0 && counts[idx] <= size {
+                group_of[idx] = Some(groups.len());
+                groups.push(Group { node: idx, bodies: vec![] });
+            }
+            if let (Some(group), Some(first)) = (group_of[idx], self.nodes[idx].children) {
+                for child in group_of[first..first + 4].iter_mut() {
+                    *child = Some(group);
+                }
+            }
         }
-        {
-            if let Some(pending) = self.process(action) {
-                self.insert(pending)
+
+        for (body, leaf) in self.locations.iter().enumerate() {
+            if let Some(group) = leaf.and_then(|leaf| group_of[leaf]) {
+                groups[group].bodies.push(body);
             }
         }
+
+        groups
     }
 
-    /// Processes the action by either inserting a new node, internalizing an
-    /// existing node, or doing nothing. Optionally returns a pending state.
-    fn process(&mut self, action: Action) -> Option<Pending> {
-        match action {
-            Action::Insert(node) => {
-                // go up from node to root, update virtual bodies along the way
-                for idx in node.ancestors() {
-                    // TODO: check if we can edit in place
-                    let mut parent = self.nodes.remove(&idx).expect("Expected a parent");
-                    parent.body.mass += node.body.mass;
-                    parent.body.position += node.body.position.clone();
-                    self.nodes.insert(idx, parent);
+    /// Calls `visit` with the mass and center of each virtual body acting on
+    /// all bodies of the given group, without allocating. Nodes are opened
+    /// by their distance to the nearest point of the group's space, so the
+    /// same interactions are valid for each body of the group. Nodes
+    /// enclosing the group are always opened, and the bodies within the
+    /// group are not visited, they must interact directly.
+    pub fn walk_group<F>(&self, group: &Group, mut visit: F) where F: FnMut(Float, &Point) {
+        let space = &self.nodes[group.node].space;
+        let mut stack = WalkStack::new();
+        stack.push(0);
+
+        while let Some(idx) = stack.pop() {
+            if idx == group.node {
+                continue;
+            }
+
+            let node = &self.nodes[idx];
+            let center = node.body.centered();
+            let dist = space.distance_to(&center.position);
+
+            // the virtual bodies of the ancestors of the group include its
+            // bodies, so they are always opened
+            if !node.space.encloses(space) && node.space.diameter() / dist < self.theta {
+                visit(center.mass, &center.position);
+            } else if let Some(first) = node.children {
+                stack.push_children(&self.nodes, first);
+            } else {
+                for member in node.members.iter() {
+                    visit(member.mass, &member.position);
                 }
+            }
+        }
+    }
 
-                self.nodes.insert(node.id, node);
-                None
-            },
-            Action::Internalize(id, pending) => {
-                self.internalize(id);
-                Some(pending)
+    /// Returns a description of each non empty node in preorder.
+    pub fn nodes(&self) -> Vec<NodeInfo> {
+        let mut parents = vec![None; self.nodes.len()];
+        let mut depths = vec![0; self.nodes.len()];
+
+        // children always come after their parent in the arena
+        for (idx, node) in self.nodes.iter().enumerate() {
+            if let Some(first) = node.children {
+                for child in first..first + 4 {
+                    parents[child] = Some(idx);
+                    depths[child] = depths[idx] + 1;
+                }
+            }
+        }
+
+        self.preorder().filter(|node| !node.is_empty()).map(|node| {
+            let center = node.body.centered();
+            NodeInfo {
+                id: node.id,
+                parent: parents[node.id],
+                depth: depths[node.id],
+                space: node.space.clone(),
+                mass: center.mass,
+                center: center.position,
+                bodies: node.bodies,
+                is_leaf: node.is_leaf(),
+            }
+        }).collect()
+    }
+
+    /// Returns the number of non empty nodes.
+    pub fn node_count(&self) -> usize {
+        self.preorder().filter(|node| !node.is_empty()).count()
+    }
+
+    /// Returns the number of non empty leaves.
+    pub fn leaf_count(&self) -> usize {
+        self.preorder().filter(|node| !node.is_empty() && node.is_leaf()).count()
+    }
+
+    /// Returns the depth of the deepest non empty node, where the root has
+    /// depth zero.
+    pub fn max_depth(&self) -> u32 {
+        self.nodes().iter().map(|node| node.depth).max().unwrap_or(0)
+    }
+
+    /// Returns the number of bodies in each non empty leaf, in preorder.
+    /// Leaves hold more bodies than the leaf size only if they span a unit
+    /// square.
+    pub fn bodies_per_leaf(&self) -> Vec<usize> {
+        self.preorder()
+            .filter(|node| !node.is_empty() && node.is_leaf())
+            .map(|node| node.bodies)
+            .collect()
+    }
+
+    /// Returns a summary of the shape of the tree.
+    pub fn stats(&self) -> TreeStats {
+        let leaves = self.bodies_per_leaf();
+        TreeStats {
+            nodes: self.node_count(),
+            leaves: leaves.len(),
+            max_depth: self.max_depth(),
+            max_bodies_per_leaf: leaves.iter().copied().max().unwrap_or(0),
+            mean_bodies_per_leaf: if leaves.is_empty() {
+                0.0
+            } else {
+                leaves.iter().sum::<usize>() as Float / leaves.len() as Float
             },
         }
     }
 
-    /// Internalizes the node at the given index by taking the node's body
-    /// and inserting it in the appropriate child.
-    fn internalize(&mut self, id: Index) {
-        let node = self.nodes.remove(&id).expect("Node doesn't exist.");
-        debug_assert!(self.is_leaf(&node), "Can't internalize an internal node");
+    /// Borrows the node for the given index, if it exists.
+    #[cfg(test)]
+    fn node(&self, idx: Index) -> Option<&Node> {
+        self.nodes.get(idx)
+    }
+
+    /// Inserts the given body into the tree. Starting at the root, the body
+    /// is accumulated into each node on the path down to the first leaf
+    /// with room for it. Full leaves on the way are split and their bodies
+    /// are moved into the appropriate children, unless they span a unit
+    /// square, in which case the leaf takes the body regardless. Panics if
+    /// the body is out of bounds.
+    fn insert(&mut self, index: usize, body: &Body) {
+        assert!(self.space.contains(&body.position),
+                "Body is out of bounds. Got {:?}", body.position);
 
-        let child = node.child_from_self();
+        let virtual_body = VirtualBody::from(body);
+        let mut idx = 0;
 
-        self.nodes.insert(node.id, node);
-        self.nodes.insert(child.id, child);
+        loop {
+            let node = &mut self.nodes[idx];
+
+            if let Some(first) = node.children {
+                node.body.accumulate(&virtual_body);
+                node.bodies += 1;
+                idx = first + node.space.child_offset(&body.position);
+
+            } else if node.members.len() < self.leaf_size || node.space.is_unit_rect() {
+                node.push(Member::new(index, body));
+                return;
+
+            } else {
+                self.internalize(idx);
+            }
+        }
     }
 
-    /// Dives into the tree rooted at the given node searching for the
-    /// position to insert the given body. If an empty leaf is found,
-    /// a new node is created, wrapped in an Insert variant and returned
-    /// for subsequent insertion. If an occupied leaf is found, then the
-    /// Internalize variant is return, which signifies the occupied leaf
-    /// needs to be internalize. Additionally, the current index and body
-    /// is included in the return so that the search can continue after
-    /// the leaf has been internalized.
-    fn action(&self, node: &Node, body: Body) -> Action {
-        debug_assert!(node.space.contains(&body.position));
+    /// Re-inserts the given body into the leaves of the tree, without
+    /// updating the internal nodes, and returns the leaf. Full leaves on the
+    /// way are split as in `insert`.
+    fn reinsert(&mut self, member: Member) -> Index {
+        let mut leaf = 0;
+
+        loop {
+            let node = &mut self.nodes[leaf];
+
+            if let Some(first) = node.children {
+                leaf = first + node.space.child_offset(&member.position);
+
+            } else if node.members.len() < self.leaf_size || node.space.is_unit_rect() {
+                node.push(member);
+                return leaf;
 
-        if self.is_leaf(node) {
-            if node.is_empty() || node.space.is_unit_rect() {
-                Action::Insert(node.with(body))
             } else {
-                Action::Internalize(node.id, Pending(node.id, body))
+                self.internalize(leaf);
             }
-        } else {
-            node.map_quadrant(body.position.clone(), move |idx: Index, q: Quadrant| {
-                match self.node(idx) {
-                    Some(child) => self.action(child, body),
-                    None => Action::Insert(Node::new(idx, q.space().clone(), VirtualBody::from(body))),
+        }
+    }
+
+    /// Internalizes the leaf at the given index by appending its four
+    /// children to the arena and moving each of the leaf's bodies into the
+    /// appropriate child.
+    fn internalize(&mut self, idx: Index) {
+        let members = mem::take(&mut self.nodes[idx].members);
+        let first = self.split(idx);
+
+        for member in members {
+            let child = first + self.nodes[idx].space.child_offset(&member.position);
+
+            // bodies the tree was built from remember their leaf
+            if let Some(location) = self.locations.get_mut(member.index) {
+                if *location == Some(idx) {
+                    *location = Some(child);
                 }
-            })
+            }
+            self.nodes[child].push(member);
         }
     }
 
+    /// Appends four empty children of the leaf at the given index to the
+    /// arena and returns the index of the first.
+    fn split(&mut self, idx: Index) -> Index {
+        debug_assert!(self.nodes[idx].is_leaf(), "Can't split an internal node");
+
+        let first = self.nodes.len();
+        let (nw, ne, sw, se) = self.nodes[idx].space.quadrants();
+
+        for (offset, quadrant) in [nw, ne, sw, se].iter().enumerate() {
+            self.nodes.push(Node::new(first + offset, quadrant.space().clone()));
+        }
+
+        self.nodes[idx].children = Some(first);
+        first
+    }
+
     /// Returns a preorder traversal iterator starting at the root node.
-    fn preorder(&self) -> PreorderTraverser {
-        self.preorder_at(0)
+    fn preorder(&self) -> PreorderTraverser<'_> {
+        PreorderTraverser::new(self, 0)
+    }
+}
+
+// Group /////////////////////////////////////////////////////////////////////
+//
+// A group of bodies below a common node, identified by their index in the
+// bodies the tree was built from. Walking the tree once per group instead
+// of once per body shares a single interaction list among the bodies.
+
+#[derive(Debug)]
+pub struct Group {
+    node: Index,
+    pub bodies: Vec<usize>,
+}
+
+// NodeInfo //////////////////////////////////////////////////////////////////
+//
+// A description of a node for inspection. Nodes are identified by their
+// index in the arena of the tree.
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct NodeInfo {
+    pub id: usize,
+    pub parent: Option<usize>,
+    pub depth: u32,
+    pub space: Square,
+    pub mass: Float,
+    pub center: Point,
+    pub bodies: usize,
+    pub is_leaf: bool,
+}
+
+// TreeStats /////////////////////////////////////////////////////////////////
+//
+// A summary of the shape of a tree. Empty nodes are not counted.
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct TreeStats {
+    pub nodes: usize,
+    pub leaves: usize,
+    pub max_depth: u32,
+    pub max_bodies_per_leaf: usize,
+    pub mean_bodies_per_leaf: Float,
+}
+
+impl fmt::Display for TreeStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(f, "{} nodes, {} leaves, depth {}, bodies per leaf mean {:.2} max {}",
+               self.nodes, self.leaves, self.max_depth,
+               self.mean_bodies_per_leaf, self.max_bodies_per_leaf)
+    }
+}
+
+// WalkStack /////////////////////////////////////////////////////////////////
+//
+// A fixed size stack of pending nodes for tree walks. Each level of the
+// tree pushes at most four nodes after popping one, and a tree is at most
+// 31 levels deep since the size of its space is a u32, so the stack never
+// holds more than 3 * 31 + 4 nodes.
+
+const WALK_STACK_SIZE: usize = 128;
+
+struct WalkStack {
+    items: [Index; WALK_STACK_SIZE],
+    len: usize,
+}
+
+impl WalkStack {
+    fn new() -> WalkStack {
+        WalkStack { items: [0; WALK_STACK_SIZE], len: 0 }
+    }
+
+    fn push(&mut self, idx: Index) {
+        self.items[self.len] = idx;
+        self.len += 1;
     }
 
-    /// Returns a preorder traversal iterator starting at given index.
-    fn preorder_at(&self, idx: Index) -> PreorderTraverser {
-        PreorderTraverser::new(self, idx)
+    /// Pushes the non empty children in reverse, so that the first child is
+    /// popped first.
+    fn push_children(&mut self, nodes: &[Node], first: Index) {
+        for idx in (first..first + 4).rev() {
+            if !nodes[idx].is_empty() {
+                self.push(idx);
+            }
+        }
     }
 
+    fn pop(&mut self) -> Option<Index> {
+        if self.len == 0 {
+            return None;
+        }
+        self.len -= 1;
+        Some(self.items[self.len])
+    }
+}
+
+// Builder ///////////////////////////////////////////////////////////////////
+//
+// Builds a tree from bodies sorted by morton key. The bodies in a node form
+// a contiguous range of the sorted keys, which is partitioned among its four
+// children by the key digit at the node's depth. Large ranges are built as
+// independent subtrees in parallel and then joined into a single arena.
+
+type Keys = [(u64, usize)];
+type Leaves = Vec<(usize, Index)>;
+
+struct Builder<'a> {
+    bodies: &'a [Body],
+    levels: u32,
+    leaf_size: usize,
+}
+
+impl<'a> Builder<'a> {
+    /// Returns the arena of the subtree for the given keys, with its root at
+    /// index 0, together with the leaf of each body.
+    fn subtree(&self, space: Square, keys: &Keys, depth: u32) -> (Vec<Node>, Leaves) {
+        let mut nodes = vec![Node::new(0, space)];
+        let mut leaves = vec![];
+
+        if keys.len() < PARALLEL_THRESHOLD || keys.len() <= self.leaf_size || nodes[0].space.is_unit_rect() {
+            self.build(&mut nodes, &mut leaves, 0, keys, depth);
+            return (nodes, leaves);
+        }
+
+        let (nw, ne, sw, se) = nodes[0].space.quadrants();
+        let parts = self.partition(keys, depth);
+
+        let subtrees: Vec<(Vec<Node>, Leaves)> = vec![nw, ne, sw, se].into_par_iter()
+            .zip(parts.into_par_iter())
+            .map(|(quadrant, keys)| self.subtree(quadrant.space().clone(), keys, depth + 1))
+            .collect();
+
+        // children roots go first, followed by the rest of each subtree
+        let mut base = 5;
+        let mut rest = vec![];
+        nodes[0].children = Some(1);
+
+        for (offset, (subtree, subtree_leaves)) in subtrees.into_iter().enumerate() {
+            let remap = |idx: Index| if idx == 0 { 1 + offset } else { base + idx - 1 };
+
+            for (idx, mut node) in subtree.into_iter().enumerate() {
+                node.id = remap(idx);
+                node.children = node.children.map(remap);
+                if idx == 0 { nodes.push(node); } else { rest.push(node); }
+            }
+            leaves.extend(subtree_leaves.into_iter().map(|(body, leaf)| (body, remap(leaf))));
+            base = 5 + rest.len();
+        }
+
+        nodes.append(&mut rest);
+        let (body, bodies) = Builder::sum(&nodes[1..5]);
+        nodes[0].body = body;
+        nodes[0].bodies = bodies;
+        (nodes, leaves)
+    }
+
+    /// Builds the subtree for the given keys below the node at the given
+    /// index, appending its descendants to the arena.
+    fn build(&self, nodes: &mut Vec<Node>, leaves: &mut Leaves, idx: Index, keys: &Keys, depth: u32) {
+        if keys.len() <= self.leaf_size || nodes[idx].space.is_unit_rect() {
+            for (_, body) in keys {
+                nodes[idx].push(Member::new(*body, &self.bodies[*body]));
+                leaves.push((*body, idx));
+            }
+            return;
+        }
+
+        let first = nodes.len();
+        let (nw, ne, sw, se) = nodes[idx].space.quadrants();
+        for (offset, quadrant) in [nw, ne, sw, se].iter().enumerate() {
+            nodes.push(Node::new(first + offset, quadrant.space().clone()));
+        }
+        nodes[idx].children = Some(first);
+
+        for (offset, keys) in self.partition(keys, depth).iter().enumerate() {
+            self.build(nodes, leaves, first + offset, keys, depth + 1);
+        }
+
+        let (body, bodies) = Builder::sum(&nodes[first..first + 4]);
+        nodes[idx].body = body;
+        nodes[idx].bodies = bodies;
+    }
+
+    /// Splits the keys of a node at the given depth among its four children.
+    fn partition<'k>(&self, keys: &'k Keys, depth: u32) -> Vec<&'k Keys> {
+        let shift = 2 * (self.levels - 1 - depth);
+        let mut parts = Vec::with_capacity(4);
+        let mut start = 0;
+
+        for offset in 0..4 {
+            let end = start + keys[start..].partition_point(|(key, _)| {
+                ((key >> shift) & 3) as usize <= offset
+            });
+            parts.push(&keys[start..end]);
+            start = end;
+        }
+
+        parts
+    }
+
+    /// Returns the sum of the virtual bodies of the given nodes, and the
+    /// number of bodies they hold.
+    fn sum(nodes: &[Node]) -> (VirtualBody, usize) {
+        nodes.iter().fold((VirtualBody::zero(), 0), |(mut acc, count), node| {
+            acc.accumulate(&node.body);
+            (acc, count + node.bodies)
+        })
+    }
 }
 
 // PreorderTraverser /////////////////////////////////////////////////////////
 //
-// An iterator over nodes of a tree in preorder (Root, child 1, ..., child n).
+// An iterator over non empty nodes of a tree in preorder (Root, child 1, ...,
+// child n). Pending nodes are kept on an explicit stack. Only used to
+// inspect trees, walks use the allocation free `WalkStack`.
 
 struct PreorderTraverser<'a> {
     tree: &'a BHTree,
-    first: Option<&'a Node>,
-    stack: Vec<ChildIterator>,
+    stack: Vec<Index>,
+    pushed: usize,
 }
 
 impl<'a> Iterator for PreorderTraverser<'a> {
     type Item = &'a Node;
 
     fn next(&mut self) -> Option<Self::Item> {
-        // first element
-        if let Some(node) = self.first.take() {
-            self.stack.push(node.children());
-            return Some(node);
-        }
-
-        // next element
-        while let Some(mut iter) = self.stack.pop() {
-            while let Some(idx) = iter.next() {
-                if let Some(node) = self.tree.node(idx) {
-                    self.stack.push(iter);
-                    self.stack.push(node.children());
-                    return Some(node)
+        let node = &self.tree.nodes[self.stack.pop()?];
+        let stack_size = self.stack.len();
+
+        // push in reverse so that the first child is visited first
+        if let Some(first) = node.children {
+            for idx in (first..first + 4).rev() {
+                if !self.tree.nodes[idx].is_empty() {
+                    self.stack.push(idx);
                 }
             }
         }
-        None
+
+        self.pushed = self.stack.len() - stack_size;
+        Some(node)
     }
 }
 
 impl<'a> PreorderTraverser<'a> {
     /// Returns a new iterator at the node for the given index.
     fn new(tree: &'a BHTree, idx: Index) -> PreorderTraverser<'a> {
-        let node = tree.node(idx).expect("Node doesn't exist");
-        PreorderTraverser { tree, first: Some(node), stack: Vec::new() }
+        assert!(idx < tree.nodes.len(), "Node doesn't exist");
+        PreorderTraverser { tree, stack: vec![idx], pushed: 0 }
     }
 
+    /// Skips the children of the node last returned.
+    #[cfg(test)]
     fn skip_children(&mut self) {
-        self.stack.pop();
+        let len = self.stack.len() - self.pushed;
+        self.stack.truncate(len);
+        self.pushed = 0;
     }
 }
 
 // Node //////////////////////////////////////////////////////////////////////
 //
-// Represents a node in the BHTree. Since the BHTree uses indices to relate
-// nodes together, each node must minimally be aware of its own index. The
-// indices of related nodes can be calculated in constant time and thus
-// do not need to be stored.
+// Represents a node in the BHTree. Each node knows its own index in the
+// arena, the number of bodies below it, and the index of its first child,
+// if it has been split. Leaves also know each of their bodies.
 
 #[derive(Clone, Debug)]
 struct Node {
     pub id: Index,
     pub space: Square,
     pub body: VirtualBody,
+    pub bodies: usize,
+    pub members: Vec<Member>,
+    pub children: Option<Index>,
 }
 
 impl fmt::Display for Node {
@@ -300,17 +821,17 @@ impl fmt::Display for Node {
 }
 
 impl Node {
-    /// Creates a new node.
-    fn new(id: Index, space: Square, body: VirtualBody) -> Node {
-        Node { id, space, body }
+    /// Creates a new empty leaf.
+    fn new(id: Index, space: Square) -> Node {
+        Node { id, space, body: VirtualBody::zero(), bodies: 0, members: vec![], children: None }
     }
 
-    /// Creates a copy of the node after adding the given body.
-    fn with(&self, body: Body) -> Node {
-        let mut body = VirtualBody::from(body);
-        body.mass += self.body.mass;
-        body.position += self.body.position.clone();
-        Node::new(self.id, self.space.clone(), body)
+    /// Adds the given body to the leaf.
+    fn push(&mut self, member: Member) {
+        self.body.accumulate(&VirtualBody::new(
+            member.mass, member.position.x * member.mass, member.position.y * member.mass));
+        self.bodies += 1;
+        self.members.push(member);
     }
 
     /// Returns true if the node has no body.
@@ -318,106 +839,31 @@ impl Node {
         self.body == VirtualBody::zero()
     }
 
-    /// Index of the north west child.
-    fn nw(&self) -> Index {
-        4 * self.id + 1
-    }
-
-    /// Index of the north east child.
-    fn ne(&self) -> Index {
-        4 * self.id + 2
-    }
-
-    /// Index of the south west child.
-    fn sw(&self) -> Index {
-        4 * self.id + 3
-    }
-
-    /// Index of the south east child.
-    fn se(&self) -> Index {
-        4 * self.id + 4
-    }
-
-    /// Moves the body into a new child node and returns it, if it exists.
-    fn child_from_self(&self) -> Node {
-        // TODO: check that this is a leaf
-        let body = self.body.clone();
-        self.map_quadrant(body.centered().position, move |idx, q| {
-            Node::new(idx, q.space().clone(), body)
-        })
-    }
-
-    /// Finds quadrant containing the given point and passes it together with
-    /// its index to the function f. Panics if the point is out of bounds.
-    fn map_quadrant<U, F>(&self, point: Point, f: F) -> U
-    where F: FnOnce(Index, Quadrant) -> U {
-        let quadrant = self.space.quadrant(&point).unwrap_or_else(|err| {
-            panic!("Couldn't find quadrant. Reason: {} Got {:?}", err.kind(), point);
-        });
-
-        match quadrant {
-            NW(space) => f(self.nw(), NW(space)),
-            NE(space) => f(self.ne(), NE(space)),
-            SW(space) => f(self.sw(), SW(space)),
-            SE(space) => f(self.se(), SE(space)),
-        }
-    }
-
-    /// Returns an iterator over the ancestor indices.
-    fn ancestors(&self) -> AncestorIterator {
-        AncestorIterator::new(self.id)
-    }
-
-    /// Returns an iterator over the children indices.
-    fn children(&self) -> ChildIterator {
-        ChildIterator::new(self.id)
+    /// Returns true if the node has no children.
+    fn is_leaf(&self) -> bool {
+        self.children.is_none()
     }
 }
 
-// AncestorIterator /////////////////////////////////////////////////////////////
+// Member ////////////////////////////////////////////////////////////////////
 //
-// An iterator over the ancestor indices up towards the root index.
+// A body stored in a leaf, identified by its index.
 
-struct AncestorIterator {
-    current: Index,
+#[derive(Clone, PartialEq, Debug)]
+struct Member {
+    index: usize,
+    mass: Float,
+    position: Point,
 }
 
-impl Iterator for AncestorIterator {
-    type Item = Index;
-
-    fn next(&mut self) -> Option<Self::Item> {
-        if self.current == 0 {
-            None
-        } else {
-            self.current -= 1;
-            self.current /= 4;
-            Some(self.current)
-        }
+impl Member {
+    fn new(index: usize, body: &Body) -> Member {
+        Member { index, mass: body.mass.value(), position: body.position.clone() }
     }
-}
-
-impl AncestorIterator {
-    fn new(start: Index) -> AncestorIterator {
-        AncestorIterator { current: start }
-    }
-}
 
-// ChildIterator /////////////////////////////////////////////////////////////
-//
-// An iterator over child indices in order (child 1, ..., child n).
-
-struct ChildIterator(Range<Index>);
-
-impl Iterator for ChildIterator {
-    type Item = Index;
-    fn next(&mut self) -> Option<Self::Item> { self.0.next() }
-}
-
-impl ChildIterator {
-    /// Returns a new iterator for children of the given index.
-    fn new(parent: Index) -> ChildIterator {
-        let start = 4 * parent + 1;
-        ChildIterator(start..(start + 4))
+    /// Returns true if the given body has the mass and position of self.
+    fn is(&self, body: &Body) -> bool {
+        self.mass == body.mass.value() && self.position == body.position
     }
 }
 
@@ -439,7 +885,7 @@ mod tests {
     /// nodes are printed with their id and body.
     fn report(tree: &BHTree) -> String {
         tree.preorder().fold(String::new(), |acc, n| {
-            if tree.is_leaf(n) {
+            if n.is_leaf() {
                 let v = n.body.centered();
                 acc + &format!("#{}\t({}, {})\n", n.id, v.position.x, v.position.y)
             } else {
@@ -448,20 +894,24 @@ mod tests {
         })
     }
 
-    fn new_body(mass: f32, x: f32, y: f32) -> Body {
+    fn new_body(mass: Float, x: Float, y: Float) -> Body {
         Body::new(mass, Point::new(x, y), Vector::zero())
     }
 
-    fn virtual_body(mass: f32, x: f32, y: f32) -> VirtualBody {
-        VirtualBody {
-            mass,
-            position: Point::new(x, y),
-        }
+    fn virtual_body(mass: Float, x: Float, y: Float) -> VirtualBody {
+        VirtualBody::new(mass, x, y)
+    }
+
+    fn check_virtual_bodies(b1: &VirtualBody, b2: &VirtualBody) {
+        assert_close!(b1.mass, b2.mass);
+        assert_close!(b1.position.x, b2.position.x);
+        assert_close!(b1.position.y, b2.position.y);
     }
 
     fn check_bodies(b1: &Body, b2: &Body) {
         assert_eq!(b1.mass, b2.mass);
-        assert_eq!(b1.position, b2.position);
+        assert_close!(b1.position.x, b2.position.x);
+        assert_close!(b1.position.y, b2.position.y);
         assert_eq!(b1.velocity, b2.velocity);
     }
 
@@ -477,9 +927,9 @@ mod tests {
         let space = Square::new(-8.0, -8.0, 4);
 
         let mut tree = BHTree::new(space);
-        tree.add(new_body(2.0, 6.0, 7.0));    // A
-        tree.add(new_body(3.6, 1.0, 2.0));    // B
-        tree.add(new_body(1.5, -4.0, -4.0));  // C
+        tree.add(&new_body(2.0, 6.0, 7.0));    // A
+        tree.add(&new_body(3.6, 1.0, 2.0));    // B
+        tree.add(&new_body(1.5, -4.0, -4.0));  // C
         tree
     }
 
@@ -501,39 +951,267 @@ mod tests {
         let space = Square::new(0.0, 0.0, 5);
 
         let mut tree = BHTree::new(space);
-        tree.add(new_body(2.0, 10.0, 25.0));    // A
-        tree.add(new_body(1.0, 6.0, 20.0));     // B
-        tree.add(new_body(4.0, 31.0, 31.0));    // C
-        tree.add(new_body(3.0, 1.0, 10.0));     // D
-        tree.add(new_body(2.5, 3.0, 11.0));     // E
-        tree.add(new_body(1.5, 5.0, 10.0));     // F
-        tree.add(new_body(2.0, 1.0, 1.0));      // G
-        tree.add(new_body(3.5, 20.0, 10.0));    // H
+        tree.add(&new_body(2.0, 10.0, 25.0));    // A
+        tree.add(&new_body(1.0, 6.0, 20.0));     // B
+        tree.add(&new_body(4.0, 31.0, 31.0));    // C
+        tree.add(&new_body(3.0, 1.0, 10.0));     // D
+        tree.add(&new_body(2.5, 3.0, 11.0));     // E
+        tree.add(&new_body(1.5, 5.0, 10.0));     // F
+        tree.add(&new_body(2.0, 1.0, 1.0));      // G
+        tree.add(&new_body(3.5, 20.0, 10.0));    // H
         tree
     }
 
+    fn added_tree(space: &Square, bodies: &[Body]) -> BHTree {
+        let mut tree = BHTree::new(space.clone());
+        for body in bodies {
+            tree.add(body);
+        }
+        tree
+    }
+
+    /// Pseudo random bodies spread over the given space.
+    fn scattered_bodies(n: usize, space: &Square) -> Vec<Body> {
+        let mut seed: u32 = 17;
+        let mut next = move || {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            (seed >> 8) as Float / (1 << 24) as Float
+        };
+
+        (0..n).map(|_| {
+            let size = space.size as Float;
+            let x = space.origin.x + next() * size;
+            let y = space.origin.y + next() * size;
+            new_body(0.5 + next(), x, y)
+        }).collect()
+    }
+
+    fn assert_same_shape(lhs: &BHTree, rhs: &BHTree) {
+        let lhs: Vec<&Node> = lhs.preorder().collect();
+        let rhs: Vec<&Node> = rhs.preorder().collect();
+        assert_eq!(lhs.len(), rhs.len());
+
+        for (a, b) in lhs.iter().zip(rhs.iter()) {
+            assert_eq!(a.space, b.space);
+            assert_eq!(a.is_leaf(), b.is_leaf());
+            assert!((a.body.mass - b.body.mass).abs() <= 1e-4 * a.body.mass);
+
+            let (a, b) = (a.body.centered().position, b.body.centered().position);
+            assert!(a.distance_to(&b) <= 1e-2, "{:?} != {:?}", a, b);
+        }
+    }
+
+    #[test]
+    fn tree_from_bodies_matches_small_tree() {
+        // given
+        let bodies = vec![
+            new_body(2.0, 6.0, 7.0),
+            new_body(3.6, 1.0, 2.0),
+            new_body(1.5, -4.0, -4.0),
+        ];
+
+        // when
+        let sut = BHTree::from_bodies(Square::new(-8.0, -8.0, 4), &bodies);
+
+        // then
+        assert_same_shape(&small_tree(), &sut);
+    }
+
+    #[test]
+    fn tree_from_bodies_matches_added_bodies() {
+        // given more bodies than are built serially
+        let space = Square::new(-512.0, -512.0, 10);
+        let mut bodies = scattered_bodies(3 * PARALLEL_THRESHOLD, &space);
+        bodies.push(new_body(1.0, 600.0, 0.0));
+
+        let mut expected = BHTree::new(space.clone());
+        for body in bodies.iter() {
+            expected.add(body);
+        }
+
+        // when
+        let sut = BHTree::from_bodies(space, &bodies);
+
+        // then
+        assert_same_shape(&expected, &sut);
+
+        for (idx, node) in sut.nodes.iter().enumerate() {
+            assert_eq!(idx, node.id);
+        }
+    }
+
+    fn assert_locations(tree: &BHTree, bodies: &[Body]) {
+        for (body, leaf) in bodies.iter().zip(tree.locations.iter()) {
+            let leaf = tree.node(leaf.expect("Expected a leaf")).unwrap();
+            assert!(leaf.is_leaf());
+            assert!(leaf.space.contains(&body.position));
+        }
+    }
+
+    #[test]
+    fn tree_from_bodies_locates_bodies() {
+        // given
+        let space = Square::new(-512.0, -512.0, 10);
+        let bodies = scattered_bodies(3 * PARALLEL_THRESHOLD, &space);
+
+        // when
+        let sut = BHTree::from_bodies(space, &bodies);
+
+        // then
+        assert_locations(&sut, &bodies);
+    }
+
+    #[test]
+    fn tree_groups_partition_bodies() {
+        // given
+        let space = Square::new(-512.0, -512.0, 10);
+        let mut bodies = scattered_bodies(500, &space);
+        bodies.push(new_body(1.0, 2000.0, 0.0));
+        let sut = BHTree::from_bodies(space, &bodies);
+
+        // when
+        let result = sut.groups(8);
+
+        // then each body within the space is in exactly one group
+        let mut grouped: Vec<usize> = result.iter().flat_map(|g| g.bodies.clone()).collect();
+        grouped.sort_unstable();
+        assert_eq!((0..500).collect::<Vec<usize>>(), grouped);
+
+        for group in result.iter() {
+            assert!(!group.bodies.is_empty() && group.bodies.len() <= 8);
+            let space = &sut.nodes[group.node].space;
+            assert!(group.bodies.iter().all(|idx| space.contains(&bodies[*idx].position)));
+        }
+    }
+
+    #[test]
+    fn tree_walk_group_visits_mass_outside_group() {
+        // given
+        let space = Square::new(-512.0, -512.0, 10);
+        let bodies = scattered_bodies(500, &space);
+        let sut = BHTree::from_bodies(space, &bodies);
+
+        for group in sut.groups(8).iter() {
+            // when
+            let mut mass = 0.0;
+            sut.walk_group(group, |m, _| mass += m);
+
+            // then
+            let inside: Float = group.bodies.iter().map(|idx| bodies[*idx].mass.value()).sum();
+            let total = sut.nodes[0].body.mass;
+            assert!((total - inside - mass).abs() < 1e-2, "Expected {}, got {}", total - inside, mass);
+        }
+    }
+
+    #[test]
+    #[allow(clippy::unnecessary_cast)]
+    fn tree_moments_match_f64_reference() {
+        // given many bodies far from the origin, whose weighted positions
+        // sum to far more than the precision of a single body
+        let space = Square::new(1024.0, 1024.0, 10);
+        let bodies = scattered_bodies(50_000, &space);
+
+        // when
+        let result = BHTree::from_bodies(Square::new(-2048.0, -2048.0, 12), &bodies);
+
+        // then
+        let (m, x, y) = bodies.iter().fold((0.0, 0.0, 0.0), |(m, x, y), body| {
+            let mass = body.mass.value() as f64;
+            (m + mass, x + mass * body.position.x as f64, y + mass * body.position.y as f64)
+        });
+        let center = result.nodes[0].body.centered();
+        assert_close!(m as Float, center.mass);
+        assert_close!((x / m) as Float, center.position.x);
+        assert_close!((y / m) as Float, center.position.y);
+
+        // whereas a naive sum in single precision drifts
+        #[cfg(not(feature = "f64"))]
+        {
+            let (m, x) = bodies.iter().fold((0.0, 0.0), |(m, x): (Float, Float), body| {
+                (m + body.mass.value(), x + body.mass.value() * body.position.x)
+            });
+            assert!((x / m - center.position.x).abs() > 1e-3);
+        }
+    }
+
+    #[test]
+    fn tree_refits_moved_bodies() {
+        // given
+        let space = Square::new(-512.0, -512.0, 10);
+        let mut bodies = scattered_bodies(500, &space);
+        let mut sut = BHTree::from_bodies(space.clone(), &bodies);
+
+        // when a few bodies move far and the rest move slightly
+        for (idx, body) in bodies.iter_mut().enumerate() {
+            if idx % 20 == 0 {
+                body.position = Point::new(-body.position.x, body.position.y * 0.5);
+            } else {
+                body.position.x += 0.01;
+            }
+        }
+
+        // then
+        assert!(sut.refit(&bodies));
+        assert_locations(&sut, &bodies);
+
+        let mut expected = BHTree::new(space);
+        for body in bodies.iter() {
+            expected.add(body);
+        }
+
+        let total = |tree: &BHTree| tree.nodes[0].body.centered();
+        assert!((total(&expected).mass - total(&sut).mass).abs() < 1e-2);
+        assert!(total(&expected).position.distance_to(&total(&sut).position) < 1e-2);
+
+        for body in bodies.iter() {
+            let lhs = sut.virtual_bodies(body);
+            let rhs = expected.virtual_bodies(body);
+            assert_eq!(lhs.len(), rhs.len());
+        }
+
+        assert_eq!(bodies.len(), sut.nodes()[0].bodies);
+        assert_eq!(bodies.len(), sut.bodies_per_leaf().iter().sum::<usize>());
+    }
+
+    #[test]
+    fn tree_refuses_refit_of_scattered_bodies() {
+        // given
+        let space = Square::new(-512.0, -512.0, 10);
+        let mut bodies = scattered_bodies(100, &space);
+        let mut sut = BHTree::from_bodies(space, &bodies);
+
+        // when
+        for body in bodies.iter_mut() {
+            body.position = Point::new(-body.position.x, -body.position.y);
+        }
+
+        // then
+        assert!(!sut.refit(&bodies));
+        assert!(!sut.refit(&bodies[..50]));
+    }
+
     #[test]
     fn tree_adds_bodies() {
         let space = Square::new(-8.0, -8.0, 4);
         let mut tree = BHTree::new(space);
 
-        tree.add(new_body(2.0, 1.0, 2.0));
+        tree.add(&new_body(2.0, 1.0, 2.0));
         assert_eq!(report(&tree),
                    "#0\t(1, 2)\n".to_string());
 
-        tree.add(new_body(1.0, 6.0, 8.0));
+        tree.add(&new_body(1.0, 6.0, 8.0));
         assert_eq!(report(&tree),
                    "#0\n\
                     #2\n\
-                    #10\t(6, 8)\n\
-                    #11\t(1, 2)\n".to_string());
+                    #6\t(6, 8)\n\
+                    #7\t(1, 2)\n".to_string());
 
-        tree.add(new_body(4.0, -4.0, -4.0));
+        tree.add(&new_body(4.0, -4.0, -4.0));
         assert_eq!(report(&tree),
                    "#0\n\
                     #2\n\
-                    #10\t(6, 8)\n\
-                    #11\t(1, 2)\n\
+                    #6\t(6, 8)\n\
+                    #7\t(1, 2)\n\
                     #3\t(-4, -4)\n".to_string());
 
         println!("\nRESULTS ---------------------------------\n");
@@ -541,29 +1219,29 @@ mod tests {
     }
 
     #[test]
-    #[should_panic]
+    #[should_panic(expected = "Body is out of bounds.")]
     fn tree_panics_if_body_out_of_bounds() {
         // given
         let mut tree = BHTree::new(Square::new(-8.0, -8.0, 4));
         let body = Body::new(1.0, Point::new(0.0, 8.1), Vector::zero());
 
         // when, then
-        tree.insert(Pending(0, body));
+        tree.insert(0, &body);
     }
 
     #[test]
     fn tree_internalizes() {
         // given
         let mut sut = small_tree();
-        assert!(sut.is_leaf(sut.node(3).unwrap()));
+        assert!(sut.node(3).unwrap().is_leaf());
 
         // when
         sut.internalize(3);
 
         // then
-        assert!(!sut.is_leaf(sut.node(3).unwrap()));
+        assert!(!sut.node(3).unwrap().is_leaf());
 
-        let body = sut.node(13).unwrap().body.centered();
+        let body = sut.node(9).unwrap().body.centered();
         assert_eq!(VirtualBody::new(1.5, -4.0, -4.0), body);
     }
 
@@ -576,8 +1254,8 @@ mod tests {
         // then
         assert_eq!(0,  sut.next().unwrap().id);
         assert_eq!(2,  sut.next().unwrap().id);
-        assert_eq!(10, sut.next().unwrap().id);
-        assert_eq!(11, sut.next().unwrap().id);
+        assert_eq!(6,  sut.next().unwrap().id);
+        assert_eq!(7,  sut.next().unwrap().id);
         assert_eq!(3,  sut.next().unwrap().id);
     }
 
@@ -594,13 +1272,13 @@ mod tests {
         assert_eq!(7, sut.next().unwrap().id);
         assert_eq!(2, sut.next().unwrap().id);
         assert_eq!(3, sut.next().unwrap().id);
-        assert_eq!(13, sut.next().unwrap().id);
+        assert_eq!(9, sut.next().unwrap().id);
 
         // when
         sut.skip_children();
 
         // then
-        assert_eq!(15, sut.next().unwrap().id);
+        assert_eq!(11, sut.next().unwrap().id);
 
         // when
         sut.skip_children();
@@ -615,12 +1293,12 @@ mod tests {
         let sut = small_tree();
 
         // then
-        assert!(!sut.is_leaf(sut.node(0).unwrap()));
-        assert!(!sut.is_leaf(sut.node(2).unwrap()));
+        assert!(!sut.node(0).unwrap().is_leaf());
+        assert!(!sut.node(2).unwrap().is_leaf());
 
-        assert!(sut.is_leaf(sut.node(3).unwrap()));
-        assert!(sut.is_leaf(sut.node(10).unwrap()));
-        assert!(sut.is_leaf(sut.node(11).unwrap()));
+        assert!(sut.node(3).unwrap().is_leaf());
+        assert!(sut.node(6).unwrap().is_leaf());
+        assert!(sut.node(7).unwrap().is_leaf());
     }
 
     #[test]
@@ -637,23 +1315,23 @@ mod tests {
 
         // A B and C
         let expected = virtual_body(7.1, 1.3521128, 2.1408453);
-        assert_eq!(expected, result(0));
+        check_virtual_bodies(&expected, &result(0));
 
         // just C
         let expected = virtual_body(1.5, -4.0, -4.0);
-        assert_eq!(expected, result(3));
+        check_virtual_bodies(&expected, &result(3));
 
         // A and B
-        let expected = virtual_body(5.6, 2.785714286, 3.785714286);
-        assert_eq!(expected, result(2));
+        let expected = virtual_body(5.6, 2.785_714_4, 3.785_714_4);
+        check_virtual_bodies(&expected, &result(2));
 
         // just A
         let expected = virtual_body(2.0, 6.0, 7.0);
-        assert_eq!(expected, result(10));
+        check_virtual_bodies(&expected, &result(6));
 
         // just B
         let expected = virtual_body(3.6, 1.0, 2.0);
-        assert_eq!(expected, result(11));
+        check_virtual_bodies(&expected, &result(7));
     }
 
     #[test]
@@ -698,7 +1376,7 @@ mod tests {
         check_bodies(&new_body(4.0, 31.0, 31.0), &result[1]);
 
         // D, E, F & G
-        check_bodies(&new_body(9.0, 2.222222222, 8.277777778), &result[2]);
+        check_bodies(&new_body(9.0, 2.222_222_3, 8.277_778), &result[2]);
 
         // H
         check_bodies(&new_body(3.5, 20.0, 10.0), &result[3]);
@@ -719,13 +1397,13 @@ mod tests {
         assert_eq!(4, result.len());
 
         // A & B
-        check_bodies(&new_body(3.0, 8.666666667, 23.333333333), &result[0]);
+        check_bodies(&new_body(3.0, 8.666_667, 23.333_334), &result[0]);
 
         // C
         check_bodies(&new_body(4.0, 31.0, 31.0), &result[1]);
 
         // D, E & F
-        check_bodies(&new_body(7.0, 2.571428571, 10.357142857), &result[2]);
+        check_bodies(&new_body(7.0, 2.571_428_5, 10.357_142), &result[2]);
 
         // H
         check_bodies(&new_body(3.5, 20.0, 10.0), &result[3]);
@@ -736,20 +1414,37 @@ mod tests {
         // given
         let mut sut = BHTree::new(Square::new(0.0, 0.0, 2));
         let body = new_body(1.0, 2.0, 2.0);
-        sut.add(body.clone());
+        sut.add(&body);
 
         // when, then
         assert_eq!(0, sut.virtual_bodies(&body).len());
     }
 
+    #[test]
+    fn tree_virtual_bodies_includes_lighter_leaves() {
+        // given
+        let sut = small_tree();
+
+        // A, which is heavier than C
+        let body = new_body(2.0, 6.0, 7.0);
+
+        // when
+        let result = sut.virtual_bodies(&body);
+
+        // then
+        assert_eq!(2, result.len());
+        check_bodies(&new_body(3.6, 1.0, 2.0), &result[0]);
+        check_bodies(&new_body(1.5, -4.0, -4.0), &result[1]);
+    }
+
     #[test]
     fn tree_virtual_body_subtracts_given_body_from_leaf() {
         // given
         let mut sut = BHTree::new(Square::new(0.0, 0.0, 1));
 
         // two bodies within same unit
-        sut.add(new_body(0.5, 0.5, 2.0));
-        sut.add(new_body(2.0, 0.5, 1.5));
+        sut.add(&new_body(0.5, 0.5, 2.0));
+        sut.add(&new_body(2.0, 0.5, 1.5));
 
         // when
         let result = sut.virtual_bodies(&new_body(0.5, 0.5, 2.0));
@@ -759,17 +1454,152 @@ mod tests {
         check_bodies(&new_body(2.0, 0.5, 1.5), &result[0]);
     }
 
+    #[test]
+    fn tree_walk_point_visits_bodies_at_point() {
+        // given
+        let sut = small_tree();
+
+        // when at B
+        let mut visited = vec![];
+        sut.walk_point(&Point::new(1.0, 2.0), |mass, _| visited.push(mass));
+
+        // then B is visited along with A, and C
+        assert_eq!(vec![2.0, 3.6, 1.5], visited);
+    }
+
+    #[test]
+    fn tree_subdivides_beyond_index_limits() {
+        // given a space that requires more than 15 levels to separate bodies
+        let mut sut = BHTree::new(Square::new(0.0, 0.0, 20));
+
+        // when
+        sut.add(&new_body(1.0, 3.0, 3.0));
+        sut.add(&new_body(2.0, 3.5, 3.5));
+
+        // then
+        let leaves: Vec<&Node> = sut.preorder().filter(|n| n.is_leaf()).collect();
+        assert_eq!(2, leaves.len());
+        assert_eq!(1, leaves[0].space.size);
+        assert_eq!(1, leaves[1].space.size);
+    }
+
+    #[test]
+    fn tree_stats_small() {
+        // given
+        let sut = small_tree();
+
+        // when
+        let result = sut.stats();
+
+        // then
+        let expected = TreeStats {
+            nodes: 5,
+            leaves: 3,
+            max_depth: 2,
+            max_bodies_per_leaf: 1,
+            mean_bodies_per_leaf: 1.0,
+        };
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn tree_nodes_medium() {
+        // given
+        let sut = medium_tree();
+
+        // when
+        let result = sut.nodes();
+
+        // then the root comes first and holds all bodies
+        assert_eq!(None, result[0].parent);
+        assert_eq!(8, result[0].bodies);
+        assert_close!(19.5, result[0].mass);
+        assert_eq!(4, sut.max_depth());
+        assert_eq!(8, sut.leaf_count());
+        assert_eq!(sut.node_count(), result.len());
+
+        // parents come before their children
+        for (idx, node) in result.iter().enumerate().skip(1) {
+            let parent = result[..idx].iter().find(|p| Some(p.id) == node.parent).unwrap();
+            assert_eq!(parent.depth + 1, node.depth);
+            assert!(!parent.is_leaf);
+        }
+
+        // D is the first body in the deepest leaf
+        let d = result.iter().find(|node| node.depth == 4).unwrap();
+        assert_eq!(Point::new(1.0, 10.0), d.center);
+        assert_eq!(1, d.bodies);
+    }
+
+    #[test]
+    fn tree_counts_merged_bodies() {
+        // given
+        let space = Square::new(0.0, 0.0, 1);
+        let bodies = vec![new_body(0.5, 0.5, 2.0), new_body(2.0, 0.5, 1.5)];
+
+        // when
+        let added = added_tree(&space, &bodies);
+        let built = BHTree::from_bodies(space, &bodies);
+
+        // then
+        assert_eq!(vec![2], added.bodies_per_leaf());
+        assert_eq!(vec![2], built.bodies_per_leaf());
+    }
+
+    #[test]
+    fn tree_with_zero_theta_visits_each_body() {
+        // given
+        let space = Square::new(0.0, 0.0, 8);
+        let bodies = scattered_bodies(100, &space);
+        let sut = BHTree::from_bodies(space, &bodies).with_theta(0.0);
+
+        for body in bodies.iter() {
+            // when
+            let mut count = 0;
+            sut.walk(body, |_, _| count += 1);
+
+            // then
+            assert_eq!(bodies.len() - 1, count);
+        }
+    }
+
+    #[test]
+    fn tree_with_larger_theta_visits_fewer_nodes() {
+        // given
+        let space = Square::new(0.0, 0.0, 8);
+        let bodies = scattered_bodies(100, &space);
+        let tight = BHTree::from_bodies(space.clone(), &bodies).with_theta(0.5);
+        let loose = BHTree::from_bodies(space, &bodies).with_theta(DEFAULT_THETA);
+
+        // when
+        let count = |tree: &BHTree| {
+            let mut count = 0;
+            tree.walk(&bodies[0], |_, _| count += 1);
+            count
+        };
+
+        // then
+        assert!(count(&loose) < count(&tight));
+    }
+
+    #[test]
+    #[should_panic(expected = "The opening parameter must not be negative.")]
+    fn tree_panics_on_negative_theta() {
+        // given
+        BHTree::new(Square::new(0.0, 0.0, 1)).with_theta(-1.0);
+    }
+
     #[test]
     fn tree_has_maximum_depth() {
         // given
         let mut sut = BHTree::new(Square::new(0.0, 0.0, 1));
 
         // when two bodies within same unit
-        sut.add(new_body(0.5, 0.5, 2.0));
-        sut.add(new_body(2.0, 0.5, 1.5));
+        sut.add(&new_body(0.5, 0.5, 2.0));
+        sut.add(&new_body(2.0, 0.5, 1.5));
 
         // then there is only root & nw child
-        assert_eq!(2, sut.nodes.len());
+        assert_eq!(2, sut.preorder().count());
 
         // and the virtual body contains both bodies
         let body = sut.node(1).unwrap().body.clone();
@@ -778,29 +1608,112 @@ mod tests {
     }
 
     #[test]
-    fn ancestor_iterator_iterates() {
+    fn tree_splits_full_leaves() {
         // given
-        let mut sut = AncestorIterator { current: 632 };
+        let mut sut = BHTree::new(Square::new(0.0, 0.0, 4)).with_leaf_size(3);
+
+        // when
+        sut.add(&new_body(1.0, 2.0, 2.0));
+        sut.add(&new_body(1.0, 3.0, 3.0));
+        sut.add(&new_body(1.0, 12.0, 12.0));
+
+        // then the root holds all bodies
+        assert_eq!(vec![3], sut.bodies_per_leaf());
+
+        // when
+        sut.add(&new_body(1.0, 14.0, 10.0));
 
         // then
-        assert_eq!(Some(157), sut.next());
-        assert_eq!(Some(39), sut.next());
-        assert_eq!(Some(9), sut.next());
-        assert_eq!(Some(2), sut.next());
-        assert_eq!(Some(0), sut.next());
-        assert_eq!(None, sut.next());
+        assert_eq!(vec![2, 2], sut.bodies_per_leaf());
+        assert_eq!(3, sut.node_count());
     }
 
     #[test]
-    fn child_iterator_iterates() {
+    fn tree_from_bodies_with_leaf_size_matches_added_bodies() {
+        // given more bodies than are built serially
+        let space = Square::new(-512.0, -512.0, 10);
+        let bodies = scattered_bodies(3 * PARALLEL_THRESHOLD, &space);
+
+        let mut expected = BHTree::new(space.clone()).with_leaf_size(8);
+        for body in bodies.iter() {
+            expected.add(body);
+        }
+
+        // when
+        let keys = morton::sorted_keys(&space, &bodies);
+        let sut = BHTree::new(space.clone()).with_leaf_size(8).build(&bodies, &keys);
+
+        // then
+        assert_same_shape(&expected, &sut);
+        assert_locations(&sut, &bodies);
+        assert!(sut.bodies_per_leaf().iter().all(|n| *n <= 8));
+        assert!(sut.node_count() < BHTree::from_bodies(space, &bodies).node_count());
+    }
+
+    #[test]
+    fn tree_walk_visits_bodies_of_leaf_directly() {
         // given
-        let mut sut = ChildIterator::new(6);
+        let mut sut = BHTree::new(Square::new(0.0, 0.0, 4)).with_leaf_size(4);
+        let bodies = [
+            new_body(1.0, 2.0, 2.0),
+            new_body(2.0, 3.0, 3.0),
+            new_body(3.0, 12.0, 12.0),
+        ];
+        for body in bodies.iter() {
+            sut.add(body);
+        }
+
+        // when
+        let result = sut.virtual_bodies(&bodies[0]);
 
         // then
-        assert_eq!(Some(25), sut.next());
-        assert_eq!(Some(26), sut.next());
-        assert_eq!(Some(27), sut.next());
-        assert_eq!(Some(28), sut.next());
-        assert_eq!(None, sut.next());
+        assert_eq!(2, result.len());
+        check_bodies(&bodies[1], &result[0]);
+        check_bodies(&bodies[2], &result[1]);
+    }
+
+    #[test]
+    fn tree_keeps_coincident_bodies_apart() {
+        // given
+        let mut sut = BHTree::new(Square::new(0.0, 0.0, 4));
+        let body = new_body(1.0, 5.5, 5.5);
+        sut.add(&body);
+        sut.add(&body);
+        sut.add(&new_body(3.0, 5.25, 5.75));
+
+        // when
+        let result = sut.virtual_bodies(&body);
+
+        // then the other bodies of the unit square act on their own
+        assert_eq!(2, result.len());
+        check_bodies(&body, &result[0]);
+        check_bodies(&new_body(3.0, 5.25, 5.75), &result[1]);
+    }
+
+    #[test]
+    fn tree_refits_with_leaf_size() {
+        // given
+        let space = Square::new(-512.0, -512.0, 10);
+        let mut bodies = scattered_bodies(500, &space);
+        let keys = morton::sorted_keys(&space, &bodies);
+        let mut sut = BHTree::new(space).with_leaf_size(4).build(&bodies, &keys);
+
+        // when
+        for body in bodies.iter_mut().step_by(20) {
+            body.position = Point::new(-body.position.x, body.position.y * 0.5);
+        }
+
+        // then
+        assert!(sut.refit(&bodies));
+        assert_locations(&sut, &bodies);
+        assert!(sut.bodies_per_leaf().iter().all(|n| *n <= 4));
+        assert_eq!(bodies.len(), sut.bodies_per_leaf().iter().sum::<usize>());
+    }
+
+    #[test]
+    #[should_panic(expected = "Leaves must hold at least one body.")]
+    fn tree_panics_on_zero_leaf_size() {
+        // given
+        BHTree::new(Square::new(0.0, 0.0, 1)).with_leaf_size(0);
     }
 }
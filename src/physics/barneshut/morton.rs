@@ -0,0 +1,154 @@
+use rayon::prelude::*;
+
+use crate::geometry::{Point, Square};
+use crate::physics::Body;
+use crate::Float;
+
+// Morton Keys ///////////////////////////////////////////////////////////////
+//
+// A morton (Z-order) key encodes the path from the root of a quadtree to the
+// unit cell containing a point, two bits per level, in the order NW, NE, SW,
+// SE. Sorting bodies by key therefore places bodies that share a node next
+// to each other, in the same order as a preorder traversal of the tree.
+
+/// Returns the morton key of the given point within the given space. Points
+/// on the boundary between cells are resolved as in `Square::child_offset`.
+pub fn key(space: &Square, point: &Point) -> u64 {
+    let mut key = 0;
+    let mut origin = space.origin.clone();
+    let mut half = space.size >> 1;
+
+    while half > 0 {
+        let cell = Square { origin: origin.clone(), size: half << 1 };
+        let offset = cell.child_offset(point);
+        key = (key << 2) | offset as u64;
+
+        if offset & 1 == 1 { origin.x += half as Float; }
+        if offset & 2 == 0 { origin.y += half as Float; }
+        half >>= 1;
+    }
+
+    key
+}
+
+/// Returns the number of levels below the root of the given space, which is
+/// also the number of digits in its keys.
+pub fn levels(space: &Square) -> u32 {
+    let levels = space.size.trailing_zeros();
+    assert!(levels <= 32, "Space is too large for morton keys. Got size {}", space.size);
+    levels
+}
+
+/// Returns the keys of all bodies contained by the given space, paired with
+/// the index of the body and sorted by key. Ties keep the original order.
+pub fn sorted_keys(space: &Square, bodies: &[Body]) -> Vec<(u64, usize)> {
+    // keys must fit in 64 bits
+    levels(space);
+
+    let mut keys: Vec<(u64, usize)> = bodies.par_iter()
+        .enumerate()
+        .filter(|(_, body)| space.contains(&body.position))
+        .map(|(idx, body)| (key(space, &body.position), idx))
+        .collect();
+
+    radix_sort(&mut keys);
+    keys
+}
+
+/// Returns the indices 0..len in the order of the given sorted keys. Indices
+/// without a key come last, in their original order.
+pub fn order(keys: &[(u64, usize)], len: usize) -> Vec<usize> {
+    let mut keyed = vec![false; len];
+    let mut order = Vec::with_capacity(len);
+
+    for (_, idx) in keys {
+        keyed[*idx] = true;
+        order.push(*idx);
+    }
+
+    order.extend((0..len).filter(|idx| !keyed[*idx]));
+    order
+}
+
+/// Stable least significant digit radix sort on the keys, one byte per pass.
+/// Passes above the highest bit set in any key are skipped.
+fn radix_sort(items: &mut Vec<(u64, usize)>) {
+    let mut buffer = vec![(0, 0); items.len()];
+    let all_bits = items.iter().fold(0, |acc, (key, _)| acc | key);
+
+    for pass in 0..8 {
+        let shift = pass * 8;
+        if all_bits >> shift == 0 { break; }
+
+        let mut counts = [0usize; 257];
+        for (key, _) in items.iter() {
+            counts[((key >> shift) & 0xff) as usize + 1] += 1;
+        }
+        for i in 0..256 {
+            counts[i + 1] += counts[i];
+        }
+        for item in items.iter() {
+            let digit = ((item.0 >> shift) & 0xff) as usize;
+            buffer[counts[digit]] = *item;
+            counts[digit] += 1;
+        }
+
+        std::mem::swap(items, &mut buffer);
+    }
+}
+
+// Tests /////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use crate::geometry::Vector;
+
+    use super::*;
+
+    fn new_body(x: Float, y: Float) -> Body {
+        Body::new(1.0, Point::new(x, y), Vector::zero())
+    }
+
+    #[test]
+    fn key_follows_quadrant_path() {
+        // given
+        let space = Square::new(0.0, 0.0, 2);
+
+        // then NW, NE, SW, SE of the root, then of the child
+        assert_eq!(0b00_00, key(&space, &Point::new(0.5, 3.5)));
+        assert_eq!(0b01_11, key(&space, &Point::new(3.5, 2.5)));
+        assert_eq!(0b10_01, key(&space, &Point::new(1.5, 1.5)));
+        assert_eq!(0b11_10, key(&space, &Point::new(2.5, 0.5)));
+    }
+
+    #[test]
+    fn radix_sort_sorts_stably() {
+        // given
+        let mut items = vec![(300, 0), (2, 1), (1 << 40, 2), (2, 3), (0, 4), (300, 5)];
+
+        // when
+        radix_sort(&mut items);
+
+        // then
+        assert_eq!(vec![(0, 4), (2, 1), (2, 3), (300, 0), (300, 5), (1 << 40, 2)], items);
+    }
+
+    #[test]
+    fn order_sorts_bodies_in_z_order() {
+        // given
+        let space = Square::new(0.0, 0.0, 2);
+        let bodies = vec![
+            new_body(2.5, 0.5),   // SE
+            new_body(9.0, 9.0),   // out of bounds
+            new_body(0.5, 3.5),   // NW
+            new_body(1.5, 1.5),   // SW
+            new_body(3.5, 2.5),   // NE
+        ];
+
+        // when
+        let result = order(&sorted_keys(&space, &bodies), bodies.len());
+
+        // then
+        assert_eq!(vec![2, 4, 3, 0, 1], result);
+    }
+}
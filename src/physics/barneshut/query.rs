@@ -0,0 +1,226 @@
+use crate::geometry::{Point, Square};
+use crate::Float;
+
+use super::{BHTree, Index, WalkStack};
+
+// Queries ///////////////////////////////////////////////////////////////////
+//
+// Spatial searches over the bodies stored in the leaves of a tree, such as
+// for collisions or density estimates. Nodes whose space can't hold a match
+// are skipped along with their children, and the bodies of the remaining
+// leaves are checked one by one. Bodies are returned by their index, see
+// `BHTree`.
+
+impl BHTree {
+    /// Returns the bodies inside the given square, including its boundary.
+    pub fn bodies_in(&self, square: &Square) -> Vec<usize> {
+        self.search(|space| space.intersects(square), |position| square.contains(position))
+    }
+
+    /// Returns the bodies whose distance to the given point is at most the
+    /// given radius.
+    pub fn bodies_within(&self, point: &Point, radius: Float) -> Vec<usize> {
+        self.search(|space| space.distance_to(point) <= radius,
+                    |position| position.distance_to(point) <= radius)
+    }
+
+    /// Returns the k bodies nearest to the given point, nearest first. Fewer
+    /// bodies are returned if the tree holds less than k.
+    pub fn nearest(&self, point: &Point, k: usize) -> Vec<usize> {
+        if k == 0 {
+            return vec![];
+        }
+
+        // the nearest bodies found so far by ascending distance
+        let mut nearest: Vec<(Float, usize)> = Vec::with_capacity(k + 1);
+        let mut stack = WalkStack::new();
+        stack.push(0);
+
+        while let Some(idx) = stack.pop() {
+            let node = &self.nodes[idx];
+            if nearest.len() == k && node.space.distance_to(point) > nearest[k - 1].0 {
+                continue;
+            }
+
+            if let Some(first) = node.children {
+                // push the farthest child first, so that the nearest is
+                // searched first and tightens the bound early
+                let mut children: [(Float, Index); 4] = [0, 1, 2, 3]
+                    .map(|offset| (self.nodes[first + offset].space.distance_to(point), first + offset));
+                children.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+                for (_, child) in children.iter().filter(|(_, child)| !self.nodes[*child].is_empty()) {
+                    stack.push(*child);
+                }
+                continue;
+            }
+
+            for member in node.members.iter() {
+                let dist = member.position.distance_to(point);
+                if nearest.len() < k || dist < nearest[k - 1].0 {
+                    let at = nearest.partition_point(|(other, _)| *other <= dist);
+                    nearest.insert(at, (dist, member.index));
+                    nearest.truncate(k);
+                }
+            }
+        }
+
+        nearest.into_iter().map(|(_, index)| index).collect()
+    }
+
+    /// Returns the bodies of the leaves accepted by `explore` whose position
+    /// is accepted by `matches`. Children are only explored if their parent
+    /// is.
+    fn search<E, M>(&self, explore: E, matches: M) -> Vec<usize>
+        where E: Fn(&Square) -> bool, M: Fn(&Point) -> bool {
+        let mut result = vec![];
+        let mut stack = WalkStack::new();
+        stack.push(0);
+
+        while let Some(idx) = stack.pop() {
+            let node = &self.nodes[idx];
+            if !explore(&node.space) {
+                continue;
+            }
+
+            if let Some(first) = node.children {
+                stack.push_children(&self.nodes, first);
+            } else {
+                result.extend(node.members.iter()
+                    .filter(|member| matches(&member.position))
+                    .map(|member| member.index));
+            }
+        }
+
+        result
+    }
+}
+
+// Tests /////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use rand::distributions::{Distribution, Uniform};
+    use rand::{SeedableRng, StdRng};
+
+    use crate::geometry::Vector;
+    use crate::physics::Body;
+
+    use super::*;
+
+    fn scattered(n: usize) -> Vec<Body> {
+        let mut rng = StdRng::from_seed([3; 32]);
+        let range = Uniform::new(-60.0, 60.0);
+        (0..n).map(|_| {
+            let position = Point::new(range.sample(&mut rng), range.sample(&mut rng));
+            Body::new(1.0, position, Vector::zero())
+        }).collect()
+    }
+
+    fn sorted(mut indices: Vec<usize>) -> Vec<usize> {
+        indices.sort_unstable();
+        indices
+    }
+
+    #[test]
+    fn tree_finds_bodies_in_square() {
+        // given
+        let bodies = scattered(500);
+        let sut = BHTree::from_bodies(Square::new(-64.0, -64.0, 7), &bodies);
+        let square = Square::new(-10.0, 5.0, 5);
+
+        // when
+        let result = sut.bodies_in(&square);
+
+        // then
+        let expected: Vec<usize> = (0..bodies.len())
+            .filter(|idx| square.contains(&bodies[*idx].position))
+            .collect();
+        assert!(!expected.is_empty());
+        assert_eq!(expected, sorted(result));
+    }
+
+    #[test]
+    fn tree_finds_bodies_within_radius() {
+        // given
+        let bodies = scattered(500);
+        let sut = BHTree::from_bodies(Square::new(-64.0, -64.0, 7), &bodies);
+        let center = Point::new(12.5, -20.0);
+
+        // when
+        let result = sut.bodies_within(&center, 15.0);
+
+        // then
+        let expected: Vec<usize> = (0..bodies.len())
+            .filter(|idx| bodies[*idx].position.distance_to(&center) <= 15.0)
+            .collect();
+        assert!(!expected.is_empty());
+        assert_eq!(expected, sorted(result));
+    }
+
+    #[test]
+    fn tree_finds_nearest_bodies() {
+        // given
+        let bodies = scattered(500);
+        let sut = BHTree::from_bodies(Square::new(-64.0, -64.0, 7), &bodies);
+        let point = Point::new(-30.0, 41.0);
+
+        // when
+        let result = sut.nearest(&point, 10);
+
+        // then
+        let mut expected: Vec<usize> = (0..bodies.len()).collect();
+        expected.sort_by(|a, b| {
+            let a = bodies[*a].position.distance_to(&point);
+            let b = bodies[*b].position.distance_to(&point);
+            a.total_cmp(&b)
+        });
+        expected.truncate(10);
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn tree_finds_fewer_nearest_bodies_than_requested() {
+        // given
+        let bodies = scattered(3);
+        let sut = BHTree::from_bodies(Square::new(-64.0, -64.0, 7), &bodies);
+
+        // then
+        assert_eq!(3, sut.nearest(&Point::zero(), 5).len());
+        assert!(sut.nearest(&Point::zero(), 0).is_empty());
+    }
+
+    #[test]
+    fn tree_keeps_merged_bodies() {
+        // given two bodies sharing a unit square, and one out of bounds
+        let mut sut = BHTree::new(Square::new(0.0, 0.0, 3));
+        sut.add(&Body::new(1.0, Point::new(2.25, 2.25), Vector::zero()));
+        sut.add(&Body::new(1.0, Point::new(20.0, 2.0), Vector::zero()));
+        sut.add(&Body::new(1.0, Point::new(2.75, 2.5), Vector::zero()));
+        sut.add(&Body::new(1.0, Point::new(7.0, 7.0), Vector::zero()));
+
+        // then bodies are identified by the order they were added
+        assert_eq!(vec![0, 2], sorted(sut.bodies_within(&Point::new(2.5, 2.5), 0.5)));
+        assert_eq!(vec![3, 2, 0], sut.nearest(&Point::new(8.0, 8.0), 5));
+    }
+
+    #[test]
+    fn tree_finds_bodies_after_refit() {
+        // given
+        let mut bodies = scattered(500);
+        let mut sut = BHTree::from_bodies(Square::new(-64.0, -64.0, 7), &bodies);
+
+        // when
+        for body in bodies.iter_mut().take(20) {
+            body.position = Point::new(-body.position.x, -body.position.y);
+        }
+        assert!(sut.refit(&bodies));
+
+        // then
+        let square = Square::new(-32.0, -32.0, 6);
+        let expected: Vec<usize> = (0..bodies.len())
+            .filter(|idx| square.contains(&bodies[*idx].position))
+            .collect();
+        assert_eq!(expected, sorted(sut.bodies_in(&square)));
+    }
+}
@@ -1,8 +1,9 @@
 use std::fmt;
 
 use crate::geometry::Point;
-use crate::geometry::Vector;
+use crate::geometry::sum;
 use crate::physics::Body;
+use crate::Float;
 
 // VirtualBody ///////////////////////////////////////////////////////////////
 //
@@ -10,11 +11,16 @@ use crate::physics::Body;
 // total sum of the collected masses and its position is the total sum of mass
 // weighted positions. To obtain a copy with the position centered on its
 // mass, call the `centered()` method.
+//
+// Nodes near the root sum many bodies, whose weighted positions are large
+// compared to each body. The rounding error of these sums is kept in a
+// compensation term, which is added back by `centered()`.
 
 #[derive(Clone, PartialEq, Debug)]
 pub struct VirtualBody {
-    pub mass: f32,
+    pub mass: Float,
     pub position: Point,
+    compensation: (Float, Float, Float),
 }
 
 impl fmt::Display for VirtualBody {
@@ -25,24 +31,22 @@ impl fmt::Display for VirtualBody {
     }
 }
 
-impl From<Body> for VirtualBody {
-    fn from(body: Body) -> Self {
-        VirtualBody {
-            mass: body.mass.value(),
-            position: &body.position * body.mass.value(),
-        }
+impl From<&Body> for VirtualBody {
+    fn from(body: &Body) -> Self {
+        VirtualBody::new(
+            body.mass.value(),
+            body.position.x * body.mass.value(),
+            body.position.y * body.mass.value(),
+        )
     }
 }
 
 impl VirtualBody {
-    pub fn to_body(self) -> Body {
-        Body::new(self.mass, self.position.clone(), Vector::zero())
-    }
-
-    pub fn new(mass: f32, x: f32, y: f32) -> VirtualBody {
+    pub fn new(mass: Float, x: Float, y: Float) -> VirtualBody {
         VirtualBody {
             mass,
             position: Point::new(x, y),
+            compensation: (0.0, 0.0, 0.0),
         }
     }
 
@@ -50,12 +54,19 @@ impl VirtualBody {
         VirtualBody::new(0.0, 0.0, 0.0)
     }
 
+    /// Adds the mass and weighted position of the given virtual body.
+    pub fn accumulate(&mut self, other: &VirtualBody) {
+        let (m, x, y) = &mut self.compensation;
+        sum::add(&mut self.mass, m, other.mass + other.compensation.0);
+        sum::add(&mut self.position.x, x, other.position.x + other.compensation.1);
+        sum::add(&mut self.position.y, y, other.position.y + other.compensation.2);
+    }
+
     pub fn centered(&self) -> VirtualBody {
-        debug_assert!(self.mass > 0.0, "Mass must be positive. Got {}", self.mass);
-        VirtualBody {
-            mass: self.mass,
-            position: &self.position / self.mass,
-        }
+        let (m, x, y) = self.compensation;
+        let mass = self.mass + m;
+        debug_assert!(mass > 0.0, "Mass must be positive. Got {}", mass);
+        VirtualBody::new(mass, (self.position.x + x) / mass, (self.position.y + y) / mass)
     }
 }
 
@@ -73,7 +84,8 @@ mod tests {
 
         // given, then
         let sut = VirtualBody::new(2.4, -24.6, -4.8);
-        assert_eq!(Point::new(-10.25, -2.0), sut.centered().position);
+        assert_close!(-10.25, sut.centered().position.x);
+        assert_close!(-2.0, sut.centered().position.y);
 
         // given, then
         let sut = VirtualBody::new(14.5, 0.0, 0.0);
@@ -0,0 +1,427 @@
+use std::array;
+use std::convert::TryInto;
+
+use rayon::prelude::*;
+
+use crate::geometry::three::Vector3;
+use crate::geometry::Vector;
+use crate::geometry::sum::{self, Sum};
+use crate::Float;
+
+use super::force::Gravity;
+use super::three::Body3;
+use super::Body;
+
+// Direct Summation //////////////////////////////////////////////////////////
+//
+// An optimised kernel for the exact force between every pair of bodies. The
+// positions and masses are copied into separate arrays, padded with massless
+// bodies to a multiple of the block size. The pairs are then visited in
+// square tiles of blocks, so that both blocks of a tile stay in the cache,
+// and each pair is evaluated once, adding the force to one body and the
+// reaction to the other.
+//
+// Rows of blocks are distributed among a bounded number of tasks. Each task
+// writes the forces on its own rows and the reactions on all later rows
+// into a private buffer, and the buffers are summed in task order. The
+// result therefore does not depend on the number of threads. All sums are
+// compensated, so that the many small forces from distant bodies are not
+// lost next to the few large forces from close bodies.
+//
+// The kernel works on bodies with any number of coordinates, which are
+// stored in one array per axis.
+
+/// The number of bodies along each side of a tile.
+const BLOCK: usize = 256;
+
+/// The number of independent accumulators in the inner loop, which lets the
+/// compiler vectorise it.
+const LANES: usize = 8;
+
+/// The maximum number of row tasks, which bounds the memory of the buffers.
+const MAX_TASKS: usize = 64;
+
+/// Returns the force on each of the given bodies exerted by all others.
+pub fn forces<B: Particle<D>, const D: usize>(gravity: &Gravity, bodies: &[B]) -> Vec<B::Force> {
+    let particles = Particles::new(bodies);
+    let blocks = particles.len() / BLOCK;
+    let rows = blocks.div_ceil(MAX_TASKS).max(1) * BLOCK;
+
+    let starts: Vec<usize> = (0..particles.len()).step_by(rows).collect();
+    let partials: Vec<Partial<D>> = starts.into_par_iter()
+        .map(|start| particles.task(gravity, start, (start + rows).min(particles.len())))
+        .collect();
+
+    (0..bodies.len()).into_par_iter().map(|idx| {
+        let mut total = [Sum::new(); D];
+        for partial in partials.iter().take_while(|p| p.start <= idx) {
+            let i = idx - partial.start;
+            for (axis, sum) in total.iter_mut().enumerate() {
+                *sum += partial.forces[axis][i];
+                *sum += partial.compensations[axis][i];
+            }
+        }
+        B::force(total.map(|sum| sum.value()))
+    }).collect()
+}
+
+// Particle //////////////////////////////////////////////////////////////////
+//
+// A body with D coordinates, and its force vector.
+
+pub trait Particle<const D: usize>: Sync {
+    type Force: Send;
+
+    fn mass(&self) -> Float;
+
+    fn coordinates(&self) -> [Float; D];
+
+    /// Returns the force with the given components.
+    fn force(components: [Float; D]) -> Self::Force;
+}
+
+impl Particle<2> for Body {
+    type Force = Vector;
+
+    fn mass(&self) -> Float {
+        self.mass.value()
+    }
+
+    fn coordinates(&self) -> [Float; 2] {
+        [self.position.x, self.position.y]
+    }
+
+    fn force([dx, dy]: [Float; 2]) -> Vector {
+        Vector::new(dx, dy)
+    }
+}
+
+impl Particle<3> for Body3 {
+    type Force = Vector3;
+
+    fn mass(&self) -> Float {
+        self.mass.value()
+    }
+
+    fn coordinates(&self) -> [Float; 3] {
+        [self.position.x, self.position.y, self.position.z]
+    }
+
+    fn force([dx, dy, dz]: [Float; 3]) -> Vector3 {
+        Vector3::new(dx, dy, dz)
+    }
+}
+
+// Particles /////////////////////////////////////////////////////////////////
+//
+// Structure of arrays layout of the bodies, padded to a multiple of BLOCK.
+
+struct Particles<const D: usize> {
+    axes: [Vec<Float>; D],
+    ms: Vec<Float>,
+}
+
+impl<const D: usize> Particles<D> {
+    fn new<B: Particle<D>>(bodies: &[B]) -> Particles<D> {
+        let len = bodies.len().div_ceil(BLOCK) * BLOCK;
+        let mut particles = Particles {
+            axes: array::from_fn(|_| vec![0.0; len]),
+            ms: vec![0.0; len],
+        };
+
+        for (idx, body) in bodies.iter().enumerate() {
+            for (axis, coordinate) in body.coordinates().iter().enumerate() {
+                particles.axes[axis][idx] = *coordinate;
+            }
+            particles.ms[idx] = body.mass();
+        }
+
+        particles
+    }
+
+    fn len(&self) -> usize {
+        self.ms.len()
+    }
+
+    /// Evaluates all pairs whose first body is within the given rows.
+    fn task(&self, gravity: &Gravity, start: usize, end: usize) -> Partial<D> {
+        let mut partial = Partial::new(start, self.len() - start);
+
+        for row in (start..end).step_by(BLOCK) {
+            self.diagonal(gravity, row, &mut partial);
+            for column in (row + BLOCK..self.len()).step_by(BLOCK) {
+                self.tile(gravity, row, column, &mut partial);
+            }
+        }
+
+        partial
+    }
+
+    /// Evaluates the pairs within the block starting at `row`.
+    fn diagonal(&self, gravity: &Gravity, row: usize, partial: &mut Partial<D>) {
+        for i in row..row + BLOCK {
+            for j in i + 1..row + BLOCK {
+                let d: [Float; D] = array::from_fn(|axis| self.axes[axis][j] - self.axes[axis][i]);
+                let f = self.ms[i] * self.ms[j] * gravity.strength(d.iter().map(|d| d * d).sum());
+
+                partial.add(i, d.map(|d| f * d));
+                partial.add(j, d.map(|d| -f * d));
+            }
+        }
+    }
+
+    /// Evaluates the pairs between the block starting at `row` and the block
+    /// starting at `column`. The reactions are collected per tile and added
+    /// to the partial at the end.
+    fn tile(&self, gravity: &Gravity, row: usize, column: usize, partial: &mut Partial<D>) {
+        let columns = column..column + BLOCK;
+        let axes: [&[Float]; D] = array::from_fn(|axis| &self.axes[axis][columns.clone()]);
+        let ms = &self.ms[columns];
+        let mut reactions = [[Lanes::default(); D]; BLOCK / LANES];
+
+        for i in row..row + BLOCK {
+            let position: [Float; D] = array::from_fn(|axis| self.axes[axis][i]);
+            let mut forces = [Lanes::default(); D];
+
+            for (chunk, reactions) in reactions.iter_mut().enumerate() {
+                let range = chunk * LANES..(chunk + 1) * LANES;
+                let xs: [&[Float; LANES]; D] = array::from_fn(|axis| lanes(&axes[axis][range.clone()]));
+                let ms = lanes(&ms[range]);
+                interact(gravity, (&position, self.ms[i]), (&xs, ms), &mut forces, reactions);
+            }
+
+            partial.add_lanes(i, &forces);
+        }
+
+        for (chunk, reactions) in reactions.iter().enumerate() {
+            for lane in 0..LANES {
+                let j = column + chunk * LANES + lane;
+                partial.add(j, array::from_fn(|axis| reactions[axis].sum[lane]));
+                partial.add(j, array::from_fn(|axis| -reactions[axis].compensation[lane]));
+            }
+        }
+    }
+}
+
+/// Evaluates the pairs between a body and a chunk of bodies, adding the
+/// forces to the body's lanes and the reactions to the chunk's lanes.
+fn interact<const D: usize>(
+    gravity: &Gravity,
+    (position, mi): (&[Float; D], Float),
+    (xs, ms): (&[&[Float; LANES]; D], &[Float; LANES]),
+    forces: &mut [Lanes; D],
+    reactions: &mut [Lanes; D],
+) {
+    for lane in 0..LANES {
+        let d: [Float; D] = array::from_fn(|axis| xs[axis][lane] - position[axis]);
+        let f = mi * ms[lane] * gravity.strength(d.iter().map(|d| d * d).sum());
+
+        for axis in 0..D {
+            forces[axis].add(lane, f * d[axis]);
+            reactions[axis].add(lane, -f * d[axis]);
+        }
+    }
+}
+
+/// Views a chunk as an array, so that the inner loop has no bounds checks.
+fn lanes(chunk: &[Float]) -> &[Float; LANES] {
+    chunk.try_into().expect("Expected a full chunk")
+}
+
+// Lanes /////////////////////////////////////////////////////////////////////
+//
+// Independent compensated sums, one per lane of the inner loop. These use
+// Kahan's branch free update, which keeps the inner loop fast. Note that
+// the sum is corrected by subtracting the compensation.
+
+#[derive(Copy, Clone, Default)]
+struct Lanes {
+    sum: [Float; LANES],
+    compensation: [Float; LANES],
+}
+
+impl Lanes {
+    #[inline]
+    fn add(&mut self, lane: usize, x: Float) {
+        let y = x - self.compensation[lane];
+        let total = self.sum[lane] + y;
+        self.compensation[lane] = (total - self.sum[lane]) - y;
+        self.sum[lane] = total;
+    }
+}
+
+// Partial ///////////////////////////////////////////////////////////////////
+//
+// Compensated forces on the bodies from `start` to the end, accumulated by
+// one task. Bodies are addressed by their index among all bodies.
+
+struct Partial<const D: usize> {
+    start: usize,
+    forces: [Vec<Float>; D],
+    compensations: [Vec<Float>; D],
+}
+
+impl<const D: usize> Partial<D> {
+    fn new(start: usize, len: usize) -> Partial<D> {
+        Partial {
+            start,
+            forces: array::from_fn(|_| vec![0.0; len]),
+            compensations: array::from_fn(|_| vec![0.0; len]),
+        }
+    }
+
+    fn add(&mut self, idx: usize, force: [Float; D]) {
+        let i = idx - self.start;
+        for (axis, component) in force.iter().enumerate() {
+            sum::add(&mut self.forces[axis][i], &mut self.compensations[axis][i], *component);
+        }
+    }
+
+    fn add_lanes(&mut self, idx: usize, lanes: &[Lanes; D]) {
+        for lane in 0..LANES {
+            self.add(idx, array::from_fn(|axis| lanes[axis].sum[lane]));
+            self.add(idx, array::from_fn(|axis| -lanes[axis].compensation[lane]));
+        }
+    }
+}
+
+// Tests /////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use crate::geometry::three::Point3;
+    use crate::geometry::Point;
+
+    use super::*;
+
+    fn new_body(mass: Float, x: Float, y: Float) -> Body {
+        Body::new(mass, Point::new(x, y), Vector::zero())
+    }
+
+    fn scattered_bodies(n: usize) -> Vec<Body> {
+        let mut seed: u32 = 29;
+        let mut next = move || {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            (seed >> 8) as Float / (1 << 24) as Float
+        };
+
+        (0..n).map(|_| {
+            let (x, y) = (next() * 1000.0 - 500.0, next() * 1000.0 - 500.0);
+            new_body(0.5 + next(), x, y)
+        }).collect()
+    }
+
+    #[test]
+    fn direct_forces_match_pairwise_gravity() {
+        // given more than one block, and a partial one
+        let gravity = Gravity::new(1.0, 4.0);
+        let bodies = scattered_bodies(BLOCK * 2 + 37);
+
+        // when
+        let result = forces(&gravity, &bodies);
+
+        // then
+        for (body, force) in bodies.iter().zip(result.iter()) {
+            let expected = bodies.iter().fold(Vector::zero(), |acc, other| {
+                acc + gravity.between(body, other)
+            });
+            let error = Vector::new(force.dx - expected.dx, force.dy - expected.dy);
+            assert!(error.magnitude() <= 1e-4 * expected.magnitude(),
+                    "Expected {:?}, got {:?}", expected, force);
+        }
+    }
+
+    #[test]
+    fn direct_forces_match_pairwise_gravity_in_3d() {
+        // given
+        let gravity = Gravity::new(1.0, 4.0);
+        let bodies: Vec<Body3> = scattered_bodies(BLOCK + 37).iter().enumerate().map(|(idx, body)| {
+            let position = Point3::new(body.position.x, body.position.y, (idx % 17) as Float * 20.0);
+            Body3::new(body.mass.value(), position, Vector3::zero())
+        }).collect();
+
+        // when
+        let result = forces(&gravity, &bodies);
+
+        // then
+        for (body, force) in bodies.iter().zip(result.iter()) {
+            let expected = bodies.iter().fold(Vector3::zero(), |acc, other| {
+                acc + gravity.between3(body, other)
+            });
+            let error = Vector3::new(force.dx - expected.dx, force.dy - expected.dy, force.dz - expected.dz);
+            assert!(error.magnitude() <= 1e-4 * expected.magnitude(),
+                    "Expected {:?}, got {:?}", expected, force);
+        }
+    }
+
+    #[test]
+    fn direct_forces_ignore_coincident_bodies() {
+        // given
+        let gravity = Gravity::new(1.0, 4.0);
+        let bodies = vec![new_body(1.0, 3.0, 3.0), new_body(2.0, 3.0, 3.0)];
+
+        // when
+        let result = forces(&gravity, &bodies);
+
+        // then
+        assert_eq!(vec![Vector::zero(), Vector::zero()], result);
+    }
+
+    #[test]
+    fn direct_forces_conserve_momentum() {
+        // given
+        let gravity = Gravity::new(1.0, 4.0);
+        let bodies = scattered_bodies(BLOCK + 5);
+
+        // when
+        let result = forces(&gravity, &bodies);
+
+        // then the forces cancel up to rounding
+        let total = result.iter().fold(Vector::zero(), |acc, f| acc + f.clone());
+        let scale = result.iter().map(|f| f.magnitude()).sum::<Float>();
+        assert!(total.magnitude() <= 1e-5 * scale, "Got {:?}", total);
+    }
+
+    /// Returns the force on the first body in double precision.
+    #[allow(clippy::unnecessary_cast)]
+    fn reference_force(bodies: &[Body], min_dist: f64) -> (f64, f64) {
+        let body = &bodies[0];
+        bodies.iter().skip(1).fold((0.0, 0.0), |(fx, fy), other| {
+            let dx = other.position.x as f64 - body.position.x as f64;
+            let dy = other.position.y as f64 - body.position.y as f64;
+            let r = (dx * dx + dy * dy).sqrt();
+            let f = body.mass.value() as f64 * other.mass.value() as f64
+                / (r * r.max(min_dist) * r.max(min_dist));
+            (fx + f * dx, fy + f * dy)
+        })
+    }
+
+    #[test]
+    fn direct_forces_match_f64_reference() {
+        // given a close heavy body and many light distant bodies, whose
+        // forces are each below the precision of the total force
+        let gravity = Gravity::new(1.0, 4.0);
+        let mut bodies = vec![new_body(1.0, 0.0, 0.0), new_body(1000.0, 5.0, 0.0)];
+        for i in 0..2000 {
+            let angle = (i as Float / 2000.0 - 0.5) * 0.2;
+            bodies.push(new_body(1.0, 1000.0 * angle.cos(), 1000.0 * angle.sin()));
+        }
+
+        // when
+        let result = forces(&gravity, &bodies);
+
+        // then
+        let (fx, fy) = reference_force(&bodies, 4.0);
+        let error = Vector::new(result[0].dx - fx as Float, result[0].dy - fy as Float);
+        assert!(error.magnitude() < 1e-6 * fx as Float, "Expected {}, got {:?}", fx, result[0]);
+
+        // whereas a naive sum in single precision loses the distant bodies
+        #[cfg(not(feature = "f64"))]
+        {
+            let naive = bodies.iter().fold(Vector::zero(), |acc, other| {
+                acc + gravity.between(&bodies[0], other)
+            });
+            assert!((naive.dx - fx as Float).abs() > 1e-5 * fx as Float);
+        }
+    }
+}
@@ -1,22 +1,71 @@
-use crate::geometry::{Square, Vector};
+use std::sync::{Arc, Mutex};
 
-use super::barneshut::BHTree;
-use super::force::Gravity;
+use rayon::prelude::*;
+
+use crate::geometry::{Point, Square, Vector};
+use crate::geometry::sum::{Sum, VectorSum};
+use crate::Float;
+
+use super::barneshut::{BHTree, DEFAULT_LEAF_SIZE, DEFAULT_THETA, morton, TreeStats};
+use super::direct;
+use super::force::{Gravity, MIN_DISTANCE};
+use super::mesh::{Mesh, short_range_factor, short_range_potential_factor};
 use super::Body;
 
 // Field /////////////////////////////////////////////////////////////////////
 //
 // A field represents an instance of space in which bodies are affected by
-// gravitational force.
+// gravitational force. Fields evaluate bodies in parallel on the current
+// rayon thread pool. The force on each body is accumulated by a single
+// thread in a fixed order, so results do not depend on the number of
+// threads.
+//
+// Besides forces, a field evaluates the gravitational potential, that is the
+// potential energy of a unit mass. The field can also be sampled at points
+// that are not bodies, such as the cells of a map.
 
-pub trait Field {
+pub trait Field: Sync {
     fn forces(&self, bodies: &[Body]) -> Vec<Vector>;
+
+    /// Returns the potential at each body due to the others. The potential
+    /// energy of a body is its potential times its mass.
+    fn potentials(&self, bodies: &[Body]) -> Vec<Float>;
+
+    /// Returns the acceleration and potential at each of the given points
+    /// due to the bodies.
+    fn sample(&self, bodies: &[Body], points: &[Point]) -> Vec<Sample>;
+}
+
+/// Lets a field be inspected while an environment owns it.
+impl<F: Field + Send> Field for Arc<F> {
+    fn forces(&self, bodies: &[Body]) -> Vec<Vector> {
+        self.as_ref().forces(bodies)
+    }
+
+    fn potentials(&self, bodies: &[Body]) -> Vec<Float> {
+        self.as_ref().potentials(bodies)
+    }
+
+    fn sample(&self, bodies: &[Body], points: &[Point]) -> Vec<Sample> {
+        self.as_ref().sample(bodies, points)
+    }
+}
+
+// Sample ////////////////////////////////////////////////////////////////////
+//
+// The state of a field at a point.
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Sample {
+    pub acceleration: Vector,
+    pub potential: Float,
 }
 
 // BruteForceField ///////////////////////////////////////////////////////////
 //
-// Brute force gravitation calculation between n bodies. For every body,
-// calculate the gravitational force with every other body directly.
+// Brute force gravitation calculation between n bodies. For every pair of
+// bodies, calculate the gravitational force directly, using the optimised
+// kernel in `direct`. This is the ground truth for the other fields.
 
 pub struct BruteForceField {
     force: Gravity,
@@ -24,26 +73,36 @@ pub struct BruteForceField {
 
 impl Field for BruteForceField {
     fn forces(&self, bodies: &[Body]) -> Vec<Vector> {
-        let mut result: Vec<Vector> = vec![];
-
-        for body in bodies {
-            let mut cumulative_force = Vector::zero();
+        direct::forces(&self.force, bodies)
+    }
 
-            for other in bodies {
-                cumulative_force += self.force.between(body, other);
+    fn potentials(&self, bodies: &[Body]) -> Vec<Float> {
+        bodies.par_iter().enumerate().map(|(idx, body)| {
+            let mut total = Sum::new();
+            for (_, other) in bodies.iter().enumerate().filter(|(other, _)| *other != idx) {
+                total += self.force.potential(&body.position, other.mass.value(), &other.position);
             }
+            total.value()
+        }).collect()
+    }
 
-            result.push(cumulative_force);
-        }
-
-        result
+    fn sample(&self, bodies: &[Body], points: &[Point]) -> Vec<Sample> {
+        points.par_iter().map(|point| {
+            let mut acceleration = VectorSum::new();
+            let mut potential = Sum::new();
+            for body in bodies {
+                acceleration += self.force.acceleration(point, body.mass.value(), &body.position);
+                potential += self.force.potential(point, body.mass.value(), &body.position);
+            }
+            Sample { acceleration: acceleration.value(), potential: potential.value() }
+        }).collect()
     }
 }
 
 impl Default for BruteForceField {
     fn default() -> Self {
         BruteForceField {
-            force: Gravity::new(1.0, 4.0),
+            force: Gravity::new(1.0, MIN_DISTANCE),
         }
     }
 }
@@ -55,40 +114,682 @@ impl BruteForceField {
 }
 
 // BHField ///////////////////////////////////////////////////////////////////
+//
+// Barnes-Hut gravitation calculation. An incremental field keeps its tree
+// between calls and refits it to the moved bodies, only rebuilding it when
+// the tree refuses the refit. A grouped field walks the tree once per group
+// of nearby bodies, and the bodies within a group interact directly.
+//
+// The field keeps the last tree and records the number of interactions of
+// each body, that is the number of virtual and real bodies acting on it, for
+// inspection.
 
 // TODO: I want to be able to mark a body as unmoveable.
 
 pub struct BHField {
     space: Square,
     force: Gravity,
+    incremental: bool,
+    group_size: Option<usize>,
+    theta: Float,
+    leaf_size: usize,
+    state: Mutex<Option<(BHTree, Vec<usize>)>>,
+    interactions: Mutex<Vec<usize>>,
 }
 
 impl Field for BHField {
     fn forces(&self, bodies: &[Body]) -> Vec<Vector> {
-        let mut result: Vec<Vector> = vec![];
-        let mut tree = BHTree::new(self.space.clone());
+        let (result, interactions) = self.with_tree(bodies, |tree, order| {
+            match self.group_size {
+                Some(size) => self.grouped_forces(tree, bodies, size),
+                None => self.single_forces(tree, order, bodies),
+            }
+        });
+        *self.interactions.lock().expect("Interactions are poisoned.") = interactions;
+        result
+    }
 
-        for body in bodies {
-            tree.add(body.clone());
+    fn potentials(&self, bodies: &[Body]) -> Vec<Float> {
+        self.with_tree(bodies, |tree, _| {
+            bodies.par_iter().map(|body| {
+                let mut total = Sum::new();
+                tree.walk(body, |mass, position| {
+                    total += self.force.potential(&body.position, mass, position);
+                });
+                total.value()
+            }).collect()
+        })
+    }
+
+    fn sample(&self, bodies: &[Body], points: &[Point]) -> Vec<Sample> {
+        self.with_tree(bodies, |tree, _| {
+            points.par_iter().map(|point| {
+                let mut acceleration = VectorSum::new();
+                let mut potential = Sum::new();
+                tree.walk_point(point, |mass, position| {
+                    acceleration += self.force.acceleration(point, mass, position);
+                    potential += self.force.potential(point, mass, position);
+                });
+                Sample { acceleration: acceleration.value(), potential: potential.value() }
+            }).collect()
+        })
+    }
+}
+
+impl Default for BHField {
+    fn default() -> Self {
+        BHField {
+            space: Square::new(-2048.0, -2048.0, 12),
+            force: Gravity::new(1.0, MIN_DISTANCE),
+            incremental: false,
+            group_size: None,
+            theta: DEFAULT_THETA,
+            leaf_size: DEFAULT_LEAF_SIZE,
+            state: Mutex::new(None),
+            interactions: Mutex::new(vec![]),
         }
+    }
+}
 
-        for body in bodies {
-            let f = tree.virtual_bodies(body).iter().fold(Vector::zero(), |acc, n| {
-                acc + self.force.between(body, &n)
-            });
+impl BHField {
+    pub fn new() -> BHField {
+        Self::default()
+    }
+
+    /// Creates a field that keeps its tree between calls.
+    pub fn incremental() -> BHField {
+        BHField { incremental: true, ..Self::default() }
+    }
+
+    /// Walks the tree once per group of at most `size` nearby bodies.
+    pub fn with_groups(self, size: usize) -> BHField {
+        BHField { group_size: Some(size), ..self }
+    }
+
+    /// Sets the opening parameter of the tree, see `BHTree::with_theta`.
+    pub fn with_theta(self, theta: Float) -> BHField {
+        BHField { theta, ..self }
+    }
+
+    /// Sets the number of bodies per leaf of the tree, see
+    /// `BHTree::with_leaf_size`.
+    pub fn with_leaf_size(self, size: usize) -> BHField {
+        assert!(size > 0, "Leaves must hold at least one body.");
+        BHField { leaf_size: size, ..self }
+    }
 
-            result.push(f);
+    /// Returns the number of interactions of each body in the last call to
+    /// `forces`, or an empty list if there was none.
+    pub fn interactions(&self) -> Vec<usize> {
+        self.interactions.lock().expect("Interactions are poisoned.").clone()
+    }
+
+    /// Returns a summary of the tree used in the last call to `forces`, if
+    /// there was one.
+    pub fn stats(&self) -> Option<TreeStats> {
+        let state = self.state.lock().expect("Tree state is poisoned.");
+        state.as_ref().map(|(tree, _)| tree.stats())
+    }
+
+    /// Calls `f` with a tree of the given bodies and their morton order. An
+    /// incremental field refits its last tree, the others build a new one.
+    fn with_tree<T, F>(&self, bodies: &[Body], f: F) -> T where F: FnOnce(&BHTree, &[usize]) -> T {
+        let mut state = self.state.lock().expect("Tree state is poisoned.");
+
+        let refitted = match state.as_mut() {
+            Some((tree, _)) if self.incremental => tree.refit(bodies),
+            _ => false,
         };
 
+        if !refitted {
+            let keys = morton::sorted_keys(&self.space, bodies);
+            let tree = BHTree::new(self.space.clone())
+                .with_theta(self.theta)
+                .with_leaf_size(self.leaf_size)
+                .build(bodies, &keys);
+            *state = Some((tree, morton::order(&keys, bodies.len())));
+        }
+
+        let (tree, order) = state.as_ref().expect("Expected a tree");
+        f(tree, order)
+    }
+
+    /// Returns the force on each body, walking the tree once per body.
+    fn single_forces(&self, tree: &BHTree, order: &[usize], bodies: &[Body]) -> (Vec<Vector>, Vec<usize>) {
+        // neighbouring bodies visit mostly the same nodes, so the bodies are
+        // copied into morton order and walked from there. This keeps both
+        // the bodies and the tree warm in the cache.
+        let sorted: Vec<Body> = order.iter().map(|idx| bodies[*idx].clone()).collect();
+        let forces: Vec<(Vector, usize)> = sorted.par_iter().map(|body| {
+            let mut total = VectorSum::new();
+            let mut count = 0;
+            tree.walk(body, |mass, position| {
+                total += self.force.towards(body, mass, position);
+                count += 1;
+            });
+            (total.value(), count)
+        }).collect();
+
+        // scatter the results back into the original order
+        let mut result = vec![Vector::zero(); bodies.len()];
+        let mut interactions = vec![0; bodies.len()];
+        for (idx, (force, count)) in order.iter().zip(forces) {
+            result[*idx] = force;
+            interactions[*idx] = count;
+        }
+        (result, interactions)
+    }
+
+    /// Returns the force on each body, walking the tree once per group.
+    fn grouped_forces(&self, tree: &BHTree, bodies: &[Body], size: usize) -> (Vec<Vector>, Vec<usize>) {
+        let groups = tree.groups(size);
+        let forces: Vec<(Vec<Vector>, usize)> = groups.par_iter().map_init(Vec::new, |list, group| {
+            list.clear();
+            tree.walk_group(group, |mass, position| list.push((mass, position.clone())));
+
+            let forces = group.bodies.iter().map(|idx| {
+                let body = &bodies[*idx];
+                let mut total = VectorSum::new();
+                for (mass, position) in list.iter() {
+                    total += self.force.towards(body, *mass, position);
+                }
+                for other in group.bodies.iter() {
+                    total += self.force.between(body, &bodies[*other]);
+                }
+                total.value()
+            }).collect();
+
+            (forces, list.len() + group.bodies.len() - 1)
+        }).collect();
+
+        let mut result = vec![Vector::zero(); bodies.len()];
+        let mut interactions = vec![0; bodies.len()];
+        let mut grouped = vec![false; bodies.len()];
+        for (group, (forces, count)) in groups.iter().zip(forces) {
+            for (idx, force) in group.bodies.iter().zip(forces) {
+                result[*idx] = force;
+                interactions[*idx] = count;
+                grouped[*idx] = true;
+            }
+        }
+
+        // bodies outside the space of the tree are not grouped
+        for (idx, body) in bodies.iter().enumerate().filter(|(idx, _)| !grouped[*idx]) {
+            let mut total = VectorSum::new();
+            tree.walk(body, |mass, position| {
+                total += self.force.towards(body, mass, position);
+                interactions[idx] += 1;
+            });
+            result[idx] = total.value();
+        }
+
+        (result, interactions)
+    }
+}
+
+// TreePMField ///////////////////////////////////////////////////////////////
+//
+// Hybrid gravitation calculation. Long range forces are obtained from a
+// particle mesh, while the remaining short range forces are obtained from a
+// Barnes-Hut tree. Virtual bodies further away than the cutoff contribute
+// a negligible short range force and are ignored.
+
+pub struct TreePMField {
+    space: Square,
+    force: Gravity,
+    mesh: Mesh,
+    cutoff: Float,
+    theta: Float,
+}
+
+impl Field for TreePMField {
+    fn forces(&self, bodies: &[Body]) -> Vec<Vector> {
+        let mut result = self.mesh.forces(bodies);
+        let tree = BHTree::from_bodies(self.space.clone(), bodies).with_theta(self.theta);
+        let split = self.mesh.split();
+
+        bodies.par_iter().zip(result.par_iter_mut()).for_each(|(body, force)| {
+            let mut total = VectorSum::new();
+            total += force.clone();
+            tree.walk(body, |mass, position| {
+                let distance = body.position.distance_to(position);
+                if distance < self.cutoff {
+                    let force = self.force.towards(body, mass, position);
+                    total += &force * short_range_factor(distance, split);
+                }
+            });
+            *force = total.value();
+        });
+
+        result
+    }
+
+    fn potentials(&self, bodies: &[Body]) -> Vec<Float> {
+        let mut result = self.mesh.potentials(bodies);
+        let tree = BHTree::from_bodies(self.space.clone(), bodies).with_theta(self.theta);
+        let split = self.mesh.split();
+
+        bodies.par_iter().zip(result.par_iter_mut()).for_each(|(body, potential)| {
+            let mut total = Sum::new();
+            total += *potential;
+            tree.walk(body, |mass, position| {
+                let distance = body.position.distance_to(position);
+                if distance < self.cutoff {
+                    let factor = short_range_potential_factor(distance, split);
+                    total += self.force.potential(&body.position, mass, position) * factor;
+                }
+            });
+            *potential = total.value();
+        });
+
+        result
+    }
+
+    fn sample(&self, bodies: &[Body], points: &[Point]) -> Vec<Sample> {
+        let mut result = self.mesh.sample(bodies, points);
+        let tree = BHTree::from_bodies(self.space.clone(), bodies).with_theta(self.theta);
+        let split = self.mesh.split();
+
+        points.par_iter().zip(result.par_iter_mut()).for_each(|(point, sample)| {
+            let mut acceleration = VectorSum::new();
+            let mut potential = Sum::new();
+            acceleration += sample.acceleration.clone();
+            potential += sample.potential;
+            tree.walk_point(point, |mass, position| {
+                let distance = point.distance_to(position);
+                if distance < self.cutoff {
+                    let force = self.force.acceleration(point, mass, position);
+                    acceleration += &force * short_range_factor(distance, split);
+                    let factor = short_range_potential_factor(distance, split);
+                    potential += self.force.potential(point, mass, position) * factor;
+                }
+            });
+            *sample = Sample { acceleration: acceleration.value(), potential: potential.value() };
+        });
+
         result
     }
 }
 
-impl BHField {
-    pub fn new() -> BHField {
-        BHField {
-            space: Square::new(-2048.0, -2048.0, 12),
-            force: Gravity::new(1.0, 4.0),
+impl Default for TreePMField {
+    fn default() -> Self {
+        TreePMField::new(256, 1.25)
+    }
+}
+
+impl TreePMField {
+    /// Creates a field with a mesh of the given number of cells along each
+    /// axis. The split scale is given as a multiple of the cell size.
+    pub fn new(cells: usize, split: Float) -> TreePMField {
+        let space = Square::new(-2048.0, -2048.0, 12);
+        let cell_size = space.size as Float / cells as Float;
+        let mesh = Mesh::new(space.clone(), cells, split * cell_size, 1.0);
+
+        TreePMField {
+            cutoff: 6.0 * mesh.split(),
+            space,
+            force: Gravity::new(1.0, MIN_DISTANCE),
+            mesh,
+            theta: DEFAULT_THETA,
+        }
+    }
+
+    /// Sets the opening parameter of the tree, see `BHTree::with_theta`.
+    pub fn with_theta(self, theta: Float) -> TreePMField {
+        TreePMField { theta, ..self }
+    }
+}
+
+// Tests /////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use std::slice;
+
+    use crate::geometry::{Point, Vector};
+
+    use super::*;
+
+    fn new_body(mass: Float, x: Float, y: Float) -> Body {
+        Body::new(mass, Point::new(x, y), Vector::zero())
+    }
+
+    fn spiral(n: usize) -> Vec<Body> {
+        (0..n).map(|i| {
+            let angle = i as Float * 0.7;
+            let radius = 5.0 + 3.0 * i as Float;
+            new_body(1.0 + (i % 4) as Float, radius * angle.cos(), radius * angle.sin())
+        }).collect()
+    }
+
+    /// Computes the forces on a pool with the given number of threads, as
+    /// bytes so that they are compared bit for bit.
+    fn forces_with_threads(field: &dyn Field, bodies: &[Body], threads: usize) -> Vec<Vec<u8>> {
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(threads).build().unwrap();
+        pool.install(|| field.forces(bodies))
+            .iter()
+            .map(|f| [f.dx.to_ne_bytes(), f.dy.to_ne_bytes()].concat())
+            .collect()
+    }
+
+    #[test]
+    fn fields_are_deterministic_across_thread_counts() {
+        // given
+        let bodies = spiral(300);
+        let fields: Vec<Box<dyn Field>> = vec![
+            Box::from(BruteForceField::new()),
+            Box::from(BHField::new()),
+            Box::from(BHField::new().with_groups(8)),
+            Box::from(TreePMField::new(32, 1.25)),
+        ];
+
+        for field in fields.iter() {
+            // when
+            let expected = forces_with_threads(field.as_ref(), &bodies, 1);
+
+            // then
+            for threads in [2, 3, 8].iter() {
+                assert_eq!(expected, forces_with_threads(field.as_ref(), &bodies, *threads));
+            }
+        }
+    }
+
+    #[test]
+    fn incremental_bh_field_matches_rebuilt_tree() {
+        // given
+        let mut bodies = spiral(300);
+        let sut = BHField::incremental();
+        let expected = BHField::new();
+
+        for _ in 0..5 {
+            // when
+            let result = sut.forces(&bodies);
+
+            // then
+            for (force, expected) in result.iter().zip(expected.forces(&bodies).iter()) {
+                let error = Vector::difference(&Point::from(force.clone()),
+                                               &Point::from(expected.clone()));
+                assert!(error.magnitude() <= 1e-3 * expected.magnitude(),
+                        "Expected {:?}, got {:?}", expected, force);
+            }
+
+            for (body, force) in bodies.iter_mut().zip(result.iter()) {
+                body.apply_force(force);
+                body.apply_velocity();
+            }
+        }
+    }
+
+    #[test]
+    fn treepm_field_approximates_brute_force() {
+        // given a close pair and distant bodies
+        let bodies = vec![
+            new_body(3.0, -600.0, -500.0),
+            new_body(1.0, 700.0, -100.0),
+            new_body(2.0, 50.0, 650.0),
+            new_body(4.0, -30.0, 20.0),
+            new_body(1.5, 10.0, 60.0),
+        ];
+
+        let sut = TreePMField::new(64, 1.25);
+
+        // when
+        let result = sut.forces(&bodies);
+        let expected = BruteForceField::new().forces(&bodies);
+
+        // then
+        for (force, expected) in result.iter().zip(expected.iter()) {
+            let error = Vector::difference(&Point::from(force.clone()),
+                                           &Point::from(expected.clone()));
+            assert!(error.magnitude() <= 0.02 * expected.magnitude(),
+                    "Expected {:?}, got {:?}", expected, force);
+        }
+    }
+
+    #[test]
+    fn treepm_field_uses_theta() {
+        // given
+        let bodies = spiral(100);
+
+        // when
+        let default = TreePMField::new(64, 1.25).forces(&bodies);
+        let exact = TreePMField::new(64, 1.25).with_theta(0.0).forces(&bodies);
+
+        // then the short range forces are no longer approximated
+        assert_ne!(default, exact);
+    }
+
+    /// Returns the total error of the given forces relative to the total
+    /// expected force.
+    fn relative_error(forces: &[Vector], expected: &[Vector]) -> Float {
+        let error: Float = forces.iter().zip(expected.iter()).map(|(force, expected)| {
+            Vector::difference(&Point::from(force.clone()), &Point::from(expected.clone()))
+                .magnitude()
+        }).sum();
+        error / expected.iter().map(|f| f.magnitude()).sum::<Float>()
+    }
+
+    #[test]
+    fn bh_field_counts_interactions() {
+        // given
+        let bodies = spiral(200);
+        let fields = vec![
+            BHField::new().with_theta(0.0),
+            BHField::new().with_theta(0.0).with_groups(16),
+        ];
+
+        for sut in fields {
+            assert!(sut.interactions().is_empty());
+            assert_eq!(None, sut.stats());
+
+            // when
+            sut.forces(&bodies);
+
+            // then each body interacts with every other body
+            assert_eq!(vec![bodies.len() - 1; bodies.len()], sut.interactions());
+            assert_eq!(bodies.len(), sut.stats().unwrap().leaves);
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn bh_field_returns_forces_in_order_of_bodies() {
+        // given
+        let bodies = spiral(100);
+        let reversed: Vec<Body> = bodies.iter().rev().cloned().collect();
+
+        // when
+        let mut result = BHField::new().forces(&reversed);
+        result.reverse();
+
+        // then
+        assert_eq!(BHField::new().forces(&bodies), result);
+    }
+
+    #[test]
+    fn grouped_bh_field_is_at_least_as_accurate() {
+        // given
+        let bodies = spiral(300);
+        let expected = BruteForceField::new().forces(&bodies);
+
+        // when
+        let grouped = BHField::new().with_groups(16).forces(&bodies);
+        let single = BHField::new().forces(&bodies);
+
+        // then
+        assert!(relative_error(&grouped, &expected) <= relative_error(&single, &expected));
+    }
+
+    #[test]
+    fn grouped_bh_field_matches_single_walks_on_lopsided_bodies() {
+        // given light bodies far from the center of mass of the root
+        let bodies = vec![
+            new_body(1.0, -2040.0, -2040.0),
+            new_body(1.0, -2038.0, -2040.0),
+            new_body(100.0, 2000.0, 2000.0),
+            new_body(100.0, 2010.0, 2000.0),
+        ];
+
+        // when
+        let grouped = BHField::new().with_groups(1).forces(&bodies);
+        let single = BHField::new().forces(&bodies);
+
+        // then the light bodies are not counted twice
+        assert!(relative_error(&grouped[..2], &single[..2]) < 1e-5,
+                "Expected {:?}, got {:?}", &single[..2], &grouped[..2]);
+    }
+
+    #[test]
+    fn single_group_bh_field_matches_brute_force() {
+        // given
+        let bodies = spiral(100);
+        let sut = BHField::new().with_groups(100);
+
+        // when
+        let result = sut.forces(&bodies);
+
+        // then all bodies interact directly
+        let expected = BruteForceField::new().forces(&bodies);
+        assert!(relative_error(&result, &expected) < 1e-5);
+    }
+
+    #[test]
+    #[should_panic(expected = "Leaves must hold at least one body.")]
+    fn bh_field_rejects_empty_leaves() {
+        // when
+        let _ = BHField::new().with_leaf_size(0);
+    }
+
+    #[test]
+    fn bh_field_with_single_leaf_matches_brute_force() {
+        // given
+        let bodies = spiral(100);
+        let sut = BHField::new().with_leaf_size(100);
+
+        // when
+        let result = sut.forces(&bodies);
+
+        // then all bodies share the root and interact directly
+        let expected = BruteForceField::new().forces(&bodies);
+        assert!(relative_error(&result, &expected) < 1e-5);
+        assert_eq!(1, sut.stats().unwrap().nodes);
+        assert_eq!(vec![bodies.len() - 1; bodies.len()], sut.interactions());
+    }
+
+    #[test]
+    fn bh_field_with_leaf_size_skips_body_far_from_its_leaf() {
+        // given a leaf whose center of mass is far from one of its bodies
+        let bodies = vec![
+            new_body(1.0, -2040.0, -2040.0),
+            new_body(100.0, 2000.0, 2000.0),
+            new_body(100.0, 2010.0, 2000.0),
+        ];
+        let sut = BHField::new().with_leaf_size(4);
+
+        // when
+        let result = sut.forces(&bodies);
+
+        // then the light body doesn't attract itself
+        let expected = BruteForceField::new().forces(&bodies);
+        assert!(relative_error(&result[..1], &expected[..1]) < 1e-5,
+                "Expected {:?}, got {:?}", expected[0], result[0]);
+    }
+
+    #[test]
+    fn brute_force_field_calculates_potentials() {
+        // given
+        let bodies = vec![
+            new_body(2.0, 0.0, 0.0),
+            new_body(1.0, 6.0, 8.0),
+            new_body(3.0, 0.0, 10.0),
+        ];
+
+        // when
+        let result = BruteForceField::new().potentials(&bodies);
+
+        // then
+        assert_close!(-1.0 / 10.0 - 3.0 / 10.0, result[0]);
+        assert_close!(-2.0 / 10.0 - 3.0 / Float::sqrt(40.0), result[1]);
+        assert_close!(-2.0 / 10.0 - 1.0 / Float::sqrt(40.0), result[2]);
+    }
+
+    #[test]
+    fn fields_sample_points_like_unit_bodies() {
+        // given
+        let bodies = spiral(50);
+        let points = vec![Point::new(12.0, -7.0), Point::new(-80.0, 40.0), bodies[3].position.clone()];
+        let fields: Vec<Box<dyn Field>> = vec![
+            Box::from(BruteForceField::new()),
+            Box::from(BHField::new().with_theta(0.0)),
+        ];
+
+        for sut in fields.iter() {
+            // when
+            let result = sut.sample(&bodies, &points);
+
+            // then
+            for (point, sample) in points.iter().zip(result.iter()) {
+                let mut probed = vec![new_body(1.0, point.x, point.y)];
+                probed.extend(bodies.iter().cloned());
+                let force = BruteForceField::new().forces(&probed)[0].clone();
+                let potential = BruteForceField::new().potentials(&probed)[0];
+
+                assert!(relative_error(slice::from_ref(&sample.acceleration), &[force]) < 1e-4);
+                assert!((sample.potential - potential).abs() < 1e-4 * potential.abs());
+            }
+        }
+    }
+
+    #[test]
+    fn bh_field_potentials_approximate_brute_force() {
+        // given
+        let bodies = spiral(300);
+        let expected = BruteForceField::new().potentials(&bodies);
+
+        let fields = [
+            BHField::new().with_theta(0.0),
+            BHField::new().with_theta(0.5),
+            BHField::incremental().with_theta(0.5).with_leaf_size(8),
+        ];
+
+        for sut in fields.iter() {
+            // when
+            let result = sut.potentials(&bodies);
+
+            // then
+            for (potential, expected) in result.iter().zip(expected.iter()) {
+                assert!((potential - expected).abs() < 0.01 * expected.abs(),
+                        "Expected {}, got {}", expected, potential);
+            }
+        }
+    }
+
+    #[test]
+    fn treepm_field_potentials_approximate_brute_force() {
+        // given a close pair and distant bodies
+        let bodies = vec![
+            new_body(3.0, -600.0, -500.0),
+            new_body(1.0, 700.0, -100.0),
+            new_body(2.0, 50.0, 650.0),
+            new_body(4.0, -30.0, 20.0),
+            new_body(1.5, 10.0, 60.0),
+        ];
+        let sut = TreePMField::new(64, 1.25);
+
+        // when
+        let result = sut.potentials(&bodies);
+        let samples = sut.sample(&bodies, &[Point::new(-20.0, 40.0)]);
+
+        // then
+        let expected = BruteForceField::new().potentials(&bodies);
+        for (potential, expected) in result.iter().zip(expected.iter()) {
+            assert!((potential - expected).abs() <= 0.02 * expected.abs(),
+                    "Expected {}, got {}", expected, potential);
+        }
+
+        let expected = BruteForceField::new().sample(&bodies, &[Point::new(-20.0, 40.0)]);
+        assert!((samples[0].potential - expected[0].potential).abs() <= 0.02 * expected[0].potential.abs());
+        let error = relative_error(slice::from_ref(&samples[0].acceleration),
+                                   slice::from_ref(&expected[0].acceleration));
+        assert!(error < 0.02);
+    }
+}
@@ -1,18 +1,34 @@
+use rayon::prelude::*;
+
+use crate::geometry::three::{Point3, Vector3};
 use crate::geometry::{Point, Vector};
+use crate::Float;
 
+use super::field::{Field, Sample};
+use super::three::Body3;
 use super::Body;
 
 // Gravity ///////////////////////////////////////////////////////////////////
 //
 // Newton's Law of Universal Gravitation.
+//
+// Below the minimum distance the magnitude of the force is held constant, to
+// avoid singularities between close bodies. The potential is softened to
+// match, so that its gradient is the force at any distance: it is -g * m / r
+// beyond the minimum distance and continues linearly below it, reaching
+// -2 * g * m / min_dist at zero distance.
+
+/// The minimum distance of the gravity of the fields, which acts as their
+/// softening length.
+pub const MIN_DISTANCE: Float = 4.0;
 
 pub struct Gravity {
-    g: f32,
-    min_dist: f32,
+    g: Float,
+    min_dist: Float,
 }
 
 impl Gravity {
-    pub fn new(g: f32, min_dist: f32) -> Gravity {
+    pub fn new(g: Float, min_dist: Float) -> Gravity {
         if min_dist <= 0.0 {
             panic!("The minimum gravitational distance \
             must be greater than 0. Got {}", min_dist);
@@ -24,17 +40,65 @@ impl Gravity {
     }
 
     pub fn between(&self, b1: &Body, b2: &Body) -> Vector {
+        self.towards(b1, b2.mass.value(), &b2.position)
+    }
+
+    /// Returns the magnitude of the force between two unit masses divided by
+    /// their distance, given the squared distance. Multiplying by the masses
+    /// and the difference of the positions yields the force vector. Returns
+    /// zero for bodies that occupy the same space.
+    #[inline]
+    pub fn strength(&self, distance_squared: Float) -> Float {
+        let distance = distance_squared.sqrt();
+        let clamped = distance.max(self.min_dist);
+
+        // evaluated unconditionally so that callers can be vectorised
+        let strength = self.g / (distance * clamped * clamped);
+        if distance_squared > 0.0 { strength } else { 0.0 }
+    }
+
+    /// Returns the force exerted on the body by a point mass.
+    pub fn towards(&self, body: &Body, mass: Float, point: &Point) -> Vector {
         // Force is undefined for two bodies that occupy the same space.
-        if b1.position == b2.position {
+        if body.position == *point {
             return Vector::zero();
         }
 
-        let difference = Vector::difference(&b2.position, &b1.position);
+        let difference = Vector::difference(point, &body.position);
         let distance = difference.magnitude().max(self.min_dist);
-        let force = (self.g * b1.mass.value() * b2.mass.value()) / (distance * distance);
+        let force = (self.g * body.mass.value() * mass) / (distance * distance);
         let direction = difference.normalized().unwrap_or_else(Vector::zero);
         &direction * force
     }
+
+    /// Returns the acceleration at the given point due to a point mass, that
+    /// is the force on a unit mass. Returns zero at the point mass itself.
+    pub fn acceleration(&self, point: &Point, mass: Float, source: &Point) -> Vector {
+        let difference = Vector::difference(source, point);
+        &difference * (self.strength(&difference * &difference) * mass)
+    }
+
+    /// Returns the potential at the given point due to a point mass, that is
+    /// the potential energy of a unit mass.
+    pub fn potential(&self, point: &Point, mass: Float, source: &Point) -> Float {
+        let distance = point.distance_to(source);
+        if distance >= self.min_dist {
+            -self.g * mass / distance
+        } else {
+            -self.g * mass * (2.0 * self.min_dist - distance) / (self.min_dist * self.min_dist)
+        }
+    }
+
+    pub fn between3(&self, b1: &Body3, b2: &Body3) -> Vector3 {
+        self.towards3(b1, b2.mass.value(), &b2.position)
+    }
+
+    /// Returns the force exerted on the body by a point mass in 3D space.
+    pub fn towards3(&self, body: &Body3, mass: Float, point: &Point3) -> Vector3 {
+        let difference = Vector3::difference(point, &body.position);
+        let strength = self.strength(&difference * &difference);
+        &difference * (strength * body.mass.value() * mass)
+    }
 }
 
 // Attractor /////////////////////////////////////////////////////////////////
@@ -47,7 +111,7 @@ pub struct Attractor {
 }
 
 impl Attractor {
-    pub fn new(mass: f32, point: Point, g: f32, min_dist: f32) -> Attractor {
+    pub fn new(mass: Float, point: Point, g: Float, min_dist: Float) -> Attractor {
         Attractor {
             body: Body::new(mass, point, Vector::zero()),
             gravity: Gravity::new(g, min_dist),
@@ -57,15 +121,44 @@ impl Attractor {
     pub fn force(&self, body: &Body) -> Vector {
         self.gravity.between(body, &self.body)
     }
+
+    /// Returns the potential of the attractor at the given point.
+    pub fn potential(&self, point: &Point) -> Float {
+        self.gravity.potential(point, self.body.mass.value(), &self.body.position)
+    }
+
+    /// Returns the acceleration towards the attractor at the given point.
+    pub fn acceleration(&self, point: &Point) -> Vector {
+        self.gravity.acceleration(point, self.body.mass.value(), &self.body.position)
+    }
+}
+
+/// An attractor is an external field, the bodies don't act on each other.
+impl Field for Attractor {
+    fn forces(&self, bodies: &[Body]) -> Vec<Vector> {
+        bodies.par_iter().map(|body| self.force(body)).collect()
+    }
+
+    fn potentials(&self, bodies: &[Body]) -> Vec<Float> {
+        bodies.par_iter().map(|body| self.potential(&body.position)).collect()
+    }
+
+    fn sample(&self, _: &[Body], points: &[Point]) -> Vec<Sample> {
+        points.par_iter().map(|point| Sample {
+            acceleration: self.acceleration(point),
+            potential: self.potential(point),
+        }).collect()
+    }
 }
 
 // Tests /////////////////////////////////////////////////////////////////////
 
 #[cfg(test)]
 mod tests {
+    use crate::geometry::three::{Point3, Vector3};
     use crate::geometry::{Point, Vector};
 
-    use super::{Attractor, Body, Gravity};
+    use super::{Attractor, Body, Body3, Field, Gravity};
 
     #[test]
     #[should_panic(expected = "The minimum gravitational distance must be greater than 0.")]
@@ -95,7 +188,55 @@ mod tests {
         let result = sut.between(&b1, &b2);
 
         // then
-        assert_eq!(result, Vector { dx: -0.1130488514, dy: -0.0502439339});
+        assert_eq!(result, Vector { dx: -0.113_048_85, dy: -0.050_243_933});
+    }
+
+    #[test]
+    fn gravity_towards_point_mass_matches_body() {
+        // given
+        let sut = Gravity::new(1.5, 4.0);
+        let b1 = Body::new(1.0, Point { x: 1.0, y: 2.0}, Vector::zero());
+        let point = Point { x: -3.5, y: 0.0};
+
+        // when
+        let result = sut.towards(&b1, 2.0, &point);
+
+        // then
+        assert_eq!(result, sut.between(&b1, &Body::new(2.0, point, Vector::zero())));
+    }
+
+    #[test]
+    fn gravity_strength_scales_difference() {
+        // given
+        let sut = Gravity::new(1.5, 4.0);
+        let b1 = Body::new(1.0, Point { x: 1.0, y: 2.0}, Vector::zero());
+        let b2 = Body::new(2.0, Point { x: -3.5, y: 0.0}, Vector::zero());
+        let expected = sut.between(&b1, &b2);
+
+        // when
+        let factor = 2.0 * sut.strength(4.5 * 4.5 + 2.0 * 2.0);
+
+        // then
+        assert!((expected.dx - factor * -4.5).abs() < 1e-6);
+        assert!((expected.dy - factor * -2.0).abs() < 1e-6);
+        assert_eq!(0.0, sut.strength(0.0));
+    }
+
+    #[test]
+    fn gravity_in_plane_matches_2d() {
+        // given
+        let sut = Gravity::new(1.5, 4.0);
+        let b1 = Body3::new(1.0, Point3::new(1.0, 2.0, 0.0), Vector3::zero());
+        let b2 = Body3::new(2.0, Point3::new(-3.5, 0.0, 0.0), Vector3::zero());
+
+        // when
+        let result = sut.between3(&b1, &b2);
+
+        // then
+        assert_close!(-0.113_048_85, result.dx);
+        assert_close!(-0.050_243_933, result.dy);
+        assert_eq!(0.0, result.dz);
+        assert_eq!(Vector3::zero(), sut.between3(&b1, &b1));
     }
 
     #[test]
@@ -121,7 +262,7 @@ mod tests {
         let result = sut.between(&b1, &b2);
 
         // then
-        let result_if_dist_was_4 = Vector { dx: 0.1677050983, dy: 0.0838525491};
+        let result_if_dist_was_4 = Vector { dx: 0.167_705_1, dy: 0.083_852_55};
         assert_eq!(result, result_if_dist_was_4);
     }
 
@@ -169,6 +310,78 @@ mod tests {
         let result = sut.force(&body);
 
         // then
-        assert_eq!(result, Vector { dx: -78.17293649, dy: -156.345873});
+        assert_close!(-78.172_935, result.dx);
+        assert_close!(-156.345_87, result.dy);
+    }
+
+    #[test]
+    fn gravity_calculates_potential() {
+        // given
+        let sut = Gravity::new(1.5, 4.0);
+        let source = Point::new(-3.5, 0.0);
+
+        // then beyond the minimum distance
+        assert_close!(-0.6, sut.potential(&Point::new(1.5, 0.0), 2.0, &source));
+
+        // and below it, continuously
+        assert_close!(-0.75, sut.potential(&Point::new(0.5, 0.0), 2.0, &source));
+        assert_close!(-1.125, sut.potential(&Point::new(-1.5, 0.0), 2.0, &source));
+        assert_close!(-1.5, sut.potential(&source, 2.0, &source));
+    }
+
+    #[test]
+    fn gravity_potential_gradient_is_acceleration() {
+        // given
+        let sut = Gravity::new(1.5, 4.0);
+        let source = Point::new(-3.5, 0.0);
+        let h = 1e-2;
+
+        for point in [Point::new(1.0, 2.0), Point::new(-2.0, 1.0)].iter() {
+            // when
+            let dx = sut.potential(&Point::new(point.x + h, point.y), 2.0, &source)
+                - sut.potential(&Point::new(point.x - h, point.y), 2.0, &source);
+            let dy = sut.potential(&Point::new(point.x, point.y + h), 2.0, &source)
+                - sut.potential(&Point::new(point.x, point.y - h), 2.0, &source);
+
+            // then
+            let expected = sut.acceleration(point, 2.0, &source);
+            assert!((expected.dx + dx / (2.0 * h)).abs() < 1e-3);
+            assert!((expected.dy + dy / (2.0 * h)).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn gravity_acceleration_is_force_on_unit_mass() {
+        // given
+        let sut = Gravity::new(1.5, 4.0);
+        let body = Body::new(1.0, Point::new(1.0, 2.0), Vector::zero());
+        let source = Point::new(-3.5, 0.0);
+
+        // when
+        let result = sut.acceleration(&body.position, 2.0, &source);
+
+        // then
+        let expected = sut.towards(&body, 2.0, &source);
+        assert_close!(expected.dx, result.dx);
+        assert_close!(expected.dy, result.dy);
+        assert_eq!(Vector::zero(), sut.acceleration(&source, 2.0, &source));
+    }
+
+    #[test]
+    fn attractor_is_field() {
+        // given
+        let sut = Attractor::new(100.0, Point::zero(), 2.3, 1.0);
+        let bodies = vec![Body::new(2.0, Point::new(3.0, 4.0), Vector::zero())];
+
+        // when
+        let forces = sut.forces(&bodies);
+        let potentials = sut.potentials(&bodies);
+        let samples = sut.sample(&bodies, &[Point::new(3.0, 4.0)]);
+
+        // then
+        assert_close!(-46.0, potentials[0]);
+        assert_close!(-46.0, samples[0].potential);
+        assert_close!(forces[0].dx / 2.0, samples[0].acceleration.dx);
+        assert_close!(forces[0].dy / 2.0, samples[0].acceleration.dy);
     }
 }
@@ -0,0 +1,164 @@
+use std::ops::{Add, Mul, Sub};
+
+use crate::consts::PI;
+use crate::Float;
+
+// Complex ///////////////////////////////////////////////////////////////////
+//
+// Minimal complex number, just enough to support the fourier transform.
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Complex {
+    pub re: Float,
+    pub im: Float,
+}
+
+impl Add for Complex {
+    type Output = Complex;
+
+    fn add(self, rhs: Complex) -> Self::Output {
+        Complex::new(self.re + rhs.re, self.im + rhs.im)
+    }
+}
+
+impl Sub for Complex {
+    type Output = Complex;
+
+    fn sub(self, rhs: Complex) -> Self::Output {
+        Complex::new(self.re - rhs.re, self.im - rhs.im)
+    }
+}
+
+impl Mul for Complex {
+    type Output = Complex;
+
+    fn mul(self, rhs: Complex) -> Self::Output {
+        Complex::new(
+            self.re * rhs.re - self.im * rhs.im,
+            self.re * rhs.im + self.im * rhs.re,
+        )
+    }
+}
+
+impl Complex {
+    pub fn new(re: Float, im: Float) -> Complex {
+        Complex { re, im }
+    }
+
+    pub fn zero() -> Complex {
+        Complex::new(0.0, 0.0)
+    }
+}
+
+// Fourier Transform /////////////////////////////////////////////////////////
+//
+// In place iterative radix-2 Cooley-Tukey transform. The inverse transform
+// is not normalized, the caller is responsible for dividing by the number
+// of samples.
+
+/// Transforms the given samples in place. Panics if the number of samples
+/// is not a power of two.
+pub fn fft(data: &mut [Complex], inverse: bool) {
+    let n = data.len();
+    assert!(n.is_power_of_two(), "FFT requires a power of two length. Got {}", n);
+
+    // bit reversal permutation
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j { data.swap(i, j); }
+    }
+
+    // butterflies
+    let sign = if inverse { 1.0 } else { -1.0 };
+    let mut len = 2;
+    while len <= n {
+        let angle = sign * 2.0 * PI / len as Float;
+        for start in (0..n).step_by(len) {
+            for k in 0..len / 2 {
+                let (sin, cos) = (angle * k as Float).sin_cos();
+                let w = Complex::new(cos, sin);
+                let a = data[start + k];
+                let b = data[start + k + len / 2] * w;
+                data[start + k] = a + b;
+                data[start + k + len / 2] = a - b;
+            }
+        }
+        len <<= 1;
+    }
+}
+
+/// Transforms the given row major square grid of the given width in place.
+pub fn fft_2d(data: &mut [Complex], width: usize, inverse: bool) {
+    debug_assert_eq!(data.len(), width * width);
+
+    for row in data.chunks_mut(width) {
+        fft(row, inverse);
+    }
+
+    let mut column = vec![Complex::zero(); width];
+    for x in 0..width {
+        for y in 0..width { column[y] = data[y * width + x]; }
+        fft(&mut column, inverse);
+        for y in 0..width { data[y * width + x] = column[y]; }
+    }
+}
+
+// Tests /////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: Complex, b: Complex) {
+        assert!((a.re - b.re).abs() < 1e-4 && (a.im - b.im).abs() < 1e-4,
+                "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn fft_transforms_impulse() {
+        // given
+        let mut data = vec![Complex::zero(); 8];
+        data[0] = Complex::new(1.0, 0.0);
+
+        // when
+        fft(&mut data, false);
+
+        // then
+        for value in data { assert_close(Complex::new(1.0, 0.0), value); }
+    }
+
+    #[test]
+    fn fft_round_trips() {
+        // given
+        let input: Vec<Complex> = (0..16)
+            .map(|i| Complex::new(i as Float * 0.5 - 3.0, (i % 3) as Float))
+            .collect();
+
+        let mut data = input.clone();
+
+        // when
+        fft_2d(&mut data, 4, false);
+        fft_2d(&mut data, 4, true);
+
+        // then
+        for (expected, value) in input.iter().zip(data.iter()) {
+            assert_close(*expected, Complex::new(value.re / 16.0, value.im / 16.0));
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "FFT requires a power of two length.")]
+    fn fft_rejects_length() {
+        // given
+        let mut data = vec![Complex::zero(); 6];
+
+        // when, then
+        fft(&mut data, false);
+    }
+}
@@ -0,0 +1,414 @@
+use rayon::prelude::*;
+
+use crate::consts::PI;
+use crate::geometry::{Point, Square, Vector};
+use crate::Float;
+
+use super::field::Sample;
+use super::Body;
+
+use self::fft::{Complex, fft_2d};
+
+mod fft;
+
+// Mesh //////////////////////////////////////////////////////////////////////
+//
+// A particle mesh solver for the long range component of gravity. The mass
+// of each body is spread over a regular grid of cells (cloud in cell), the
+// grid is convolved with the long range kernel in fourier space, and the
+// resulting potential is differentiated and interpolated back to the
+// bodies.
+//
+// The gravitational kernel -g/r is split by a gaussian of scale `split` into
+// a smooth long range part -g * erf(r / 2s) / r, handled here, and a short
+// range remainder whose force is scaled by `short_range_factor`. Because
+// the long range part is smooth, it is well resolved by the mesh, while the
+// short range part vanishes beyond a few multiples of the split scale.
+//
+// The grid is padded to twice its width so that the convolution is not
+// periodic, which means bodies on opposite edges of the space do not see
+// each other's images.
+
+pub struct Mesh {
+    space: Square,
+    cells: usize,
+    split: Float,
+    g: Float,
+    kernel: Vec<Complex>,
+}
+
+impl Mesh {
+    /// Creates a mesh covering the given space with the given number of cells
+    /// along each axis. The number of cells must be a power of two.
+    pub fn new(space: Square, cells: usize, split: Float, g: Float) -> Mesh {
+        if !cells.is_power_of_two() || cells < 2 {
+            panic!("The number of mesh cells must be a power of two. Got {}", cells);
+        }
+        if split <= 0.0 {
+            panic!("The split scale must be greater than 0. Got {}", split);
+        }
+
+        let mut mesh = Mesh { space, cells, split, g, kernel: vec![] };
+        mesh.kernel = mesh.kernel();
+        mesh
+    }
+
+    /// Returns the width of a single cell.
+    pub fn cell_size(&self) -> Float {
+        self.space.size as Float / self.cells as Float
+    }
+
+    /// Returns the scale at which forces are split between the mesh and the
+    /// short range solver.
+    pub fn split(&self) -> Float {
+        self.split
+    }
+
+    /// Returns the long range force for each of the given bodies. Bodies
+    /// outside the space of the mesh neither exert nor feel any force.
+    pub fn forces(&self, bodies: &[Body]) -> Vec<Vector> {
+        let potential = self.potential(&self.density(bodies));
+        let field = self.gradient(&potential);
+
+        bodies.par_iter().map(|body| {
+            if !self.space.contains(&body.position) {
+                return Vector::zero();
+            }
+
+            let acceleration = self.stencil(&body.position).iter()
+                .fold(Vector::zero(), |acc, &(idx, weight)| {
+                    acc + &field[idx] * weight
+                });
+
+            &acceleration * body.mass.value()
+        }).collect()
+    }
+
+    /// Returns the long range potential at each of the given bodies due to
+    /// the other bodies. Bodies outside the space of the mesh have none.
+    pub fn potentials(&self, bodies: &[Body]) -> Vec<Float> {
+        let potential = self.potential(&self.density(bodies));
+
+        bodies.par_iter().map(|body| {
+            if !self.space.contains(&body.position) {
+                return 0.0;
+            }
+
+            let total = self.interpolate(&potential, &body.position);
+            total - body.mass.value() * self.self_potential(&body.position)
+        }).collect()
+    }
+
+    /// Returns the long range acceleration and potential at each of the
+    /// given points. Points outside the space of the mesh feel neither.
+    pub fn sample(&self, bodies: &[Body], points: &[Point]) -> Vec<Sample> {
+        let potential = self.potential(&self.density(bodies));
+        let field = self.gradient(&potential);
+
+        points.par_iter().map(|point| {
+            if !self.space.contains(point) {
+                return Sample { acceleration: Vector::zero(), potential: 0.0 };
+            }
+
+            let acceleration = self.stencil(point).iter()
+                .fold(Vector::zero(), |acc, &(idx, weight)| {
+                    acc + &field[idx] * weight
+                });
+
+            Sample { acceleration, potential: self.interpolate(&potential, point) }
+        }).collect()
+    }
+
+    /// Returns the grid of masses obtained by spreading each body over its
+    /// four nearest cell centers.
+    fn density(&self, bodies: &[Body]) -> Vec<Float> {
+        let mut density = vec![0.0; self.cells * self.cells];
+
+        for body in bodies.iter().filter(|b| self.space.contains(&b.position)) {
+            for (idx, weight) in self.stencil(&body.position).iter() {
+                density[*idx] += weight * body.mass.value();
+            }
+        }
+
+        density
+    }
+
+    /// Returns the potential at each cell center by convolving the density
+    /// with the long range kernel.
+    fn potential(&self, density: &[Float]) -> Vec<Float> {
+        let n = self.cells;
+        let width = 2 * n;
+        let mut grid = vec![Complex::zero(); width * width];
+
+        for y in 0..n {
+            for x in 0..n {
+                grid[y * width + x].re = density[y * n + x];
+            }
+        }
+
+        fft_2d(&mut grid, width, false);
+        for (value, kernel) in grid.iter_mut().zip(self.kernel.iter()) {
+            *value = *value * *kernel;
+        }
+        fft_2d(&mut grid, width, true);
+
+        let norm = (width * width) as Float;
+        let mut potential = vec![0.0; n * n];
+        for y in 0..n {
+            for x in 0..n {
+                potential[y * n + x] = grid[y * width + x].re / norm;
+            }
+        }
+
+        potential
+    }
+
+    /// Returns the acceleration at each cell center, using central
+    /// differences in the interior and one sided differences at the edges.
+    fn gradient(&self, potential: &[Float]) -> Vec<Vector> {
+        let n = self.cells;
+        let h = self.cell_size();
+        let at = |x: usize, y: usize| potential[y * n + x];
+
+        let derivative = |lo: Float, hi: Float, steps: usize| (hi - lo) / (steps as Float * h);
+
+        let mut field = Vec::with_capacity(n * n);
+        for y in 0..n {
+            for x in 0..n {
+                let (x0, x1) = (x.saturating_sub(1), (x + 1).min(n - 1));
+                let (y0, y1) = (y.saturating_sub(1), (y + 1).min(n - 1));
+                let dx = derivative(at(x0, y), at(x1, y), x1 - x0);
+                let dy = derivative(at(x, y0), at(x, y1), y1 - y0);
+                field.push(Vector::new(-dx, -dy));
+            }
+        }
+
+        field
+    }
+
+    /// Returns the value of the given grid at the given point.
+    fn interpolate(&self, grid: &[Float], point: &Point) -> Float {
+        self.stencil(point).iter().map(|&(idx, weight)| grid[idx] * weight).sum()
+    }
+
+    /// Returns the potential of a unit mass at the given point on itself.
+    /// Its mass is spread over the same cells that the potential is then
+    /// interpolated from, so this is the kernel between each pair of these
+    /// cells, weighted by both of their weights.
+    fn self_potential(&self, point: &Point) -> Float {
+        let stencil = self.stencil(point);
+        let (n, h) = (self.cells, self.cell_size());
+        let mut total = 0.0;
+
+        for &(a, wa) in stencil.iter() {
+            for &(b, wb) in stencil.iter() {
+                let dx = (a % n) as Float - (b % n) as Float;
+                let dy = (a / n) as Float - (b / n) as Float;
+                total += wa * wb * self.long_range_potential(h * (dx * dx + dy * dy).sqrt());
+            }
+        }
+
+        total
+    }
+
+    /// Returns the cloud in cell weights of the four cells surrounding the
+    /// given point. Cells beyond the edge of the mesh are clamped.
+    fn stencil(&self, point: &Point) -> [(usize, Float); 4] {
+        let h = self.cell_size();
+        let max = (self.cells - 1) as Float;
+        let u = ((point.x - self.space.origin.x) / h - 0.5).max(0.0).min(max);
+        let v = ((point.y - self.space.origin.y) / h - 0.5).max(0.0).min(max);
+
+        let (x0, y0) = (u.floor() as usize, v.floor() as usize);
+        let (x1, y1) = ((x0 + 1).min(self.cells - 1), (y0 + 1).min(self.cells - 1));
+        let (tx, ty) = (u - x0 as Float, v - y0 as Float);
+
+        let n = self.cells;
+        [
+            (y0 * n + x0, (1.0 - tx) * (1.0 - ty)),
+            (y0 * n + x1, tx * (1.0 - ty)),
+            (y1 * n + x0, (1.0 - tx) * ty),
+            (y1 * n + x1, tx * ty),
+        ]
+    }
+
+    /// Returns the fourier transform of the long range kernel sampled on the
+    /// padded grid.
+    fn kernel(&self) -> Vec<Complex> {
+        let width = 2 * self.cells;
+        let h = self.cell_size();
+        let mut kernel = vec![Complex::zero(); width * width];
+
+        for y in 0..width {
+            for x in 0..width {
+                // distances wrap around so the kernel is centered at zero
+                let dx = x.min(width - x) as Float * h;
+                let dy = y.min(width - y) as Float * h;
+                let r = (dx * dx + dy * dy).sqrt();
+                kernel[y * width + x].re = self.long_range_potential(r);
+            }
+        }
+
+        fft_2d(&mut kernel, width, false);
+        kernel
+    }
+
+    /// Returns the long range potential at distance r from a unit mass.
+    fn long_range_potential(&self, r: Float) -> Float {
+        let s = 2.0 * self.split;
+        if r == 0.0 {
+            // limit of erf(r / s) / r as r approaches 0
+            -self.g * 2.0 / (s * PI.sqrt())
+        } else {
+            -self.g * erf(r / s) / r
+        }
+    }
+}
+
+// Force Split ///////////////////////////////////////////////////////////////
+
+/// Returns the fraction of the newtonian force between two bodies at the
+/// given distance that is not accounted for by the mesh.
+pub fn short_range_factor(distance: Float, split: Float) -> Float {
+    let u = distance / (2.0 * split);
+    erfc(u) + 2.0 * u / PI.sqrt() * (-u * u).exp()
+}
+
+/// Returns the fraction of the newtonian potential between two bodies at the
+/// given distance that is not accounted for by the mesh.
+pub fn short_range_potential_factor(distance: Float, split: Float) -> Float {
+    erfc(distance / (2.0 * split))
+}
+
+/// Returns the error function of x.
+pub fn erf(x: Float) -> Float {
+    1.0 - erfc(x)
+}
+
+/// Returns the complementary error function of x, using a Chebyshev fit
+/// with a fractional error below 1.2e-7.
+#[allow(clippy::excessive_precision)]
+pub fn erfc(x: Float) -> Float {
+    let z = x.abs();
+    let t = 1.0 / (1.0 + 0.5 * z);
+    let poly = -z * z - 1.265_512_23 + t * (1.000_023_68 + t * (0.374_091_96
+        + t * (0.096_784_18 + t * (-0.186_288_06 + t * (0.278_868_07
+        + t * (-1.135_203_98 + t * (1.488_515_87 + t * (-0.822_152_23
+        + t * 0.170_872_77))))))));
+    let result = t * poly.exp();
+    if x >= 0.0 { result } else { 2.0 - result }
+}
+
+// Tests /////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use crate::physics::force::Gravity;
+
+    use super::*;
+
+    fn new_body(mass: Float, x: Float, y: Float) -> Body {
+        Body::new(mass, Point::new(x, y), Vector::zero())
+    }
+
+    #[test]
+    #[should_panic(expected = "The number of mesh cells must be a power of two.")]
+    fn mesh_with_invalid_cells() {
+        // given
+        Mesh::new(Square::new(0.0, 0.0, 4), 12, 1.0, 1.0);
+    }
+
+    #[test]
+    fn erf_approximates() {
+        // then
+        assert!(erf(0.0).abs() < 1e-6);
+        assert!((erf(0.5) - 0.520_499_9).abs() < 1e-6);
+        assert!((erf(1.0) - 0.842_700_8).abs() < 1e-6);
+        assert!((erf(-1.0) + 0.842_700_8).abs() < 1e-6);
+        assert!((erf(3.0) - 0.999_977_9).abs() < 1e-6);
+    }
+
+    #[test]
+    fn short_range_factor_decays() {
+        // then
+        assert!((short_range_factor(0.0, 2.0) - 1.0).abs() < 1e-6);
+        assert!(short_range_factor(2.0, 2.0) > 0.9);
+        assert!(short_range_factor(12.0, 2.0) < 1e-3);
+    }
+
+    #[test]
+    fn mesh_attracts_distant_bodies() {
+        // given
+        let sut = Mesh::new(Square::new(-256.0, -256.0, 9), 64, 10.0, 1.0);
+        let gravity = Gravity::new(1.0, 4.0);
+        let bodies = vec![new_body(10.0, -100.0, 4.0), new_body(1.0, 100.0, -4.0)];
+
+        // when
+        let result = sut.forces(&bodies);
+
+        // then the long range force dominates at large distances
+        for (i, j) in [(0, 1), (1, 0)].iter() {
+            let expected = gravity.between(&bodies[*i], &bodies[*j]);
+            let error = Vector::difference(&Point::from(result[*i].clone()),
+                                           &Point::from(expected.clone()));
+            assert!(error.magnitude() / expected.magnitude() < 0.02,
+                    "Expected {:?}, got {:?}", expected, result[*i]);
+        }
+    }
+
+    #[test]
+    fn mesh_ignores_bodies_out_of_bounds() {
+        // given
+        let sut = Mesh::new(Square::new(0.0, 0.0, 6), 16, 4.0, 1.0);
+        let bodies = vec![new_body(10.0, 10.0, 10.0), new_body(1.0, 100.0, 10.0)];
+
+        // when
+        let result = sut.forces(&bodies);
+
+        // then
+        assert_eq!(Vector::zero(), result[1]);
+        assert!(result[0].magnitude() < 1e-3);
+    }
+
+    #[test]
+    fn mesh_potential_excludes_own_mass() {
+        // given
+        let sut = Mesh::new(Square::new(-256.0, -256.0, 9), 64, 10.0, 1.0);
+        let gravity = Gravity::new(1.0, 4.0);
+        let bodies = vec![new_body(10.0, -100.0, 4.3), new_body(1.0, 100.0, -4.0)];
+
+        // when
+        let result = sut.potentials(&bodies);
+
+        // then the long range potential dominates at large distances
+        for (i, j) in [(0, 1), (1, 0)].iter() {
+            let (body, other) = (&bodies[*i], &bodies[*j]);
+            let expected = gravity.potential(&body.position, other.mass.value(), &other.position);
+            assert!((result[*i] - expected).abs() / expected.abs() < 0.02,
+                    "Expected {}, got {}", expected, result[*i]);
+        }
+    }
+
+    #[test]
+    fn mesh_samples_points() {
+        // given
+        let sut = Mesh::new(Square::new(-256.0, -256.0, 9), 64, 10.0, 1.0);
+        let gravity = Gravity::new(1.0, 4.0);
+        let bodies = vec![new_body(10.0, -100.0, 4.0)];
+        let point = Point::new(90.0, -20.0);
+
+        // when
+        let result = sut.sample(&bodies, &[point.clone(), Point::new(300.0, 0.0)]);
+
+        // then
+        let expected = gravity.potential(&point, 10.0, &bodies[0].position);
+        assert!((result[0].potential - expected).abs() / expected.abs() < 0.02);
+
+        let expected = gravity.acceleration(&point, 10.0, &bodies[0].position);
+        let error = Vector::difference(&Point::from(result[0].acceleration.clone()),
+                                       &Point::from(expected.clone()));
+        assert!(error.magnitude() / expected.magnitude() < 0.02);
+
+        assert_eq!(0.0, result[1].potential);
+    }
+}
@@ -1,20 +1,27 @@
 use std::cmp::Eq;
 use std::fmt;
+use std::io;
 
 use crate::geometry::{Point, Vector};
 use crate::physics::field::{BHField, Field};
-use crate::util::write::DataWriter;
+use crate::util::sink::{Sink, Snapshot};
+use crate::Float;
 
 pub mod force;
+pub mod accuracy;
 pub mod barneshut;
+pub mod direct;
 pub mod field;
+pub mod mesh;
+pub mod octree;
+pub mod three;
 
 // Mass //////////////////////////////////////////////////////////////////////
 //
 // Simple wrapper type that can only hold a positive floating point value.
 
 #[derive(PartialEq, Copy, Clone)]
-pub struct Mass(f32);
+pub struct Mass(Float);
 
 impl fmt::Display for Mass {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
@@ -28,19 +35,19 @@ impl fmt::Debug for Mass {
     }
 }
 
-impl From<f32> for Mass {
-    fn from(m: f32) -> Self {
+impl From<Float> for Mass {
+    fn from(m: Float) -> Self {
         Mass::new(m)
     }
 }
 
 impl Mass {
-    pub fn new(m: f32) -> Mass {
+    pub fn new(m: Float) -> Mass {
         if m <= 0.0 { panic!("A mass must be greater than 0. Got {}", m); }
         Mass(m)
     }
 
-    pub fn value(self) -> f32 {
+    pub fn value(self) -> Float {
         self.0
     }
 }
@@ -48,11 +55,20 @@ impl Mass {
 // Environment ///////////////////////////////////////////////////////////////
 //
 // An environment represents a space in which bodies interact with fields.
+// After each update, the state of the bodies is written to the sinks of the
+// environment, if any. Bodies may be assigned groups, see `Loader`, which
+// are passed on to the sinks. Without groups, all bodies are in group zero.
+
+/// The time advanced by each update. Velocities are applied whole, so time
+/// is measured in updates.
+pub const TIME_STEP: Float = 1.0;
 
 pub struct Environment {
     pub bodies: Vec<Body>,
     pub fields: Vec<Box<dyn Field>>,
-    writer: DataWriter,
+    pub sinks: Vec<Box<dyn Sink>>,
+    pub groups: Vec<usize>,
+    frame: usize,
 }
 
 impl Default for Environment {
@@ -61,22 +77,30 @@ impl Default for Environment {
         Environment {
             bodies: vec![],
             fields: vec![Box::from(field)],
-            writer: DataWriter::new("data"),
+            sinks: vec![],
+            groups: vec![],
+            frame: 0,
         }
     }
 }
 
 impl Environment {
-    pub fn new(fields: Vec<Box<dyn Field>>, writer: DataWriter) -> Environment {
-        Environment { fields, writer, ..Self::default() }
+    pub fn new(fields: Vec<Box<dyn Field>>, sinks: Vec<Box<dyn Sink>>) -> Environment {
+        Environment { fields, sinks, ..Self::default() }
     }
 
-    pub fn update(&mut self) {
+    /// Advances the bodies by one time step and writes their new state to
+    /// the sinks. Stops at the first sink that fails.
+    pub fn update(&mut self) -> io::Result<()> {
+        let mut accelerations = vec![Vector::zero(); self.bodies.len()];
+
         for field in self.fields.iter() {
             let forces = field.forces(&self.bodies[..]);
 
-            for (body, force) in self.bodies.iter_mut().zip(forces.iter()) {
+            let bodies = self.bodies.iter_mut().zip(accelerations.iter_mut());
+            for ((body, acceleration), force) in bodies.zip(forces.iter()) {
                 body.apply_force(force);
+                *acceleration += force / body.mass.value();
             }
         }
 
@@ -84,8 +108,32 @@ impl Environment {
             body.apply_velocity();
         }
 
-        let points = self.bodies.iter().map(|b| b.position.clone()).collect();
-        self.writer.write(points);
+        let frame = self.frame;
+        self.frame += 1;
+
+        if !self.sinks.is_empty() {
+            let mut snapshot = Snapshot::new(frame, self.time(), &self.bodies).with_accelerations(accelerations);
+            if !self.groups.is_empty() {
+                snapshot = snapshot.with_groups(&self.groups);
+            }
+            for sink in self.sinks.iter_mut() {
+                sink.write(&snapshot)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The time passed since the initial state.
+    pub fn time(&self) -> Float {
+        self.frame as Float * TIME_STEP
+    }
+
+    /// Finishes the sinks after the last update.
+    pub fn finish(&mut self) -> io::Result<()> {
+        for sink in self.sinks.iter_mut() {
+            sink.finish()?;
+        }
+        Ok(())
     }
 }
 
@@ -120,12 +168,12 @@ impl Eq for Body {}
 impl PartialEq for Body {
     fn eq(&self, other: &'_ Body) -> bool {
         // compared referentially
-        self as *const _ == other as *const _
+        std::ptr::eq(self, other)
     }
 }
 
 impl Body {
-    pub fn new(mass: f32, position: Point, velocity: Vector) -> Body {
+    pub fn new(mass: Float, position: Point, velocity: Vector) -> Body {
         Body {
             mass: Mass::from(mass),
             position,
@@ -147,7 +195,11 @@ impl Body {
 
 #[cfg(test)]
 mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
     use crate::geometry::{Point, Vector};
+    use crate::physics::field::BruteForceField;
 
     use super::*;
 
@@ -190,6 +242,104 @@ mod tests {
         assert_eq!(Point::new(1.0, 2.0), sut.position);
     }
 
+    /// Records the snapshots it is given, or fails if it has no room.
+    struct Recorder {
+        snapshots: Rc<RefCell<Vec<Snapshot>>>,
+        capacity: usize,
+    }
+
+    impl Sink for Recorder {
+        fn write(&mut self, snapshot: &Snapshot) -> io::Result<()> {
+            let mut snapshots = self.snapshots.borrow_mut();
+            if snapshots.len() == self.capacity {
+                return Err(io::Error::other("Recorder is full."));
+            }
+            snapshots.push(snapshot.clone());
+            Ok(())
+        }
+    }
+
+    fn recorder(capacity: usize) -> (Box<dyn Sink>, Rc<RefCell<Vec<Snapshot>>>) {
+        let snapshots = Rc::new(RefCell::new(vec![]));
+        (Box::new(Recorder { snapshots: Rc::clone(&snapshots), capacity }), snapshots)
+    }
+
+    #[test]
+    fn environment_writes_snapshots_to_sinks() {
+        // given
+        let (first, first_snapshots) = recorder(10);
+        let (second, second_snapshots) = recorder(10);
+        let mut sut = Environment::new(vec![], vec![first, second]);
+        sut.bodies = vec![Body::new(2.0, Point::new(1.0, 2.0), Vector::new(-2.0, 5.0))];
+
+        // when
+        sut.update().unwrap();
+        sut.update().unwrap();
+
+        // then
+        let snapshots = first_snapshots.borrow();
+        assert_eq!(*snapshots, *second_snapshots.borrow());
+        assert_eq!(2, snapshots.len());
+        assert_eq!((0, 1.0), (snapshots[0].frame, snapshots[0].time));
+        assert_eq!((1, 2.0), (snapshots[1].frame, snapshots[1].time));
+        assert_eq!(vec![Point::new(-3.0, 12.0)], snapshots[1].positions);
+        assert_eq!(Some(vec![Vector::zero()]), snapshots[1].accelerations);
+        assert_eq!(2.0, sut.time());
+    }
+
+    #[test]
+    fn environment_writes_groups_and_accelerations() {
+        // given
+        let (sink, snapshots) = recorder(10);
+        let field = BruteForceField::new();
+        let mut sut = Environment::new(vec![Box::from(field)], vec![sink]);
+        sut.bodies = vec![
+            Body::new(2.0, Point::new(0.0, 0.0), Vector::zero()),
+            Body::new(1.0, Point::new(10.0, 0.0), Vector::zero()),
+        ];
+        sut.groups = vec![3, 5];
+        let forces = BruteForceField::new().forces(&sut.bodies);
+
+        // when
+        sut.update().unwrap();
+
+        // then
+        let snapshot = &snapshots.borrow()[0];
+        assert_eq!(vec![3, 5], snapshot.groups);
+        let expected = vec![&forces[0] / 2.0, &forces[1] / 1.0];
+        assert_eq!(Some(expected), snapshot.accelerations);
+    }
+
+    #[test]
+    fn environment_without_sinks_updates() {
+        // given
+        let mut sut = Environment::new(vec![], vec![]);
+        sut.bodies = vec![Body::new(2.0, Point::new(1.0, 2.0), Vector::new(-2.0, 5.0))];
+
+        // when
+        sut.update().unwrap();
+
+        // then
+        assert_eq!(Point::new(-1.0, 7.0), sut.bodies[0].position);
+        assert!(sut.finish().is_ok());
+    }
+
+    #[test]
+    fn environment_returns_sink_errors() {
+        // given
+        let (sink, snapshots) = recorder(1);
+        let mut sut = Environment::new(vec![], vec![sink]);
+
+        // when
+        let first = sut.update();
+        let second = sut.update();
+
+        // then
+        assert!(first.is_ok());
+        assert_eq!("Recorder is full.", second.unwrap_err().to_string());
+        assert_eq!(1, snapshots.borrow().len());
+    }
+
     #[test]
     fn body_applies_velocity() {
         // given
@@ -0,0 +1,406 @@
+use crate::geometry::sum::Sum;
+use crate::geometry::three::{Cube, Point3};
+use crate::Float;
+
+use super::barneshut::DEFAULT_THETA;
+use super::three::Body3;
+
+// Octree ////////////////////////////////////////////////////////////////////
+//
+// The 3D counterpart of the Barnes-Hut quadtree. Each internal node splits
+// its cube into eight octants, and bodies are stored in the leaves. Forces
+// are approximated the same way, by walking the tree and treating distant
+// nodes as virtual bodies.
+//
+// The nodes are stored contiguously in an arena, with the root at index 0.
+// When a node is split, its eight children are appended to the arena
+// together, so a node only needs to know the index of its first child.
+
+type Index = usize;
+
+const CHILDREN: usize = 8;
+
+#[derive(Debug)]
+pub struct Octree {
+    space: Cube,
+    nodes: Vec<Node>,
+    theta: Float,
+}
+
+impl Octree {
+    /// Initialized tree with a root node spanning the given space.
+    pub fn new(space: Cube) -> Octree {
+        let root = Node::new(space.clone());
+        Octree { space, nodes: vec![root], theta: DEFAULT_THETA }
+    }
+
+    /// Builds a tree containing the given bodies. Bodies outside of the space
+    /// are ignored.
+    pub fn from_bodies(space: Cube, bodies: &[Body3]) -> Octree {
+        let mut tree = Octree::new(space);
+        for body in bodies {
+            tree.add(body);
+        }
+        tree
+    }
+
+    /// Sets the opening parameter of walks, see `BHTree::with_theta`. Panics
+    /// if theta is negative.
+    pub fn with_theta(self, theta: Float) -> Octree {
+        if theta.is_nan() || theta < 0.0 {
+            panic!("The opening parameter must not be negative. Got {}", theta);
+        }
+        Octree { theta, ..self }
+    }
+
+    /// Inserts the given body into the tree, if it is inside its space.
+    pub fn add(&mut self, body: &Body3) {
+        if self.space.contains(&body.position) {
+            self.insert(body);
+        }
+    }
+
+    /// Calls `visit` with the mass and center of each virtual body acting on
+    /// the given body, without allocating. Nodes are opened as in
+    /// `BHTree::walk`.
+    pub fn walk<F>(&self, body: &Body3, mut visit: F) where F: FnMut(Float, &Point3) {
+        let own = self.leaf_of(&body.position);
+        let mut stack = WalkStack::new();
+        stack.push(0);
+
+        while let Some(idx) = stack.pop() {
+            let node = &self.nodes[idx];
+            let (mass, center) = node.body.centered();
+            let dist = body.position.distance_to(&center);
+
+            if !node.space.contains(&body.position) && node.space.diameter() / dist < self.theta {
+                visit(mass, &center);
+
+            } else if let Some(first) = node.children {
+                stack.push_children(&self.nodes, first);
+
+            } else {
+                let mut virtual_body = node.body.clone();
+
+                // subtract the body from the leaf containing it
+                if own == Some(idx) {
+                    let negative = VirtualBody3::weighted(-body.mass.value(), &body.position);
+                    virtual_body.accumulate(&negative);
+                }
+
+                if virtual_body.mass() > 0.0 {
+                    let (mass, center) = virtual_body.centered();
+                    visit(mass, &center);
+                }
+            }
+        }
+    }
+
+    /// Returns the index of the leaf a body at the given position would be
+    /// inserted into. Points on the boundary of octants are contained by
+    /// several leaves, so this descends the same way as `insert`.
+    fn leaf_of(&self, position: &Point3) -> Option<Index> {
+        if !self.space.contains(position) {
+            return None;
+        }
+        let mut idx = 0;
+        while let Some(first) = self.nodes[idx].children {
+            idx = first + self.nodes[idx].space.child_offset(position);
+        }
+        Some(idx)
+    }
+
+    /// Inserts the given body into the tree. Starting at the root, the body
+    /// is accumulated into each node on the path down to the first empty
+    /// leaf. Occupied leaves on the way are split and their body is moved
+    /// into the appropriate child, unless they span a unit cube, in which
+    /// case the bodies are merged.
+    fn insert(&mut self, body: &Body3) {
+        let virtual_body = VirtualBody3::from(body);
+        let mut idx = 0;
+
+        loop {
+            let node = &mut self.nodes[idx];
+
+            if let Some(first) = node.children {
+                node.body.accumulate(&virtual_body);
+                idx = first + node.space.child_offset(&body.position);
+
+            } else if node.is_empty() || node.space.is_unit_cube() {
+                node.body.accumulate(&virtual_body);
+                return;
+
+            } else {
+                self.internalize(idx);
+            }
+        }
+    }
+
+    /// Internalizes the leaf at the given index by appending its eight
+    /// children to the arena and moving the leaf's body into the
+    /// appropriate child.
+    fn internalize(&mut self, idx: Index) {
+        let first = self.nodes.len();
+        let (_, center) = self.nodes[idx].body.centered();
+        let offset = self.nodes[idx].space.child_offset(&center);
+
+        for octant in 0..CHILDREN {
+            let space = self.nodes[idx].space.octant(octant);
+            self.nodes.push(Node::new(space));
+        }
+
+        self.nodes[first + offset].body = self.nodes[idx].body.clone();
+        self.nodes[idx].children = Some(first);
+    }
+}
+
+// VirtualBody3 //////////////////////////////////////////////////////////////
+//
+// The total mass and mass weighted position of the bodies below a node,
+// summed with compensation as in the quadtree.
+
+#[derive(Clone, Default, Debug)]
+struct VirtualBody3 {
+    mass: Sum,
+    x: Sum,
+    y: Sum,
+    z: Sum,
+}
+
+impl From<&Body3> for VirtualBody3 {
+    fn from(body: &Body3) -> Self {
+        VirtualBody3::weighted(body.mass.value(), &body.position)
+    }
+}
+
+impl VirtualBody3 {
+    fn weighted(mass: Float, position: &Point3) -> VirtualBody3 {
+        let mut result = VirtualBody3::default();
+        result.mass += mass;
+        result.x += position.x * mass;
+        result.y += position.y * mass;
+        result.z += position.z * mass;
+        result
+    }
+
+    fn accumulate(&mut self, other: &VirtualBody3) {
+        self.mass += other.mass.value();
+        self.x += other.x.value();
+        self.y += other.y.value();
+        self.z += other.z.value();
+    }
+
+    fn mass(&self) -> Float {
+        self.mass.value()
+    }
+
+    /// Returns the mass and its center.
+    fn centered(&self) -> (Float, Point3) {
+        let mass = self.mass();
+        (mass, Point3::new(self.x.value() / mass, self.y.value() / mass, self.z.value() / mass))
+    }
+}
+
+// WalkStack /////////////////////////////////////////////////////////////////
+//
+// A fixed size stack of pending nodes for tree walks. Each level of the
+// tree pushes at most eight nodes after popping one, and a tree is at most
+// 31 levels deep, so the stack never holds more than 7 * 31 + 8 nodes.
+
+const WALK_STACK_SIZE: usize = 256;
+
+struct WalkStack {
+    items: [Index; WALK_STACK_SIZE],
+    len: usize,
+}
+
+impl WalkStack {
+    fn new() -> WalkStack {
+        WalkStack { items: [0; WALK_STACK_SIZE], len: 0 }
+    }
+
+    fn push(&mut self, idx: Index) {
+        self.items[self.len] = idx;
+        self.len += 1;
+    }
+
+    /// Pushes the non empty children in reverse, so that the first child is
+    /// popped first.
+    fn push_children(&mut self, nodes: &[Node], first: Index) {
+        for idx in (first..first + CHILDREN).rev() {
+            if !nodes[idx].is_empty() {
+                self.push(idx);
+            }
+        }
+    }
+
+    fn pop(&mut self) -> Option<Index> {
+        if self.len == 0 {
+            return None;
+        }
+        self.len -= 1;
+        Some(self.items[self.len])
+    }
+}
+
+// Node //////////////////////////////////////////////////////////////////////
+
+#[derive(Debug)]
+struct Node {
+    space: Cube,
+    body: VirtualBody3,
+    children: Option<Index>,
+}
+
+impl Node {
+    fn new(space: Cube) -> Node {
+        Node { space, body: VirtualBody3::default(), children: None }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.body.mass() == 0.0
+    }
+}
+
+// Tests /////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use crate::geometry::three::Vector3;
+
+    use super::*;
+
+    fn new_body(mass: Float, x: Float, y: Float, z: Float) -> Body3 {
+        Body3::new(mass, Point3::new(x, y, z), Vector3::zero())
+    }
+
+    #[test]
+    fn octree_splits_occupied_leaves() {
+        // given
+        let bodies = vec![
+            new_body(1.0, 1.0, 1.0, 1.0),
+            new_body(1.0, 3.0, 3.0, 3.0),
+        ];
+
+        // when
+        let sut = Octree::from_bodies(Cube::new(0.0, 0.0, 0.0, 2), &bodies);
+
+        // then
+        assert_eq!(9, sut.nodes.len());
+        assert_eq!(Some(1), sut.nodes[0].children);
+        assert_eq!(1.0, sut.nodes[1 + 2].body.mass());
+        assert_eq!(1.0, sut.nodes[1 + 5].body.mass());
+    }
+
+    #[test]
+    fn octree_merges_bodies_in_unit_cubes() {
+        // given
+        let bodies = vec![
+            new_body(1.0, 0.25, 0.25, 0.25),
+            new_body(3.0, 0.75, 0.75, 0.75),
+        ];
+
+        // when
+        let sut = Octree::from_bodies(Cube::new(0.0, 0.0, 0.0, 0), &bodies);
+
+        // then
+        assert_eq!(1, sut.nodes.len());
+        let (mass, center) = sut.nodes[0].body.centered();
+        assert_eq!(4.0, mass);
+        assert_eq!(Point3::new(0.625, 0.625, 0.625), center);
+    }
+
+    #[test]
+    fn octree_ignores_bodies_out_of_bounds() {
+        // given
+        let bodies = vec![new_body(1.0, 5.0, 1.0, 1.0)];
+
+        // when
+        let sut = Octree::from_bodies(Cube::new(0.0, 0.0, 0.0, 2), &bodies);
+
+        // then
+        assert!(sut.nodes[0].is_empty());
+    }
+
+    #[test]
+    fn octree_walk_visits_other_bodies() {
+        // given
+        let bodies = vec![
+            new_body(1.0, 1.0, 1.0, 1.0),
+            new_body(1.0, 3.0, 3.0, 3.0),
+            new_body(1.0, 100.0, 100.0, 100.0),
+            new_body(1.0, 102.0, 101.0, 100.0),
+        ];
+        let sut = Octree::from_bodies(Cube::new(0.0, 0.0, 0.0, 7), &bodies);
+
+        // when
+        let mut visited = vec![];
+        sut.walk(&bodies[0], |mass, position| visited.push((mass, position.clone())));
+
+        // then the near body is visited alone and the far ones as one
+        let total: Float = visited.iter().map(|(mass, _)| mass).sum();
+        assert_eq!(3.0, total);
+        assert!(visited.contains(&(1.0, Point3::new(3.0, 3.0, 3.0))));
+        assert!(visited.contains(&(2.0, Point3::new(101.0, 100.5, 100.0))));
+    }
+
+    #[test]
+    fn octree_walk_subtracts_body_only_from_its_leaf() {
+        // given a body on the boundary of the leaf of a lighter one
+        let bodies = vec![
+            new_body(2.0, 2.0, 2.0, 2.0),
+            new_body(1.0, 1.0, 1.0, 1.0),
+        ];
+        let sut = Octree::from_bodies(Cube::new(0.0, 0.0, 0.0, 2), &bodies);
+
+        // when
+        let mut visited = vec![];
+        sut.walk(&bodies[0], |mass, position| visited.push((mass, position.clone())));
+
+        // then
+        assert_eq!(vec![(1.0, Point3::new(1.0, 1.0, 1.0))], visited);
+    }
+
+    #[test]
+    fn octree_walk_opens_nodes_containing_body() {
+        // given a light body far from the center of mass of the root
+        let bodies = vec![
+            new_body(1.0, 1.0, 1.0, 1.0),
+            new_body(100.0, 60.0, 60.0, 60.0),
+            new_body(100.0, 62.0, 60.0, 60.0),
+        ];
+        let sut = Octree::from_bodies(Cube::new(0.0, 0.0, 0.0, 6), &bodies);
+
+        // when
+        let mut visited = vec![];
+        sut.walk(&bodies[0], |mass, position| visited.push((mass, position.clone())));
+
+        // then the body doesn't attract itself
+        assert_eq!(vec![(200.0, Point3::new(61.0, 60.0, 60.0))], visited);
+    }
+
+    #[test]
+    fn octree_with_zero_theta_visits_each_body() {
+        // given
+        let bodies: Vec<Body3> = (0..20)
+            .map(|i| new_body(1.0, i as Float * 6.0, (i % 5) as Float * 9.0, (i % 3) as Float * 13.0))
+            .collect();
+        let sut = Octree::from_bodies(Cube::new(0.0, 0.0, 0.0, 7), &bodies).with_theta(0.0);
+
+        for body in bodies.iter() {
+            // when
+            let mut count = 0;
+            sut.walk(body, |_, _| count += 1);
+
+            // then
+            assert_eq!(bodies.len() - 1, count);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "The opening parameter must not be negative.")]
+    fn octree_panics_on_negative_theta() {
+        // given
+        Octree::new(Cube::new(0.0, 0.0, 0.0, 1)).with_theta(-1.0);
+    }
+}
@@ -0,0 +1,264 @@
+use std::io;
+
+use rayon::prelude::*;
+
+use crate::geometry::sum::VectorSum3;
+use crate::geometry::three::{Cube, Point3, Vector3};
+use crate::util::sink::{Sink3, Snapshot3};
+use crate::Float;
+
+use super::barneshut::DEFAULT_THETA;
+use super::direct;
+use super::force::{Gravity, MIN_DISTANCE};
+use super::octree::Octree;
+use super::{Mass, TIME_STEP};
+
+// Body3 /////////////////////////////////////////////////////////////////////
+//
+// A movable object in 3D space.
+
+#[derive(Clone, Debug)]
+pub struct Body3 {
+    pub mass: Mass,
+    pub position: Point3,
+    pub velocity: Vector3,
+}
+
+impl Body3 {
+    pub fn new(mass: Float, position: Point3, velocity: Vector3) -> Body3 {
+        Body3 {
+            mass: Mass::from(mass),
+            position,
+            velocity,
+        }
+    }
+
+    pub fn apply_force(&mut self, force: &Vector3) {
+        self.velocity += force / self.mass.value();
+    }
+
+    pub fn apply_velocity(&mut self) {
+        self.position.x += self.velocity.dx;
+        self.position.y += self.velocity.dy;
+        self.position.z += self.velocity.dz;
+    }
+}
+
+// Field3 ////////////////////////////////////////////////////////////////////
+//
+// The 3D counterpart of `Field`.
+
+pub trait Field3: Sync {
+    fn forces(&self, bodies: &[Body3]) -> Vec<Vector3>;
+}
+
+// BruteForceField3 //////////////////////////////////////////////////////////
+//
+// Direct gravitation calculation between each pair of bodies in 3D space,
+// using the same kernel as `BruteForceField`.
+
+pub struct BruteForceField3 {
+    force: Gravity,
+}
+
+impl Field3 for BruteForceField3 {
+    fn forces(&self, bodies: &[Body3]) -> Vec<Vector3> {
+        direct::forces(&self.force, bodies)
+    }
+}
+
+impl Default for BruteForceField3 {
+    fn default() -> Self {
+        BruteForceField3 {
+            force: Gravity::new(1.0, MIN_DISTANCE),
+        }
+    }
+}
+
+impl BruteForceField3 {
+    pub fn new() -> BruteForceField3 {
+        Self::default()
+    }
+}
+
+// OctreeField ///////////////////////////////////////////////////////////////
+//
+// Barnes-Hut gravitation calculation in 3D space, using an octree that is
+// rebuilt on each call.
+
+pub struct OctreeField {
+    space: Cube,
+    force: Gravity,
+    theta: Float,
+}
+
+impl Field3 for OctreeField {
+    fn forces(&self, bodies: &[Body3]) -> Vec<Vector3> {
+        let tree = Octree::from_bodies(self.space.clone(), bodies).with_theta(self.theta);
+        bodies.par_iter().map(|body| {
+            let mut total = VectorSum3::new();
+            tree.walk(body, |mass, position| {
+                total += self.force.towards3(body, mass, position);
+            });
+            total.value()
+        }).collect()
+    }
+}
+
+impl Default for OctreeField {
+    fn default() -> Self {
+        OctreeField {
+            space: Cube::new(-2048.0, -2048.0, -2048.0, 12),
+            force: Gravity::new(1.0, MIN_DISTANCE),
+            theta: DEFAULT_THETA,
+        }
+    }
+}
+
+impl OctreeField {
+    pub fn new() -> OctreeField {
+        Self::default()
+    }
+
+    /// Sets the opening parameter of the tree, see `BHTree::with_theta`.
+    pub fn with_theta(self, theta: Float) -> OctreeField {
+        OctreeField { theta, ..self }
+    }
+}
+
+// Environment3 //////////////////////////////////////////////////////////////
+//
+// The 3D counterpart of `Environment`.
+
+pub struct Environment3 {
+    pub bodies: Vec<Body3>,
+    pub fields: Vec<Box<dyn Field3>>,
+    pub sinks: Vec<Box<dyn Sink3>>,
+    frame: usize,
+}
+
+impl Default for Environment3 {
+    fn default() -> Self {
+        Environment3 {
+            bodies: vec![],
+            fields: vec![Box::from(OctreeField::new())],
+            sinks: vec![],
+            frame: 0,
+        }
+    }
+}
+
+impl Environment3 {
+    pub fn new(fields: Vec<Box<dyn Field3>>, sinks: Vec<Box<dyn Sink3>>) -> Environment3 {
+        Environment3 { fields, sinks, ..Self::default() }
+    }
+
+    /// Advances the bodies by one time step and writes their new state to
+    /// the sinks. Stops at the first sink that fails.
+    pub fn update(&mut self) -> io::Result<()> {
+        for field in self.fields.iter() {
+            let forces = field.forces(&self.bodies[..]);
+
+            for (body, force) in self.bodies.iter_mut().zip(forces.iter()) {
+                body.apply_force(force);
+            }
+        }
+
+        for body in self.bodies.iter_mut() {
+            body.apply_velocity();
+        }
+
+        let frame = self.frame;
+        self.frame += 1;
+
+        if !self.sinks.is_empty() {
+            let snapshot = Snapshot3::new(frame, self.time(), &self.bodies);
+            for sink in self.sinks.iter_mut() {
+                sink.write(&snapshot)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The time passed since the initial state.
+    pub fn time(&self) -> Float {
+        self.frame as Float * TIME_STEP
+    }
+
+    /// Finishes the sinks after the last update.
+    pub fn finish(&mut self) -> io::Result<()> {
+        for sink in self.sinks.iter_mut() {
+            sink.finish()?;
+        }
+        Ok(())
+    }
+}
+
+// Tests /////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use rand::distributions::{Distribution, Uniform};
+    use rand::{SeedableRng, StdRng};
+
+    use super::*;
+
+    #[test]
+    fn body3_applies_force_and_velocity() {
+        // given
+        let mut sut = Body3::new(2.0, Point3::new(1.0, 2.0, 3.0), Vector3::new(-2.0, 5.0, 1.0));
+
+        // when
+        sut.apply_force(&Vector3::new(3.0, -3.0, 1.0));
+        sut.apply_velocity();
+
+        // then
+        assert_eq!(Vector3::new(-0.5, 3.5, 1.5), sut.velocity);
+        assert_eq!(Point3::new(0.5, 5.5, 4.5), sut.position);
+    }
+
+    #[test]
+    fn octree_field_approximates_brute_force() {
+        // given
+        let mut rng = StdRng::from_seed([7; 32]);
+        let range = Uniform::new(-500.0, 500.0);
+        let mut bodies: Vec<Body3> = (0..500).map(|_| {
+            let position = Point3::new(range.sample(&mut rng), range.sample(&mut rng), range.sample(&mut rng));
+            Body3::new(1.0, position, Vector3::zero())
+        }).collect();
+        bodies.push(Body3::new(500.0, Point3::zero(), Vector3::zero()));
+
+        // when
+        let expected = BruteForceField3::new().forces(&bodies);
+        let result = OctreeField::new().forces(&bodies);
+
+        // then
+        let error: Float = expected.iter().zip(result.iter())
+            .map(|(e, r)| Vector3::new(e.dx - r.dx, e.dy - r.dy, e.dz - r.dz).magnitude())
+            .sum();
+        let total: Float = expected.iter().map(|e| e.magnitude()).sum();
+        assert!(error / total < 0.1, "Relative error {}", error / total);
+    }
+
+    #[test]
+    fn octree_field_with_zero_theta_matches_brute_force() {
+        // given
+        let bodies: Vec<Body3> = (0..50)
+            .map(|i| {
+                let position = Point3::new(i as Float * 7.0, (i % 7) as Float * 11.0, (i % 3) as Float * 5.0);
+                Body3::new(1.0 + (i % 4) as Float, position, Vector3::zero())
+            })
+            .collect();
+
+        // when
+        let expected = BruteForceField3::new().forces(&bodies);
+        let result = OctreeField::new().with_theta(0.0).forces(&bodies);
+
+        // then
+        for (e, r) in expected.iter().zip(result.iter()) {
+            assert_close!(e.dx, r.dx);
+            assert_close!(e.dy, r.dy);
+            assert_close!(e.dz, r.dz);
+        }
+    }
+}
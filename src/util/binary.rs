@@ -0,0 +1,264 @@
+use std::fs;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::marker::PhantomData;
+use std::path::Path;
+
+use crate::geometry::three::{Point3, Vector3};
+use crate::geometry::{Point, Vector};
+use crate::physics::three::Body3;
+use crate::physics::Body;
+use crate::Float;
+
+use super::sink::{Sink, Sink3, Snapshot, Snapshot3};
+
+// Binary Files //////////////////////////////////////////////////////////////
+//
+// Helpers shared by the binary file formats. Files store integers and
+// floats of fixed sizes in either byte order, while bodies use `Float`,
+// which is either f32 or f64 depending on the build.
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub(super) enum Order {
+    Little,
+    Big,
+}
+
+impl Order {
+    pub(super) fn u32(self, bytes: &[u8]) -> u32 {
+        let mut value = [0; 4];
+        value.copy_from_slice(&bytes[..4]);
+        match self {
+            Order::Little => u32::from_le_bytes(value),
+            Order::Big => u32::from_be_bytes(value),
+        }
+    }
+
+    pub(super) fn u64(self, bytes: &[u8]) -> u64 {
+        let mut value = [0; 8];
+        value.copy_from_slice(&bytes[..8]);
+        match self {
+            Order::Little => u64::from_le_bytes(value),
+            Order::Big => u64::from_be_bytes(value),
+        }
+    }
+
+    pub(super) fn f32(self, bytes: &[u8]) -> f32 {
+        f32::from_bits(self.u32(bytes))
+    }
+
+    pub(super) fn f64(self, bytes: &[u8]) -> f64 {
+        f64::from_bits(self.u64(bytes))
+    }
+
+    pub(super) fn put_u32(self, value: u32) -> [u8; 4] {
+        match self {
+            Order::Little => value.to_le_bytes(),
+            Order::Big => value.to_be_bytes(),
+        }
+    }
+
+    #[allow(clippy::unnecessary_cast)]
+    pub(super) fn put_f32(self, value: Float) -> [u8; 4] {
+        self.put_u32((value as f32).to_bits())
+    }
+
+    pub(super) fn put_f64(self, value: f64) -> [u8; 8] {
+        match self {
+            Order::Little => value.to_le_bytes(),
+            Order::Big => value.to_be_bytes(),
+        }
+    }
+}
+
+/// Returns an error for a malformed file.
+pub(super) fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Returns the given mass, or an error unless it is positive, which also
+/// rejects NaN.
+#[allow(clippy::neg_cmp_op_on_partial_ord)]
+pub(super) fn positive(mass: Float) -> io::Result<Float> {
+    if !(mass > 0.0) {
+        return Err(invalid(&format!("Expected positive masses, got {}.", mass)));
+    }
+    Ok(mass)
+}
+
+#[allow(clippy::unnecessary_cast)]
+pub(super) fn wide(value: Float) -> f64 {
+    value as f64
+}
+
+#[allow(clippy::unnecessary_cast)]
+pub(super) fn widen(value: f32) -> Float {
+    value as Float
+}
+
+#[allow(clippy::unnecessary_cast)]
+pub(super) fn narrow(value: f64) -> Float {
+    value as Float
+}
+
+// ParticleFile //////////////////////////////////////////////////////////////
+//
+// A snapshot format of other codes, holding particles in 3D space. A format
+// reads and writes its files and creates them from particles, and the
+// conversion from and to bodies follows. 2D bodies lie in the plane z = 0,
+// and z is dropped when particles are loaded as bodies in the plane.
+
+pub trait ParticleFile: Sized {
+    /// Creates a file of the given particles.
+    fn from_particles(time: Float, ids: &[usize], masses: &[Float],
+                      positions: Vec<Point3>, velocities: Vec<Vector3>) -> Self;
+
+    fn masses(&self) -> &[Float];
+
+    fn positions(&self) -> &[Point3];
+
+    fn velocities(&self) -> &[Vector3];
+
+    fn read<R: Read>(input: R) -> io::Result<Self>;
+
+    fn write<W: Write>(&self, out: &mut W) -> io::Result<()>;
+
+    /// The name of the file of the given frame.
+    fn file_name(frame: usize) -> String;
+
+    fn open(path: &str) -> io::Result<Self> {
+        Self::read(BufReader::new(fs::File::open(path)?))
+    }
+
+    fn from_snapshot(snapshot: &Snapshot) -> Self {
+        Self::from_particles(
+            snapshot.time,
+            &snapshot.ids,
+            &snapshot.masses,
+            snapshot.positions.iter().map(|p| Point3::new(p.x, p.y, 0.0)).collect(),
+            snapshot.velocities.iter().map(|v| Vector3::new(v.dx, v.dy, 0.0)).collect(),
+        )
+    }
+
+    fn from_snapshot3(snapshot: &Snapshot3) -> Self {
+        Self::from_particles(
+            snapshot.time,
+            &snapshot.ids,
+            &snapshot.masses,
+            snapshot.positions.clone(),
+            snapshot.velocities.clone(),
+        )
+    }
+
+    /// The particles as bodies in the plane, dropping the z axis.
+    fn bodies(&self) -> Vec<Body> {
+        let particles = self.masses().iter().zip(self.positions()).zip(self.velocities());
+        particles
+            .map(|((mass, p), v)| Body::new(*mass, Point::new(p.x, p.y), Vector::new(v.dx, v.dy)))
+            .collect()
+    }
+
+    fn bodies3(&self) -> Vec<Body3> {
+        let particles = self.masses().iter().zip(self.positions()).zip(self.velocities());
+        particles
+            .map(|((mass, p), v)| Body3::new(*mass, p.clone(), v.clone()))
+            .collect()
+    }
+}
+
+/// Loads the bodies of the given file in the plane.
+pub fn load_from_path<F: ParticleFile>(path: &str) -> io::Result<Vec<Body>> {
+    Ok(F::open(path)?.bodies())
+}
+
+pub fn load_from_path3<F: ParticleFile>(path: &str) -> io::Result<Vec<Body3>> {
+    Ok(F::open(path)?.bodies3())
+}
+
+// ParticleWriter ////////////////////////////////////////////////////////////
+//
+// A sink writing each snapshot as a particle file in the specified
+// directory, named after the frame by the format. The directory is created
+// with the first file.
+
+pub struct ParticleWriter<F> {
+    directory: String,
+    format: PhantomData<F>,
+}
+
+impl<F: ParticleFile> ParticleWriter<F> {
+    pub fn new(directory: &str) -> ParticleWriter<F> {
+        ParticleWriter { directory: directory.to_owned(), format: PhantomData }
+    }
+
+    fn write_file(&self, frame: usize, file: &F) -> io::Result<()> {
+        fs::create_dir_all(&self.directory)?;
+        let path = Path::new(&self.directory).join(F::file_name(frame));
+        let mut out = BufWriter::new(fs::File::create(path)?);
+        file.write(&mut out)?;
+        out.flush()
+    }
+}
+
+impl<F: ParticleFile> Sink for ParticleWriter<F> {
+    fn write(&mut self, snapshot: &Snapshot) -> io::Result<()> {
+        self.write_file(snapshot.frame, &F::from_snapshot(snapshot))
+    }
+}
+
+impl<F: ParticleFile> Sink3 for ParticleWriter<F> {
+    fn write(&mut self, snapshot: &Snapshot3) -> io::Result<()> {
+        self.write_file(snapshot.frame, &F::from_snapshot3(snapshot))
+    }
+}
+
+// Tests /////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use crate::util::gadget::Gadget;
+
+    use super::*;
+
+    #[test]
+    fn order_reads_and_writes_both_byte_orders() {
+        // given
+        let bytes = [0x3f, 0xc0, 0, 0];
+
+        // then
+        assert_eq!(1.5, Order::Big.f32(&bytes));
+        assert_eq!(bytes, Order::Big.put_f32(1.5));
+        assert_eq!(0xc03f, Order::Little.u32(&bytes));
+        assert_eq!(1.5, Order::Little.f64(&Order::Little.put_f64(1.5)));
+    }
+
+    #[test]
+    fn positive_rejects_nan_and_non_positive_masses() {
+        // then
+        assert_eq!(2.0, positive(2.0).unwrap());
+        for mass in [0.0, -1.0, Float::NAN].iter() {
+            assert_eq!(io::ErrorKind::InvalidData, positive(*mass).unwrap_err().kind());
+        }
+    }
+
+    #[test]
+    fn particle_file_of_snapshot_in_the_plane() {
+        // given
+        let bodies = vec![
+            Body::new(2.0, Point::new(1.5, -2.0), Vector::new(0.25, 3.0)),
+            Body::new(0.5, Point::new(4.0, 8.0), Vector::new(-1.0, 0.0)),
+        ];
+
+        // when
+        let result = Gadget::from_snapshot(&Snapshot::new(0, 1.0, &bodies));
+
+        // then the bodies lie in the plane z = 0, and are loaded back
+        assert_eq!(Point3::new(1.5, -2.0, 0.0), result.positions()[0]);
+        assert_eq!(Vector3::new(-1.0, 0.0, 0.0), result.velocities()[1]);
+        assert_eq!(vec![2.0, 0.5], result.masses());
+        for (body, loaded) in bodies.iter().zip(result.bodies().iter()) {
+            assert_eq!(body.position, loaded.position);
+            assert_eq!(body.velocity, loaded.velocity);
+        }
+        assert_eq!(0.0, result.bodies3()[1].position.z);
+    }
+}
@@ -10,13 +10,16 @@ use yaml_rust::ScanError;
 use yaml_rust::Yaml;
 use yaml_rust::YamlLoader;
 
+use crate::geometry::three::{Point3, Vector3};
 use crate::geometry::Point;
 use crate::geometry::Vector;
-use crate::geometry::util::Transformation;
+use crate::geometry::util::{Transformation, Transformation3};
+use crate::physics::three::Body3;
 use crate::physics::Body;
 use crate::physics::Mass;
 use crate::util::distribution::Error::*;
 use crate::util::gens::*;
+use crate::Float;
 
 // TODO: I want to be able to have automatic orbital velocity
 // Question: If I clone the gens, do they produce the same sequence?
@@ -28,30 +31,54 @@ use crate::util::gens::*;
 // defines 1) various types of property generators, 2) how to use these
 // generators to create body objects, and 3) the kinetic and spacial between
 // between of bodies as a hierarchy of systems.
+//
+// The loader is generic over the frame of the bodies, which is either 2D
+// (`TVR`) or 3D (`TVR3`). The same file can be loaded in both: 3D keys are
+// ignored in 2D, and default to zero, or to rotations about the z axis, in
+// 3D.
+//
+// The bodies created from the same body definition form a group, such as
+// the stars of a disk. Groups are numbered from zero in the order their
+// definitions are referenced by the systems.
 
 #[derive(Default)]
-pub struct Loader {
-    tree: DistributionTree,
-    bodies: HashMap<String, Vec<Node>>,
+pub struct Loader<F: Frame = TVR> {
+    tree: DistributionTree<F>,
+    bodies: HashMap<String, Vec<Node<F>>>,
+    group_names: Vec<String>,
+    node_groups: HashMap<Index, usize>,
+    groups: Vec<usize>,
     mass_gens: HashMap<String, MassGen>,
     translation_gens: HashMap<String, TranslationGen>,
     velocity_gens: HashMap<String, VelocityGen>,
     rotation_gens: HashMap<String, RotationGen>,
+    translation_gens3: HashMap<String, TranslationGen3>,
+    velocity_gens3: HashMap<String, VelocityGen3>,
+    rotation_gens3: HashMap<String, AxisRotationGen>,
 }
 
 impl Loader {
     pub fn new() -> Loader {
         Loader::default()
     }
+}
+
+impl Loader<TVR3> {
+    /// Creates a loader for bodies in 3D space.
+    pub fn new_3d() -> Loader<TVR3> {
+        Loader::default()
+    }
+}
 
-    pub fn load_from_path(&mut self, path: &str) -> Result<Vec<Body>> {
+impl<F: Frame> Loader<F> {
+    pub fn load_from_path(&mut self, path: &str) -> Result<Vec<F::Body>> {
         let mut file = File::open(path)?;
         let mut contents = String::new();
         file.read_to_string(&mut contents)?;
         self.load(contents)
     }
 
-    pub fn load(&mut self, config: String) -> Result<Vec<Body>> {
+    pub fn load(&mut self, config: String) -> Result<Vec<F::Body>> {
         let docs = YamlLoader::load_from_str(&config)?;
         let doc = &docs[0];
 
@@ -68,7 +95,19 @@ impl Loader {
         self.parse_bodies(bodies)?;
 
         self.parse_system(doc)?;
-        Ok(self.tree.bodies())
+        let (indices, bodies): (Vec<Index>, Vec<F::Body>) = self.tree.bodies().into_iter().unzip();
+        self.groups = indices.iter().map(|idx| self.node_groups[idx]).collect();
+        Ok(bodies)
+    }
+
+    /// Returns the group of each loaded body, in the order of the bodies.
+    pub fn groups(&self) -> &[usize] {
+        &self.groups
+    }
+
+    /// Returns the name of the body definition of each group.
+    pub fn group_names(&self) -> &[String] {
+        &self.group_names
     }
 
     // Accessors /////////////////////////////////////////////////////////////
@@ -97,27 +136,51 @@ impl Loader {
     }
 
     /// Attempts to get the real number at the given key for the given object.
-    fn get_real(&self, object: &Yaml, key: &str) -> Result<f32> {
+    fn get_real(&self, object: &Yaml, key: &str) -> Result<Float> {
         let value = self.get_value(object, key)?;
         match value.as_f64() {
-            Some(result) => Ok(result as f32),
+            Some(result) => Ok(result as Float),
             None => Err(ExpectedType(key.to_owned() + ": Real")),
         }
     }
 
     /// Returns either the real number at the given key for the given object, or the
     /// default value provide if they key is not found.
-    fn get_real_or(&self, object: &Yaml, key: &str, default: f32) -> Result<f32> {
+    fn get_real_or(&self, object: &Yaml, key: &str, default: Float) -> Result<Float> {
         let value = match self.get_value(object, key) {
             Ok(value) => value,
             Err(_) => return Ok(default),
         };
         match value.as_f64() {
-            Some(result) => Ok(result as f32),
+            Some(result) => Ok(result as Float),
             None => Err(ExpectedType(key.to_owned() + ": Real")),
         }
     }
 
+    /// Returns either the closed range at the given key for the given object,
+    /// or the empty range at zero if the key is not found.
+    fn get_range_or_zero(&self, object: &Yaml, key: &str) -> Result<(Float, Float)> {
+        match self.get_value(object, key) {
+            Ok(range) => Ok((self.get_real(range, "min")?, self.get_real(range, "max")?)),
+            Err(MissingKey(_)) => Ok((0.0, 0.0)),
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Returns either the axis at the given key for the given object, or the
+    /// z axis if the key is not found.
+    fn get_axis_or_z(&self, object: &Yaml, key: &str) -> Result<Vector3> {
+        match self.get_value(object, key) {
+            Ok(axis) => Ok(Vector3::new(
+                self.get_real_or(axis, "x", 0.0)?,
+                self.get_real_or(axis, "y", 0.0)?,
+                self.get_real_or(axis, "z", 0.0)?,
+            )),
+            Err(MissingKey(_)) => Ok(Vector3::new(0.0, 0.0, 1.0)),
+            Err(error) => Err(error),
+        }
+    }
+
     /// Attempts to get the string at the given key for the given object.
     fn get_string(&self, object: &Yaml, key: &str) -> Result<String> {
         let value = self.get_value(object, key)?;
@@ -139,7 +202,7 @@ impl Loader {
     // Gen Parsing ///////////////////////////////////////////////////////////
 
     /// Parses each generate description in the given list and stores them
-    /// in the corresponding hash map of self.
+    /// in the corresponding hash maps of self, both for 2D and 3D.
     fn parse_gens(&mut self, gens: &[Yaml]) -> Result<()> {
         for gen in gens {
             let name = self.get_string(gen, "name")?;
@@ -152,15 +215,24 @@ impl Loader {
                 },
                 "translation" => {
                     let translation_gen = self.parse_translation_gen(gen)?;
-                    self.translation_gens.insert(name, translation_gen);
+                    let (z_min, z_max) = self.get_range_or_zero(gen, "z")?;
+                    let translation_gen3 = TranslationGen3::new(translation_gen.clone(), z_min, z_max);
+                    self.translation_gens.insert(name.clone(), translation_gen);
+                    self.translation_gens3.insert(name, translation_gen3);
                 },
                 "velocity" => {
                     let velocity_gen = self.parse_velocity_gen(gen)?;
-                    self.velocity_gens.insert(name, velocity_gen);
+                    let (dz_min, dz_max) = self.get_range_or_zero(gen, "dz")?;
+                    let velocity_gen3 = VelocityGen3::new(velocity_gen.clone(), dz_min, dz_max);
+                    self.velocity_gens.insert(name.clone(), velocity_gen);
+                    self.velocity_gens3.insert(name, velocity_gen3);
                 },
                 "rotation" => {
                     let rotation_gen = self.parse_rotation_gen(gen)?;
-                    self.rotation_gens.insert(name, rotation_gen);
+                    let axis = self.get_axis_or_z(gen, "axis")?;
+                    let rotation_gen3 = AxisRotationGen::new(axis, rotation_gen.clone());
+                    self.rotation_gens.insert(name.clone(), rotation_gen);
+                    self.rotation_gens3.insert(name, rotation_gen3);
                 },
                 _ => return Err(InvalidValue(gen_type)),
             };
@@ -285,7 +357,7 @@ impl Loader {
 
     /// Returns the named rotation gen if it exists, else creates one from concrete values
     /// else provides default value of 0.0.
-    fn parse_rotation(&self, object: &Yaml) -> Result<Box<dyn Generator<Output=f32>>> {
+    fn parse_rotation(&self, object: &Yaml) -> Result<Box<dyn Generator<Output=Float>>> {
         // check for gen reference
         if let Ok(gen_name) = self.get_string(object, "r") {
             // look it up
@@ -300,10 +372,99 @@ impl Loader {
         Ok(Box::new(Repeater::new(rotation.to_radians())))
     }
 
+    /// Returns the named 3D translation gen if it exists, else creates one
+    /// from concrete values, else provides default value of (0.0, 0.0, 0.0).
+    fn parse_translation3(&self, object: &Yaml) -> Result<Box<dyn Generator<Output=Point3>>> {
+        // check for gen reference
+        if let Ok(gen_name) = self.get_string(object, "t") {
+            // look it up
+            return match self.translation_gens3.get(gen_name.as_str()) {
+                None => Err(UnknownReference(gen_name)),
+                Some(gen) => Ok(Box::new(gen.clone())),
+            }
+        }
+
+        // get concrete values
+        let translation = match self.get_value(object, "t") {
+            Err(error) => match error {
+                // provide default
+                MissingKey(_) => Point3::zero(),
+                _ => return Err(error),
+            },
+            Ok(value) => {
+                let x = self.get_real(value, "x")?;
+                let y = self.get_real(value, "y")?;
+                let z = self.get_real_or(value, "z", 0.0)?;
+                Point3::new(x, y, z)
+            },
+        };
+
+        Ok(Box::new(Repeater::new(translation)))
+    }
+
+    /// Returns the named 3D velocity gen if it exists, else creates one from
+    /// concrete values, else provides default value of (0.0, 0.0, 0.0).
+    fn parse_velocity3(&self, object: &Yaml) -> Result<Box<dyn Generator<Output=Vector3>>> {
+        // check for gen reference
+        if let Ok(gen_name) = self.get_string(object, "v") {
+            // look it up
+            return match self.velocity_gens3.get(gen_name.as_str()) {
+                None => Err(UnknownReference(gen_name)),
+                Some(gen) => Ok(Box::new(gen.clone())),
+            }
+        }
+
+        // get concrete values
+        let velocity = match self.get_value(object, "v") {
+            Err(error) => match error {
+                // provide default
+                MissingKey(_) => Vector3::zero(),
+                _ => return Err(error),
+            },
+            Ok(value) => {
+                let dx = self.get_real(value, "dx")?;
+                let dy = self.get_real(value, "dy")?;
+                let dz = self.get_real_or(value, "dz", 0.0)?;
+                Vector3::new(dx, dy, dz)
+            },
+        };
+
+        Ok(Box::new(Repeater::new(velocity)))
+    }
+
+    /// Returns the named 3D rotation gen if it exists, else creates one from
+    /// concrete values, else provides the identity. A concrete rotation is
+    /// either an angle about the z axis, or an angle about the given axis.
+    fn parse_rotation3(&self, object: &Yaml) -> Result<Box<dyn Generator<Output=Transformation3>>> {
+        // check for gen reference
+        if let Ok(gen_name) = self.get_string(object, "r") {
+            // look it up
+            return match self.rotation_gens3.get(gen_name.as_str()) {
+                None => Err(UnknownReference(gen_name)),
+                Some(gen) => Ok(Box::new(gen.clone())),
+            }
+        }
+
+        // get concrete values
+        let rotation = match self.get_value(object, "r") {
+            Ok(value) if value.as_hash().is_some() => {
+                let angle = self.get_real(value, "angle")?;
+                let axis = self.get_axis_or_z(value, "axis")?;
+                Transformation3::rotation(&axis, angle.to_radians())
+            },
+            _ => {
+                let angle = self.get_real_or(object, "r", 0.0)?;
+                Transformation3::rotation(&Vector3::new(0.0, 0.0, 1.0), angle.to_radians())
+            },
+        };
+
+        Ok(Box::new(Repeater::new(rotation)))
+    }
+
     // Body Parsing //////////////////////////////////////////////////////////
 
     /// Parses the given body description.
-    fn parse_body(&self, body: &Yaml) -> Result<(String, Vec<Node>)> {
+    fn parse_body(&self, body: &Yaml) -> Result<(String, Vec<Node<F>>)> {
         let name = self.get_string(body, "name")?;
         let num = self.get_int_or(body, "num", 1)?;
 
@@ -312,15 +473,12 @@ impl Loader {
         }
 
         let mut mass = self.parse_mass(body)?;
-        let mut trans = self.parse_translation(body)?;
-        let mut vel = self.parse_velocity(body)?;
-        let mut rot = self.parse_rotation(body)?;
+        let mut frame = F::parse(self, body)?;
 
-        let mut nodes: Vec<Node> = Vec::new();
+        let mut nodes: Vec<Node<F>> = Vec::new();
 
         for _ in 1..=num {
-            let tvr = TVR(trans.generate(), vel.generate(), rot.generate());
-            let node = Node::Body(tvr, mass.generate());
+            let node = Node::Body(frame.generate(), mass.generate());
             nodes.push(node);
         }
 
@@ -347,9 +505,14 @@ impl Loader {
             return match self.bodies.remove(name.as_str()) {
                 None => Err(UnknownReference(name)),
                 Some(bodies) => {
+                    let group = self.group_names.len();
+                    self.group_names.push(name);
+
                     let mut indices = vec![];
                     for body in bodies {
-                        indices.push(self.tree.add_node(body));
+                        let idx = self.tree.add_node(body);
+                        self.node_groups.insert(idx, group);
+                        indices.push(idx);
                     }
                     return Ok(indices)
                 },
@@ -357,13 +520,7 @@ impl Loader {
         }
 
         // transformation for the system
-        let tvr: TVR;
-        {
-            let t = self.parse_translation(system)?.generate();
-            let v = self.parse_velocity(system)?.generate();
-            let r = self.parse_rotation(system)?.generate();
-            tvr = TVR(t, v, r);
-        }
+        let tvr = F::parse(self, system)?.generate();
 
         // parse the subsystems
         let mut subsystems: Vec<Index> = vec![];
@@ -378,12 +535,31 @@ impl Loader {
     }
 }
 
+// Frame /////////////////////////////////////////////////////////////////////
+//
+// The translation, velocity and rotation of a node relative to its parent.
+// Frames are accumulated down the tree, and the body at a leaf is created
+// from its accumulated frame.
+
+pub trait Frame: Clone + Default {
+    type Body;
+
+    /// Returns a generator of frames described by the given object.
+    fn parse(loader: &Loader<Self>, object: &Yaml) -> Result<Box<dyn Generator<Output=Self>>>;
+
+    /// Returns the given frame relative to the parent frame self.
+    fn merge(&self, curr: &Self) -> Self;
+
+    fn body(self, mass: Mass) -> Self::Body;
+}
+
 // TVR ///////////////////////////////////////////////////////////////////////
 //
 // Small helper struct to contain Translation, Velocity, and Rotation data.
 
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Clone, PartialEq, Debug)]
-struct TVR(Point, Vector, f32);
+pub struct TVR(Point, Vector, Float);
 
 impl Default for TVR {
     fn default() -> Self {
@@ -391,6 +567,98 @@ impl Default for TVR {
     }
 }
 
+impl Frame for TVR {
+    type Body = Body;
+
+    fn parse(loader: &Loader<Self>, object: &Yaml) -> Result<Box<dyn Generator<Output=Self>>> {
+        Ok(Box::new(TVRGen(
+            loader.parse_translation(object)?,
+            loader.parse_velocity(object)?,
+            loader.parse_rotation(object)?,
+        )))
+    }
+
+    // First we accumulate rotation, so we can rotate the new position and
+    // velocity. These new values are added to the previous values, so that
+    // they are accumulated too.
+    fn merge(&self, curr: &TVR) -> TVR {
+        let rotation = self.2 + curr.2;
+        let transform = Transformation::rotation(rotation);
+        let (position, velocity) = (&transform * curr.0.clone(), &transform * curr.1.clone());
+        let (position, velocity) = (self.0.clone() + position, self.1.clone() + velocity);
+        TVR(position, velocity, rotation)
+    }
+
+    fn body(self, mass: Mass) -> Body {
+        Body::new(mass.value(), self.0, self.1)
+    }
+}
+
+struct TVRGen(
+    Box<dyn Generator<Output=Point>>,
+    Box<dyn Generator<Output=Vector>>,
+    Box<dyn Generator<Output=Float>>,
+);
+
+impl Generator for TVRGen {
+    type Output = TVR;
+    fn generate(&mut self) -> Self::Output {
+        TVR(self.0.generate(), self.1.generate(), self.2.generate())
+    }
+}
+
+// TVR3 //////////////////////////////////////////////////////////////////////
+//
+// Translation, Velocity, and Rotation data in 3D space. Rotations about
+// different axes don't commute, so the accumulated rotation is the product
+// of the rotations, with the rotation of the child applied first.
+
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Clone, PartialEq, Debug)]
+pub struct TVR3(Point3, Vector3, Transformation3);
+
+impl Default for TVR3 {
+    fn default() -> Self {
+        TVR3(Point3::zero(), Vector3::zero(), Transformation3::identity())
+    }
+}
+
+impl Frame for TVR3 {
+    type Body = Body3;
+
+    fn parse(loader: &Loader<Self>, object: &Yaml) -> Result<Box<dyn Generator<Output=Self>>> {
+        Ok(Box::new(TVR3Gen(
+            loader.parse_translation3(object)?,
+            loader.parse_velocity3(object)?,
+            loader.parse_rotation3(object)?,
+        )))
+    }
+
+    fn merge(&self, curr: &TVR3) -> TVR3 {
+        let rotation = &self.2 * &curr.2;
+        let (position, velocity) = (&rotation * curr.0.clone(), &rotation * curr.1.clone());
+        let (position, velocity) = (self.0.clone() + position, self.1.clone() + velocity);
+        TVR3(position, velocity, rotation)
+    }
+
+    fn body(self, mass: Mass) -> Body3 {
+        Body3::new(mass.value(), self.0, self.1)
+    }
+}
+
+struct TVR3Gen(
+    Box<dyn Generator<Output=Point3>>,
+    Box<dyn Generator<Output=Vector3>>,
+    Box<dyn Generator<Output=Transformation3>>,
+);
+
+impl Generator for TVR3Gen {
+    type Output = TVR3;
+    fn generate(&mut self) -> Self::Output {
+        TVR3(self.0.generate(), self.1.generate(), self.2.generate())
+    }
+}
+
 // Node //////////////////////////////////////////////////////////////////////
 //
 // Each node in the tree has the three tvr properties. System nodes contain a
@@ -399,9 +667,9 @@ impl Default for TVR {
 type Index = usize;
 
 #[derive(Clone, PartialEq, Debug)]
-enum Node {
-    System(TVR, Vec<Index>),
-    Body(TVR, Mass),
+enum Node<F> {
+    System(F, Vec<Index>),
+    Body(F, Mass),
 }
 
 // DistributionTree //////////////////////////////////////////////////////////
@@ -411,38 +679,28 @@ enum Node {
 // to create the Body objects given the various data stored at each node.
 
 #[derive(Default, Debug)]
-struct DistributionTree {
-    nodes: Vec<Node>
+struct DistributionTree<F> {
+    nodes: Vec<Node<F>>
 }
 
-impl DistributionTree {
+impl<F: Frame> DistributionTree<F> {
     /// Adds the given node to the tree and return its index.
-    fn add_node(&mut self, node: Node) -> Index {
+    fn add_node(&mut self, node: Node<F>) -> Index {
         self.nodes.push(node);
         self.nodes.len() - 1
     }
 
-    /// Traverses the tree and returns the bodies derived from it.
-    fn bodies(&mut self) -> Vec<Body> {
+    /// Traverses the tree and returns the bodies derived from it, each with
+    /// the index of its node.
+    fn bodies(&mut self) -> Vec<(Index, F::Body)> {
         // start at the root node
-        let start = vec![self.nodes.len() - 1];
+        let start = [self.nodes.len() - 1];
         // stores the children indices and tvr data for visited nodes.
-        let mut stack: Vec<(Iter<Index>, TVR)> = vec![];
-        let mut bodies: Vec<Body> = vec![];
+        let mut stack: Vec<(Iter<Index>, F)> = vec![];
+        let mut bodies: Vec<(Index, F::Body)> = vec![];
 
         // As we descend the tree, we must accumulate the tvr data.
-        // First we accumulate rotation, so we can rotate the new position
-        // and velocity. These new values are added to the previous values,
-        // so that they are accumulated too.
-        let merge = |prev: &TVR, curr: &TVR| -> TVR {
-            let rotation = prev.2 + curr.2;
-            let transform = Transformation::rotation(rotation);
-            let (position, velocity) = (&transform * curr.0.clone(), &transform * curr.1.clone());
-            let (position, velocity) = (prev.0.clone() + position, prev.1.clone() + velocity);
-            TVR(position, velocity, rotation)
-        };
-
-        stack.push((start.iter(), TVR::default()));
+        stack.push((start.iter(), F::default()));
 
         // there are potentially systems to inspect
         while let Some((mut systems, prev_tvr)) = stack.pop() {
@@ -451,13 +709,13 @@ impl DistributionTree {
                 match &self.nodes[*next] {
                     // it's a body
                     Node::Body(curr_tvr, mass) => {
-                        let new_tvr = merge(&prev_tvr, curr_tvr);
-                        bodies.push(Body::new(mass.value(), new_tvr.0, new_tvr.1));
+                        let new_tvr = prev_tvr.merge(curr_tvr);
+                        bodies.push((*next, new_tvr.body(*mass)));
                         stack.push((systems, prev_tvr));
                     },
                     // it's a system
                     Node::System(curr_tvr, subsystems) => {
-                        let new_tvr = merge(&prev_tvr, curr_tvr);
+                        let new_tvr = prev_tvr.merge(curr_tvr);
                         stack.push((systems, prev_tvr));
                         stack.push((subsystems.iter(), new_tvr));
                     },
@@ -512,11 +770,12 @@ impl fmt::Display for Error {
 
 #[cfg(test)]
 mod tests {
-    use std::f32::consts::PI;
+    use crate::consts::PI;
 
     use yaml_rust::Yaml;
     use yaml_rust::YamlLoader;
 
+    use crate::geometry::three::{Point3, Vector3};
     use crate::geometry::Point;
     use crate::geometry::Vector;
     use crate::physics::Mass;
@@ -581,13 +840,13 @@ mod tests {
     fn loader_get_real() {
         // given
         let sut = Loader::new();
-        let object = yaml("num: 3.14");
+        let object = yaml("num: 2.5");
 
         // when
         let result = sut.get_real(&object, "num").unwrap();
 
         // then
-        assert_eq!(3.14, result);
+        assert_eq!(2.5, result);
     }
 
     #[test]
@@ -607,13 +866,13 @@ mod tests {
     fn loader_get_real_or() {
         // given
         let sut = Loader::new();
-        let object = yaml("foo: 2.17");
+        let object = yaml("foo: 1.5");
 
         // when
-        let result = sut.get_real_or(&object, "bar", 3.14).unwrap();
+        let result = sut.get_real_or(&object, "bar", 2.5).unwrap();
 
         // then
-        assert_eq!(3.14, result);
+        assert_eq!(2.5, result);
     }
 
     #[test]
@@ -712,16 +971,16 @@ mod tests {
         assert!(result.is_ok());
 
         assert_eq!(1, sut.mass_gens.len());
-        assert!(sut.mass_gens.get("m").is_some());
+        assert!(sut.mass_gens.contains_key("m"));
 
         assert_eq!(1, sut.translation_gens.len());
-        assert!(sut.translation_gens.get("t").is_some());
+        assert!(sut.translation_gens.contains_key("t"));
 
         assert_eq!(1, sut.velocity_gens.len());
-        assert!(sut.velocity_gens.get("v").is_some());
+        assert!(sut.velocity_gens.contains_key("v"));
 
         assert_eq!(1, sut.rotation_gens.len());
-        assert!(sut.rotation_gens.get("r").is_some());
+        assert!(sut.rotation_gens.contains_key("r"));
     }
 
     #[test]
@@ -865,7 +1124,7 @@ mod tests {
 
         // then
         let mass = result.generate().value();
-        assert!(mass >= 6.0 && mass <= 6.3);
+        assert!((6.0..=6.3).contains(&mass));
     }
 
     #[test]
@@ -1028,7 +1287,7 @@ mod tests {
 
         // then
         let rotation = result.generate();
-        assert!(rotation >= 1.0 && rotation <= 2.0);
+        assert!((1.0..=2.0).contains(&rotation));
     }
 
     #[test]
@@ -1365,6 +1624,115 @@ mod tests {
         assert_eq!(Vector::new(2.0, 5.0), moon.velocity);
     }
 
+    #[test]
+    fn loader_load_groups() {
+        // given
+        let mut sut = Loader::new();
+        let input = "
+        bodies:
+          - {name: sun, m: 100.0}
+          - {name: planets, num: 3, m: 1.0}
+          - {name: moon, m: 0.1}
+
+        systems:
+          - {name: sun}
+          - systems:
+              - name: moon
+              - name: planets
+        ";
+
+        // when
+        let result = sut.load(String::from(input)).unwrap();
+
+        // then
+        assert_eq!(5, result.len());
+        assert_eq!(&[0, 1, 2, 2, 2], sut.groups());
+        assert_eq!(&["sun", "moon", "planets"], sut.group_names());
+        assert_eq!(0.1, result[1].mass.value());
+    }
+
+    #[test]
+    fn loader_load_3d_bodies() {
+        // given
+        let mut sut = Loader::new_3d();
+        let input = "
+        bodies:
+          - {name: sun, m: 100.0}
+          - {name: earth, m: 10.0, t: {x: 0.0, y: 10.0, z: 5.0}, v: {dx: 2.0, dy: 0.0, dz: 1.0}}
+
+        systems:
+          - {name: sun}
+          -
+            t: {x: 100.0, y: 0.0}
+            r: {angle: 90.0, axis: {x: 1.0}}
+            systems:
+              - name: earth
+        ";
+
+        // when
+        let result = sut.load(String::from(input)).unwrap();
+
+        // then
+        assert_eq!(2, result.len());
+        assert_eq!(Point3::zero(), result[0].position);
+
+        // the earth is rotated about the x axis
+        let earth = &result[1];
+        assert_eq!(10.0, earth.mass.value());
+        assert_close!(100.0, earth.position.x);
+        assert_close!(-5.0, earth.position.y);
+        assert_close!(10.0, earth.position.z);
+        assert_close!(2.0, earth.velocity.dx);
+        assert_close!(-1.0, earth.velocity.dy);
+        assert_close!(0.0, earth.velocity.dz);
+    }
+
+    #[test]
+    fn loader_load_3d_gens() {
+        // given
+        let mut sut = Loader::new_3d();
+        let input = "
+        gens:
+          - {name: t, type: translation, x: {min: 1.0, max: 2.0}, y: {min: 1.0, max: 2.0}, z: {min: 3.0, max: 4.0}}
+          - {name: v, type: velocity, dx: {min: 0.0, max: 0.0}, dy: {min: 0.0, max: 0.0}}
+          - {name: r, type: rotation, min: 0.0, max: 360.0, axis: {x: 0.0, y: 0.0, z: 1.0}}
+
+        bodies:
+          - {name: stars, num: 10, m: 1.0, t: t, v: v, r: r}
+
+        systems:
+          - {name: stars}
+        ";
+
+        // when
+        let result = sut.load(String::from(input)).unwrap();
+
+        // then rotations about the z axis keep the height
+        assert_eq!(10, result.len());
+        for body in result {
+            assert!(body.position.z >= 3.0 && body.position.z <= 4.0);
+            assert_eq!(Vector3::zero(), body.velocity);
+        }
+    }
+
+    #[test]
+    fn loader_load_3d_config_in_2d() {
+        // given
+        let mut sut = Loader::new();
+        let input = "
+        bodies:
+          - {name: earth, m: 10.0, t: {x: 1.0, y: 2.0, z: 5.0}}
+        systems:
+          - {name: earth}
+        ";
+
+        // when
+        let result = sut.load(String::from(input)).unwrap();
+
+        // then
+        assert_eq!(Point::new(1.0, 2.0), result[0].position);
+    }
+
     #[test]
     fn loader_load_no_gens() {
         // given
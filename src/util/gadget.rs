@@ -0,0 +1,447 @@
+use std::io::{self, Read, Write};
+
+use crate::geometry::three::{Point3, Vector3};
+use crate::Float;
+
+use super::binary::{self, invalid, narrow, wide, Order, ParticleFile, ParticleWriter};
+
+// Gadget ////////////////////////////////////////////////////////////////////
+//
+// A snapshot in the binary format 1 of Gadget-2, to exchange initial
+// conditions and results with other codes. The file is a sequence of
+// Fortran records, each enclosed by its size in bytes as a 4 byte integer:
+//
+//   header      256 bytes: particle counts and masses of the 6 types, the
+//               time, cosmological parameters, padded with zeros
+//   positions   x, y, z per particle
+//   velocities  vx, vy, vz per particle
+//   ids         integer per particle
+//   masses      per particle of the types without a mass in the header
+//
+// Particles are ordered by type. Floats are single precision, or double
+// precision if the records are twice as large, and ids are 4 or 8 bytes
+// wide in the same way. Files are read in either byte order, and written
+// little endian with single precision floats and 4 byte ids, which every
+// reader accepts. Any records after the masses, such as the properties of
+// gas particles, are ignored.
+//
+// Newton has no particle types, so bodies are written as halo particles,
+// which need no further records. Values are not converted between units,
+// and 2D bodies lie in the plane z = 0.
+
+/// The size of the header record.
+const HEADER_SIZE: usize = 256;
+
+/// The number of particle types.
+const TYPES: usize = 6;
+
+/// The type bodies are written as.
+const HALO: usize = 1;
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Gadget {
+    pub time: Float,
+    pub ids: Vec<usize>,
+    pub masses: Vec<Float>,
+    pub positions: Vec<Point3>,
+    pub velocities: Vec<Vector3>,
+}
+
+impl ParticleFile for Gadget {
+    fn from_particles(time: Float, ids: &[usize], masses: &[Float],
+                      positions: Vec<Point3>, velocities: Vec<Vector3>) -> Gadget {
+        Gadget { time, ids: ids.to_vec(), masses: masses.to_vec(), positions, velocities }
+    }
+
+    fn masses(&self) -> &[Float] {
+        &self.masses
+    }
+
+    fn positions(&self) -> &[Point3] {
+        &self.positions
+    }
+
+    fn velocities(&self) -> &[Vector3] {
+        &self.velocities
+    }
+
+    fn read<R: Read>(input: R) -> io::Result<Gadget> {
+        let mut records = Records::open(input)?;
+
+        let header = records.next(&[HEADER_SIZE])?;
+        let order = records.order;
+        let counts: Vec<usize> = (0..TYPES).map(|t| order.u32(&header[4 * t..]) as usize).collect();
+        let table: Vec<f64> = (0..TYPES).map(|t| order.f64(&header[24 + 8 * t..])).collect();
+        let time = order.f64(&header[72..]);
+        let files = order.u32(&header[124..]);
+        if files > 1 {
+            return Err(invalid("Snapshots split into several files aren't supported."));
+        }
+
+        let n: usize = counts.iter().sum();
+        let positions = records.floats(n * 3)?;
+        let velocities = records.floats(n * 3)?;
+        let ids = records.ids(n)?;
+
+        // types without a mass in the header have one per particle
+        let variable: usize = (0..TYPES).filter(|&t| table[t] == 0.0).map(|t| counts[t]).sum();
+        let mut read = if variable > 0 { records.floats(variable)? } else { vec![] }.into_iter();
+        let mut masses = Vec::with_capacity(n);
+        for t in 0..TYPES {
+            for _ in 0..counts[t] {
+                let mass = if table[t] == 0.0 { read.next().unwrap() } else { narrow(table[t]) };
+                masses.push(binary::positive(mass)?);
+            }
+        }
+
+        Ok(Gadget {
+            time: narrow(time),
+            ids,
+            masses,
+            positions: positions.chunks(3).map(|p| Point3::new(p[0], p[1], p[2])).collect(),
+            velocities: velocities.chunks(3).map(|v| Vector3::new(v[0], v[1], v[2])).collect(),
+        })
+    }
+
+    fn write<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        self.write_ordered(out, Order::Little)
+    }
+
+    /// Named as Gadget names its snapshots.
+    fn file_name(frame: usize) -> String {
+        format!("snapshot_{:03}", frame)
+    }
+}
+
+impl Gadget {
+    /// The number of particles.
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    fn write_ordered<W: Write>(&self, out: &mut W, order: Order) -> io::Result<()> {
+        let n = self.len();
+        if n > u32::MAX as usize || self.ids.iter().any(|&id| id > u32::MAX as usize) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Too many bodies for 4 byte ids."));
+        }
+
+        let mut header = Vec::with_capacity(HEADER_SIZE);
+        for t in 0..TYPES {
+            header.extend(&order.put_u32(if t == HALO { n as u32 } else { 0 }));
+        }
+        for _ in 0..TYPES {
+            header.extend(&order.put_f64(0.0));
+        }
+        header.extend(&order.put_f64(wide(self.time)));
+        header.extend(&order.put_f64(0.0));                 // redshift
+        header.extend(&[0; 8]);                             // star formation and feedback flags
+        for t in 0..TYPES {
+            header.extend(&order.put_u32(if t == HALO { n as u32 } else { 0 }));
+        }
+        header.extend(&[0; 4]);                             // cooling flag
+        header.extend(&order.put_u32(1));                   // files
+        header.extend(&order.put_f64(0.0));                 // box size
+        header.extend(&order.put_f64(0.0));                 // omega 0
+        header.extend(&order.put_f64(0.0));                 // omega lambda
+        header.extend(&order.put_f64(1.0));                 // hubble parameter
+        header.resize(HEADER_SIZE, 0);
+        write_record(out, order, &header)?;
+
+        let mut positions = Vec::with_capacity(12 * n);
+        let mut velocities = Vec::with_capacity(12 * n);
+        let mut ids = Vec::with_capacity(4 * n);
+        let mut masses = Vec::with_capacity(4 * n);
+        for idx in 0..n {
+            let (p, v) = (&self.positions[idx], &self.velocities[idx]);
+            for value in [p.x, p.y, p.z].iter() {
+                positions.extend(&order.put_f32(*value));
+            }
+            for value in [v.dx, v.dy, v.dz].iter() {
+                velocities.extend(&order.put_f32(*value));
+            }
+            ids.extend(&order.put_u32(self.ids[idx] as u32));
+            masses.extend(&order.put_f32(self.masses[idx]));
+        }
+        write_record(out, order, &positions)?;
+        write_record(out, order, &velocities)?;
+        write_record(out, order, &ids)?;
+        if n > 0 {
+            write_record(out, order, &masses)?;
+        }
+        Ok(())
+    }
+}
+
+// Records ///////////////////////////////////////////////////////////////////
+//
+// Reads the Fortran records of a snapshot. The byte order is that in which
+// the size of the first record, the header, reads 256. The size of each
+// record is checked against the sizes it may have before it is read.
+
+fn write_record<W: Write>(out: &mut W, order: Order, bytes: &[u8]) -> io::Result<()> {
+    let size = order.put_u32(bytes.len() as u32);
+    out.write_all(&size)?;
+    out.write_all(bytes)?;
+    out.write_all(&size)
+}
+
+struct Records<R: Read> {
+    input: R,
+    order: Order,
+    /// The size of the next record, if already read.
+    size: Option<usize>,
+}
+
+impl<R: Read> Records<R> {
+    fn open(mut input: R) -> io::Result<Records<R>> {
+        let mut size = [0; 4];
+        input.read_exact(&mut size)?;
+        let order = if Order::Little.u32(&size) as usize == HEADER_SIZE {
+            Order::Little
+        } else if Order::Big.u32(&size) as usize == HEADER_SIZE {
+            Order::Big
+        } else {
+            return Err(invalid("Not a Gadget-2 format 1 snapshot."));
+        };
+        Ok(Records { input, order, size: Some(HEADER_SIZE) })
+    }
+
+    /// Reads the next record, which must have one of the given sizes.
+    fn next(&mut self, sizes: &[usize]) -> io::Result<Vec<u8>> {
+        let size = match self.size.take() {
+            Some(size) => size,
+            None => self.read_size()?,
+        };
+        if !sizes.contains(&size) {
+            return Err(invalid(&format!("Expected a record of {:?} bytes, got {}.", sizes, size)));
+        }
+        let mut bytes = vec![0; size];
+        self.input.read_exact(&mut bytes)?;
+        if self.read_size()? != size {
+            return Err(invalid("Record sizes don't match."));
+        }
+        Ok(bytes)
+    }
+
+    fn read_size(&mut self) -> io::Result<usize> {
+        let mut size = [0; 4];
+        self.input.read_exact(&mut size)?;
+        Ok(self.order.u32(&size) as usize)
+    }
+
+    /// Reads a record of the given number of floats, in either precision.
+    fn floats(&mut self, count: usize) -> io::Result<Vec<Float>> {
+        let bytes = self.next(&[4 * count, 8 * count])?;
+        let order = self.order;
+        if bytes.len() == 4 * count {
+            Ok(bytes.chunks(4).map(|value| binary::widen(order.f32(value))).collect())
+        } else {
+            Ok(bytes.chunks(8).map(|value| narrow(order.f64(value))).collect())
+        }
+    }
+
+    /// Reads a record of the given number of 4 or 8 byte ids.
+    fn ids(&mut self, count: usize) -> io::Result<Vec<usize>> {
+        let bytes = self.next(&[4 * count, 8 * count])?;
+        let order = self.order;
+        if bytes.len() == 4 * count {
+            Ok(bytes.chunks(4).map(|value| order.u32(value) as usize).collect())
+        } else {
+            Ok(bytes.chunks(8).map(|value| order.u64(value) as usize).collect())
+        }
+    }
+}
+
+// GadgetWriter //////////////////////////////////////////////////////////////
+//
+// A sink writing each snapshot as a Gadget-2 snapshot in the specified
+// directory, named `snapshot_N` after the frame as Gadget does. The
+// directory is created with the first file.
+
+pub type GadgetWriter = ParticleWriter<Gadget>;
+
+// Tests /////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io::Cursor;
+
+    use crate::util::binary::load_from_path;
+    use crate::util::sink::{Sink, Snapshot};
+
+    use super::*;
+
+    fn gadget() -> Gadget {
+        Gadget {
+            time: 2.5,
+            ids: vec![7, 3],
+            masses: vec![2.0, 0.5],
+            positions: vec![Point3::new(1.5, -2.0, 0.25), Point3::new(4.0, 8.0, 0.0)],
+            velocities: vec![Vector3::new(0.25, 3.0, -1.0), Vector3::new(-1.0, 0.0, 0.5)],
+        }
+    }
+
+    /// Encloses the given bytes in a little endian record.
+    fn record(bytes: &[u8]) -> Vec<u8> {
+        let mut result = vec![];
+        write_record(&mut result, Order::Little, bytes).unwrap();
+        result
+    }
+
+    #[test]
+    fn gadget_writes_records() {
+        // given
+        let sut = gadget();
+        let mut result = vec![];
+
+        // when
+        sut.write(&mut result).unwrap();
+
+        // then header, positions, velocities, ids and masses
+        assert_eq!(8 + 256 + 2 * (8 + 24) + (8 + 8) + (8 + 8), result.len());
+        assert_eq!(&256u32.to_le_bytes(), &result[..4]);
+        assert_eq!(&2u32.to_le_bytes(), &result[8..12]);
+        assert_eq!(&2.5f64.to_le_bytes(), &result[4 + 72..4 + 80]);
+
+        let positions = 8 + 256;
+        assert_eq!(&24u32.to_le_bytes(), &result[positions..positions + 4]);
+        assert_eq!(&1.5f32.to_le_bytes(), &result[positions + 4..positions + 8]);
+
+        let ids = positions + 2 * (8 + 24);
+        assert_eq!(&7u32.to_le_bytes(), &result[ids + 4..ids + 8]);
+    }
+
+    #[test]
+    fn gadget_round_trip() {
+        // given
+        let mut bytes = vec![];
+        gadget().write(&mut bytes).unwrap();
+
+        // when
+        let result = Gadget::read(Cursor::new(bytes)).unwrap();
+
+        // then
+        assert_eq!(gadget(), result);
+    }
+
+    #[test]
+    fn gadget_reads_big_endian() {
+        // given
+        let mut bytes = vec![];
+        gadget().write_ordered(&mut bytes, Order::Big).unwrap();
+
+        // when
+        let result = Gadget::read(Cursor::new(bytes)).unwrap();
+
+        // then
+        assert_eq!(gadget(), result);
+    }
+
+    #[test]
+    fn gadget_reads_mass_table_and_double_precision() {
+        // given a gas particle of variable mass and two stars of mass 3
+        let mut header = vec![0; HEADER_SIZE];
+        header[..4].copy_from_slice(&1u32.to_le_bytes());
+        header[16..20].copy_from_slice(&2u32.to_le_bytes());
+        header[24 + 32..24 + 40].copy_from_slice(&3.0f64.to_le_bytes());
+
+        let floats: Vec<u8> = (0..9).flat_map(|i| (i as f64).to_le_bytes().to_vec()).collect();
+        let ids: Vec<u8> = (0..3).flat_map(|i| (i as u64 + 10).to_le_bytes().to_vec()).collect();
+
+        let mut bytes = record(&header);
+        bytes.extend(record(&floats));
+        bytes.extend(record(&floats));
+        bytes.extend(record(&ids));
+        bytes.extend(record(&0.5f32.to_le_bytes()));
+        bytes.extend(record(&[0; 4]));                // internal energy of the gas
+
+        // when
+        let result = Gadget::read(Cursor::new(bytes)).unwrap();
+
+        // then
+        assert_eq!(vec![10, 11, 12], result.ids);
+        assert_eq!(vec![0.5, 3.0, 3.0], result.masses);
+        assert_eq!(Point3::new(6.0, 7.0, 8.0), result.positions[2]);
+        assert_eq!(Vector3::new(3.0, 4.0, 5.0), result.velocities[1]);
+    }
+
+    #[test]
+    fn gadget_rejects_other_files() {
+        // given
+        let bytes = b"# vtk DataFile Version 3.0".to_vec();
+
+        // when
+        let result = Gadget::read(Cursor::new(bytes));
+
+        // then
+        assert_eq!(io::ErrorKind::InvalidData, result.unwrap_err().kind());
+    }
+
+    #[test]
+    fn gadget_rejects_mismatched_records() {
+        // given
+        let mut bytes = vec![];
+        gadget().write(&mut bytes).unwrap();
+        let end = 8 + 256 + 28;
+        bytes[end..end + 4].copy_from_slice(&20u32.to_le_bytes());
+
+        // when
+        let result = Gadget::read(Cursor::new(bytes));
+
+        // then
+        assert_eq!(io::ErrorKind::InvalidData, result.unwrap_err().kind());
+    }
+
+    #[test]
+    fn gadget_rejects_records_larger_than_the_header_allows() {
+        // given a positions record claiming 4 GB
+        let mut bytes = vec![];
+        gadget().write(&mut bytes).unwrap();
+        let positions = 8 + 256;
+        bytes[positions..positions + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+
+        // when
+        let result = Gadget::read(Cursor::new(bytes));
+
+        // then
+        assert_eq!(io::ErrorKind::InvalidData, result.unwrap_err().kind());
+    }
+
+    #[test]
+    fn gadget_rejects_nan_masses() {
+        // given
+        let mut sut = gadget();
+        sut.masses[1] = Float::NAN;
+        let mut bytes = vec![];
+        sut.write(&mut bytes).unwrap();
+
+        // when
+        let result = Gadget::read(Cursor::new(bytes));
+
+        // then
+        assert_eq!(io::ErrorKind::InvalidData, result.unwrap_err().kind());
+    }
+
+    #[test]
+    fn gadget_writer_writes_snapshots() {
+        // given
+        let bodies = gadget().bodies();
+        let mut sut = GadgetWriter::new("temp-gadget");
+
+        // when
+        Sink::write(&mut sut, &Snapshot::new(4, 5.0, &bodies)).unwrap();
+
+        // then
+        let result = load_from_path::<Gadget>("temp-gadget/snapshot_004").unwrap();
+        assert_eq!(bodies.len(), result.len());
+        assert_eq!(bodies[1].position, result[1].position);
+
+        // after
+        fs::remove_dir_all("temp-gadget").expect("Error cleaning up test.");
+    }
+}
@@ -1,14 +1,15 @@
-use std::f32::consts::PI;
-
 use rand::distributions::Uniform;
 use rand::Rng;
 use rand::thread_rng;
 use rand::ThreadRng;
 
+use crate::consts::PI;
 use crate::geometry::Point;
 use crate::geometry::Vector;
-use crate::geometry::util::Transformation;
+use crate::geometry::three::{Point3, Vector3};
+use crate::geometry::util::{Transformation, Transformation3};
 use crate::physics::Mass;
+use crate::Float;
 
 // Generator /////////////////////////////////////////////////////////////////
 //
@@ -43,16 +44,16 @@ impl<T> Generator for Repeater<T> where T: Clone {
 
 // UniformGen ////////////////////////////////////////////////////////////////
 //
-// Uniformly generates random f32 within a closed range of values.
+// Uniformly generates random Float within a closed range of values.
 
 #[derive(Clone, Debug)]
 pub struct UniformGen {
-    distribution: Uniform<f32>,
+    distribution: Uniform<Float>,
     rand: ThreadRng,
 }
 
 impl UniformGen {
-    pub fn new(min: f32, max: f32) -> UniformGen {
+    pub fn new(min: Float, max: Float) -> UniformGen {
         UniformGen {
             distribution: Uniform::new_inclusive(min, max),
             rand: thread_rng(),
@@ -61,7 +62,7 @@ impl UniformGen {
 }
 
 impl Generator for UniformGen {
-    type Output = f32;
+    type Output = Float;
     fn generate(&mut self) -> Self::Output {
         self.rand.sample(self.distribution)
     }
@@ -78,7 +79,7 @@ pub struct TranslationGen {
 }
 
 impl TranslationGen {
-    pub fn new(x_min: f32, x_max: f32, y_min: f32, y_max: f32) -> TranslationGen {
+    pub fn new(x_min: Float, x_max: Float, y_min: Float, y_max: Float) -> TranslationGen {
         TranslationGen {
             x: UniformGen::new(x_min, x_max),
             y: UniformGen::new(y_min, y_max),
@@ -104,7 +105,7 @@ pub struct MassGen {
 }
 
 impl MassGen {
-    pub fn new(min: f32, max: f32) -> MassGen {
+    pub fn new(min: Float, max: Float) -> MassGen {
         if min <= 0.0 || max <= 0.0 {
             panic!("MassGen requires positive range. Got [{}, {}]", min, max);
         }
@@ -130,16 +131,16 @@ pub struct RotationGen {
 }
 
 impl RotationGen {
-    pub fn new_radians(min: f32, max: f32) -> RotationGen {
+    pub fn new_radians(min: Float, max: Float) -> RotationGen {
         let (low, high) = RotationGen::normalize(min, max);
         RotationGen { gen: UniformGen::new(low, high) }
     }
 
-    pub fn new_degrees(min: f32, max: f32) -> RotationGen {
+    pub fn new_degrees(min: Float, max: Float) -> RotationGen {
         RotationGen::new_radians(min.to_radians(), max.to_radians())
     }
 
-    fn normalize(mut min: f32, mut max: f32) -> (f32, f32) {
+    fn normalize(mut min: Float, mut max: Float) -> (Float, Float) {
         let pi_2 = 2.0 * PI;
 
         // add 2PI to min until it it exceeds 0
@@ -153,7 +154,7 @@ impl RotationGen {
 }
 
 impl Generator for RotationGen {
-    type Output = f32;
+    type Output = Float;
     fn generate(&mut self) -> Self::Output {
         self.gen.generate()
     }
@@ -170,7 +171,7 @@ pub struct VelocityGen {
 }
 
 impl VelocityGen {
-    pub fn new(dx_min: f32, dx_max: f32, dy_min: f32, dy_max: f32) -> VelocityGen {
+    pub fn new(dx_min: Float, dx_max: Float, dy_min: Float, dy_max: Float) -> VelocityGen {
         VelocityGen {
             dx: UniformGen::new(dx_min, dx_max),
             dy: UniformGen::new(dy_min, dy_max),
@@ -221,12 +222,88 @@ impl Generator for RadialGen {
     }
 }
 
+// TranslationGen3 ///////////////////////////////////////////////////////////
+//
+// Uniformly generates random Point3s within closed ranges.
+
+#[derive(Clone, Debug)]
+pub struct TranslationGen3 {
+    xy: TranslationGen,
+    z: UniformGen,
+}
+
+impl TranslationGen3 {
+    pub fn new(xy: TranslationGen, z_min: Float, z_max: Float) -> TranslationGen3 {
+        TranslationGen3 { xy, z: UniformGen::new(z_min, z_max) }
+    }
+}
+
+impl Generator for TranslationGen3 {
+    type Output = Point3;
+
+    fn generate(&mut self) -> Self::Output {
+        let xy = self.xy.generate();
+        Point3::new(xy.x, xy.y, self.z.generate())
+    }
+}
+
+// VelocityGen3 //////////////////////////////////////////////////////////////
+//
+// Uniformly generates random 3D velocities within closed ranges.
+
+#[derive(Clone, Debug)]
+pub struct VelocityGen3 {
+    dxy: VelocityGen,
+    dz: UniformGen,
+}
+
+impl VelocityGen3 {
+    pub fn new(dxy: VelocityGen, dz_min: Float, dz_max: Float) -> VelocityGen3 {
+        VelocityGen3 { dxy, dz: UniformGen::new(dz_min, dz_max) }
+    }
+}
+
+impl Generator for VelocityGen3 {
+    type Output = Vector3;
+
+    fn generate(&mut self) -> Self::Output {
+        let dxy = self.dxy.generate();
+        Vector3::new(dxy.dx, dxy.dy, self.dz.generate())
+    }
+}
+
+// AxisRotationGen ///////////////////////////////////////////////////////////
+//
+// Generates rotations about a fixed axis by random angles within a closed
+// range.
+
+#[derive(Clone, Debug)]
+pub struct AxisRotationGen {
+    axis: Vector3,
+    angle: RotationGen,
+}
+
+impl AxisRotationGen {
+    pub fn new(axis: Vector3, angle: RotationGen) -> AxisRotationGen {
+        AxisRotationGen { axis, angle }
+    }
+}
+
+impl Generator for AxisRotationGen {
+    type Output = Transformation3;
+
+    fn generate(&mut self) -> Self::Output {
+        Transformation3::rotation(&self.axis, self.angle.generate())
+    }
+}
+
 // Tests /////////////////////////////////////////////////////////////////////
 
 #[cfg(test)]
 mod tests {
-    use std::f32::consts::PI;
+    use crate::consts::PI;
 
+    use crate::geometry::three::{Point3, Vector3};
     use crate::geometry::Point;
     use crate::geometry::Vector;
     use crate::physics::Mass;
@@ -256,7 +333,7 @@ mod tests {
     fn uniform_gen_generates() {
         // given
         let mut sut = UniformGen::new(1.0, 2.0);
-        let within_range = |n: f32| n >= 1.0 && n <= 2.0;
+        let within_range = |n: Float| (1.0..=2.0).contains(&n);
 
         // then
         assert!(within_range(sut.generate()));
@@ -314,16 +391,16 @@ mod tests {
         // when
         let (low, high) = RotationGen::normalize(low, high);
 
-        // then
-        assert_eq!(-4.0840707, low);
-        assert_eq!(2.5132432, high);
+        // then up to the rounding of the inputs
+        assert!((-1.3 * PI - low).abs() < 1e-4, "Got {}", low);
+        assert!((0.8 * PI - high).abs() < 1e-4, "Got {}", high);
     }
 
     #[test]
     fn rotation_gen_generates() {
         // given
         let mut sut = RotationGen::new_radians(0.5 * PI, PI);
-        let within_range = |r| r >= 0.5 * PI && r <= PI;
+        let within_range = |r| (0.5 * PI..=PI).contains(&r);
 
         // then
         assert!(within_range(sut.generate()));
@@ -336,7 +413,7 @@ mod tests {
     fn rotation_gen_from_degrees_generates() {
         // given
         let mut sut = RotationGen::new_degrees(90.0, 180.0);
-        let within_range = |r| r >= 0.5 * PI && r <= PI;
+        let within_range = |r| (0.5 * PI..=PI).contains(&r);
 
         // then
         assert!(within_range(sut.generate()));
@@ -370,7 +447,7 @@ mod tests {
 
         let within_range = |(p, v): (Point, Vector)| {
             let dist_to_origin = p.distance_to(&Point::zero());
-            let dist_in_range = dist_to_origin >= 100.0 && dist_to_origin <= 200.0;
+            let dist_in_range = (100.0..=200.0).contains(&dist_to_origin);
             let y_is_positive = p.y >= 0.0;
             let v_min = v.magnitude() >= Vector::new(1.0, 2.0).magnitude();
             let v_max = v.magnitude() <= Vector::new(3.0, 4.0).magnitude();
@@ -408,4 +485,51 @@ mod tests {
             assert!(dot_product.abs() <= 0.0001);
         }
     }
+
+    #[test]
+    fn translation_gen3_generates() {
+        // given
+        let mut sut = TranslationGen3::new(TranslationGen::new(1.0, 2.0, 3.0, 4.0), 5.0, 6.0);
+        let within_range = |p: Point3| {
+            p.x >= 1.0 && p.x <= 2.0 && p.y >= 3.0 && p.y <= 4.0 && p.z >= 5.0 && p.z <= 6.0
+        };
+
+        // then
+        assert!(within_range(sut.generate()));
+        assert!(within_range(sut.generate()));
+        assert!(within_range(sut.generate()));
+        assert!(within_range(sut.generate()));
+    }
+
+    #[test]
+    fn velocity_gen3_generates() {
+        // given
+        let mut sut = VelocityGen3::new(VelocityGen::new(-1.0, 1.0, 2.0, 3.0), -3.0, -2.0);
+        let within_range = |v: Vector3| {
+            v.dx >= -1.0 && v.dx <= 1.0 && v.dy >= 2.0 && v.dy <= 3.0 && v.dz >= -3.0 && v.dz <= -2.0
+        };
+
+        // then
+        assert!(within_range(sut.generate()));
+        assert!(within_range(sut.generate()));
+        assert!(within_range(sut.generate()));
+        assert!(within_range(sut.generate()));
+    }
+
+    #[test]
+    fn axis_rotation_gen_keeps_axis_fixed() {
+        // given
+        let axis = Vector3::new(1.0, 2.0, 3.0);
+        let mut sut = AxisRotationGen::new(axis.clone(), RotationGen::new_radians(0.0, PI));
+
+        for _ in 0..4 {
+            // when
+            let result = &sut.generate() * axis.clone();
+
+            // then
+            assert!((result.dx - 1.0).abs() < 1e-5);
+            assert!((result.dy - 2.0).abs() < 1e-5);
+            assert!((result.dz - 3.0).abs() < 1e-5);
+        }
+    }
 }
@@ -0,0 +1,257 @@
+use std::io::{self, Write};
+
+use crate::geometry::Point;
+use crate::Float;
+
+// Image /////////////////////////////////////////////////////////////////////
+//
+// A minimal RGB raster, enough to render maps and frames without an imaging
+// library. Pixels are stored row by row from the top left corner, and are
+// written as 8 bit RGB PNG files.
+
+pub type Color = [u8; 3];
+
+pub const BLACK: Color = [0, 0, 0];
+pub const WHITE: Color = [255, 255, 255];
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pixels: Vec<u8>,
+}
+
+impl Image {
+    /// Creates a black image of the given size.
+    pub fn new(width: usize, height: usize) -> Image {
+        Image { width, height, pixels: vec![0; width * height * 3] }
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> Color {
+        let idx = self.offset(x, y);
+        [self.pixels[idx], self.pixels[idx + 1], self.pixels[idx + 2]]
+    }
+
+    /// Sets the pixel at the given column and row. Pixels outside of the
+    /// image are ignored.
+    pub fn set(&mut self, x: usize, y: usize, color: Color) {
+        if x < self.width && y < self.height {
+            let idx = self.offset(x, y);
+            self.pixels[idx..idx + 3].copy_from_slice(&color);
+        }
+    }
+
+    /// Adds the given color to the pixel at the given column and row,
+    /// saturating each channel. Pixels outside of the image are ignored.
+    pub fn add(&mut self, x: usize, y: usize, color: Color) {
+        if x < self.width && y < self.height {
+            let idx = self.offset(x, y);
+            for (channel, value) in self.pixels[idx..idx + 3].iter_mut().zip(color.iter()) {
+                *channel = channel.saturating_add(*value);
+            }
+        }
+    }
+
+    /// Draws a line between the given pixel coordinates, which may lie
+    /// outside of the image.
+    pub fn line(&mut self, from: (Float, Float), to: (Float, Float), color: Color) {
+        let (dx, dy) = (to.0 - from.0, to.1 - from.1);
+        let steps = dx.abs().max(dy.abs()).ceil().max(1.0) as usize;
+
+        for step in 0..=steps {
+            let t = step as Float / steps as Float;
+            let (x, y) = (from.0 + t * dx, from.1 + t * dy);
+            if x >= 0.0 && y >= 0.0 {
+                self.set(x as usize, y as usize, color);
+            }
+        }
+    }
+
+    /// Writes the image as a PNG file and flushes the output.
+    pub fn write_png<W: Write>(&self, mut out: W) -> io::Result<()> {
+        let mut encoder = png::Encoder::new(&mut out, self.width as u32, self.height as u32);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&self.pixels)?;
+        writer.finish()?;
+        out.flush()
+    }
+
+    fn offset(&self, x: usize, y: usize) -> usize {
+        (y * self.width + x) * 3
+    }
+}
+
+/// Maps a value in [0, 1] to a color of a perceptually uniform scale from
+/// dark blue to yellow, similar to viridis. Values outside are clamped.
+pub fn colormap(value: Float) -> Color {
+    const STOPS: [Color; 5] = [[68, 1, 84], [59, 82, 139], [33, 145, 140], [94, 201, 98], [253, 231, 37]];
+
+    let scaled = value.clamp(0.0, 1.0) * (STOPS.len() - 1) as Float;
+    let idx = (scaled as usize).min(STOPS.len() - 2);
+    let t = scaled - idx as Float;
+    let (lower, upper) = (STOPS[idx], STOPS[idx + 1]);
+
+    let mut color = [0; 3];
+    for channel in 0..3 {
+        let (a, b) = (lower[channel] as Float, upper[channel] as Float);
+        color[channel] = (a + t * (b - a)).round() as u8;
+    }
+    color
+}
+
+// Viewport //////////////////////////////////////////////////////////////////
+//
+// The region of the simulation space shown in an image. The y axis of space
+// points up and that of an image down, so the top row of an image shows the
+// largest y.
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Viewport {
+    pub min: Point,
+    pub max: Point,
+}
+
+impl Viewport {
+    pub fn new(min: Point, max: Point) -> Viewport {
+        assert!(min.x < max.x && min.y < max.y, "A viewport must not be empty.");
+        Viewport { min, max }
+    }
+
+    /// Returns the smallest square viewport centered on the given points,
+    /// extended on each side by the given fraction of its size. A single
+    /// point or none get a viewport of size one.
+    pub fn fitting<'a, I>(points: I, margin: Float) -> Viewport where I: IntoIterator<Item=&'a Point> {
+        let mut min = Point::new(Float::MAX, Float::MAX);
+        let mut max = Point::new(Float::MIN, Float::MIN);
+        for point in points {
+            min = Point::new(min.x.min(point.x), min.y.min(point.y));
+            max = Point::new(max.x.max(point.x), max.y.max(point.y));
+        }
+        if min.x > max.x {
+            min = Point::zero();
+            max = Point::zero();
+        }
+
+        let size = (max.x - min.x).max(max.y - min.y).max(1.0) * (1.0 + 2.0 * margin);
+        let center = Point::new((min.x + max.x) / 2.0, (min.y + max.y) / 2.0);
+        Viewport::new(Point::new(center.x - size / 2.0, center.y - size / 2.0),
+                      Point::new(center.x + size / 2.0, center.y + size / 2.0))
+    }
+
+    pub fn width(&self) -> Float {
+        self.max.x - self.min.x
+    }
+
+    pub fn height(&self) -> Float {
+        self.max.y - self.min.y
+    }
+
+    /// Returns the pixel coordinates of the given point in an image of the
+    /// given size. Points outside of the viewport lie outside of the image.
+    pub fn to_pixel(&self, point: &Point, width: usize, height: usize) -> (Float, Float) {
+        let x = (point.x - self.min.x) / self.width() * width as Float;
+        let y = (self.max.y - point.y) / self.height() * height as Float;
+        (x, y)
+    }
+
+    /// Returns the point at the center of the given pixel of an image of
+    /// the given size.
+    pub fn to_point(&self, x: usize, y: usize, width: usize, height: usize) -> Point {
+        let px = self.min.x + (x as Float + 0.5) / width as Float * self.width();
+        let py = self.max.y - (y as Float + 0.5) / height as Float * self.height();
+        Point::new(px, py)
+    }
+}
+
+// Tests /////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn image_writes_png() {
+        // given
+        let mut sut = Image::new(3, 2);
+        sut.set(2, 1, [10, 20, 30]);
+        sut.set(5, 5, WHITE);
+        let mut result = vec![];
+
+        // when
+        sut.write_png(&mut result).unwrap();
+
+        // then the image is complete
+        assert_eq!(b"IEND", &result[result.len() - 8..result.len() - 4]);
+        let decoder = png::Decoder::new(&result[..]);
+        let mut reader = decoder.read_info().unwrap();
+        let mut buffer = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buffer).unwrap();
+        assert_eq!((3, 2), (info.width, info.height));
+        assert_eq!(png::ColorType::Rgb, info.color_type);
+        assert_eq!(&[10, 20, 30], &buffer[15..18]);
+        assert_eq!(&[0, 0, 0], &buffer[..3]);
+    }
+
+    #[test]
+    fn image_adds_saturating() {
+        // given
+        let mut sut = Image::new(1, 1);
+
+        // when
+        sut.add(0, 0, [200, 10, 0]);
+        sut.add(0, 0, [100, 10, 0]);
+
+        // then
+        assert_eq!([255, 20, 0], sut.get(0, 0));
+    }
+
+    #[test]
+    fn image_draws_line() {
+        // given
+        let mut sut = Image::new(4, 4);
+
+        // when
+        sut.line((0.5, 0.5), (3.5, 3.5), WHITE);
+
+        // then
+        for i in 0..4 {
+            assert_eq!(WHITE, sut.get(i, i));
+        }
+        assert_eq!(BLACK, sut.get(3, 0));
+    }
+
+    #[test]
+    fn colormap_interpolates_stops() {
+        // then
+        assert_eq!([68, 1, 84], colormap(-1.0));
+        assert_eq!([33, 145, 140], colormap(0.5));
+        assert_eq!([253, 231, 37], colormap(1.0));
+    }
+
+    #[test]
+    fn viewport_fits_points() {
+        // given
+        let points = vec![Point::new(-2.0, 1.0), Point::new(6.0, 3.0)];
+
+        // when
+        let result = Viewport::fitting(&points, 0.25);
+
+        // then
+        assert_eq!(Viewport::new(Point::new(-4.0, -4.0), Point::new(8.0, 8.0)), result);
+    }
+
+    #[test]
+    fn viewport_maps_points_to_pixels() {
+        // given
+        let sut = Viewport::new(Point::new(-10.0, -10.0), Point::new(10.0, 10.0));
+
+        // then the top left corner is the origin of the image
+        assert_eq!((0.0, 0.0), sut.to_pixel(&Point::new(-10.0, 10.0), 100, 100));
+        assert_eq!((75.0, 50.0), sut.to_pixel(&Point::new(5.0, 0.0), 100, 100));
+        let center = sut.to_point(0, 0, 100, 100);
+        assert_close!(-9.9, center.x);
+        assert_close!(9.9, center.y);
+    }
+}
@@ -0,0 +1,415 @@
+use std::fs;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::geometry::{Point, Vector};
+use crate::physics::field::{Field, Sample};
+use crate::physics::Body;
+use crate::Float;
+
+use super::image::{self, Image, Viewport};
+use super::npy;
+
+// FieldMap //////////////////////////////////////////////////////////////////
+//
+// The potential and acceleration of the fields of an environment sampled on
+// a regular grid over a viewport, to visualise the dynamics of a
+// configuration. Each sample lies at the center of its cell. Cells are
+// stored row by row from the top left corner, like the pixels of an image,
+// so the first row holds the largest y.
+//
+// Both quantities span orders of magnitude near bodies, so they are
+// rendered on a log scale. The potential is drawn with contour lines at
+// evenly spaced levels, and the acceleration with arrows showing its
+// direction.
+
+/// The number of contour levels of the potential image.
+const CONTOUR_LEVELS: usize = 12;
+
+/// The distance in pixels between the arrows of the acceleration image.
+const ARROW_SPACING: usize = 16;
+
+const CONTOUR: image::Color = [255, 255, 255];
+
+pub struct FieldMap {
+    pub viewport: Viewport,
+    pub columns: usize,
+    pub rows: usize,
+    pub samples: Vec<Sample>,
+}
+
+impl FieldMap {
+    /// Samples the sum of the given fields on a grid with the given number
+    /// of columns. The number of rows follows from the aspect ratio of the
+    /// viewport.
+    pub fn new(fields: &[Box<dyn Field>], bodies: &[Body], viewport: Viewport, columns: usize) -> FieldMap {
+        assert!(columns > 0, "A map must have at least one column.");
+        let rows = ((columns as Float * viewport.height() / viewport.width()).round() as usize).max(1);
+
+        let points: Vec<Point> = (0..rows)
+            .flat_map(|y| (0..columns).map(move |x| (x, y)))
+            .map(|(x, y)| viewport.to_point(x, y, columns, rows))
+            .collect();
+
+        let mut samples = vec![Sample { acceleration: Vector::zero(), potential: 0.0 }; points.len()];
+        for field in fields {
+            for (sum, sample) in samples.iter_mut().zip(field.sample(bodies, &points)) {
+                sum.acceleration += sample.acceleration;
+                sum.potential += sample.potential;
+            }
+        }
+
+        FieldMap { viewport, columns, rows, samples }
+    }
+
+    /// Returns the center of the cell at the given column and row.
+    pub fn point(&self, x: usize, y: usize) -> Point {
+        self.viewport.to_point(x, y, self.columns, self.rows)
+    }
+
+    /// Writes a line `x,y,potential,ax,ay` for each cell, after a header.
+    pub fn write_csv<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        writeln!(out, "x,y,potential,ax,ay")?;
+        for (idx, sample) in self.samples.iter().enumerate() {
+            let point = self.point(idx % self.columns, idx / self.columns);
+            let a = &sample.acceleration;
+            writeln!(out, "{},{},{},{},{}", point.x, point.y, sample.potential, a.dx, a.dy)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the cells as an array of shape (rows, columns, 3), holding
+    /// the potential and the acceleration of each cell.
+    pub fn write_npy<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let values: Vec<Float> = self.samples.iter()
+            .flat_map(|sample| vec![sample.potential, sample.acceleration.dx, sample.acceleration.dy])
+            .collect();
+        npy::write(out, &[self.rows, self.columns, 3], &values)
+    }
+
+    /// Renders the potential as a heatmap with contour lines, one pixel per
+    /// cell. Deep wells are dark.
+    pub fn potential_image(&self) -> Image {
+        let values: Vec<Float> = self.samples.iter().map(|sample| sample.potential.abs()).collect();
+        let scaled = log_scaled(&values);
+        let mut image = self.heatmap(&scaled, true);
+
+        // a contour passes between neighbouring cells of different levels
+        let level = |idx: usize| (scaled[idx] * CONTOUR_LEVELS as Float).min(CONTOUR_LEVELS as Float - 1.0) as usize;
+        for y in 0..self.rows {
+            for x in 0..self.columns {
+                let idx = y * self.columns + x;
+                let right = x + 1 < self.columns && level(idx) != level(idx + 1);
+                let below = y + 1 < self.rows && level(idx) != level(idx + self.columns);
+                if right || below {
+                    image.set(x, y, CONTOUR);
+                }
+            }
+        }
+
+        image
+    }
+
+    /// Renders the magnitude of the acceleration as a heatmap, one pixel per
+    /// cell, with arrows in its direction.
+    pub fn acceleration_image(&self) -> Image {
+        let values: Vec<Float> = self.samples.iter().map(|sample| sample.acceleration.magnitude()).collect();
+        let mut image = self.heatmap(&log_scaled(&values), false);
+
+        let length = ARROW_SPACING as Float * 0.4;
+        for y in (ARROW_SPACING / 2..self.rows).step_by(ARROW_SPACING) {
+            for x in (ARROW_SPACING / 2..self.columns).step_by(ARROW_SPACING) {
+                let direction = match self.samples[y * self.columns + x].acceleration.normalized() {
+                    Some(direction) => direction,
+                    None => continue,
+                };
+
+                // the y axis of the image points down
+                let (dx, dy) = (direction.dx * length, -direction.dy * length);
+                let center = (x as Float + 0.5, y as Float + 0.5);
+                let tip = (center.0 + dx, center.1 + dy);
+                image.line((center.0 - dx, center.1 - dy), tip, CONTOUR);
+                image.line(tip, (tip.0 - 0.5 * (dx - dy), tip.1 - 0.5 * (dy + dx)), CONTOUR);
+                image.line(tip, (tip.0 - 0.5 * (dx + dy), tip.1 - 0.5 * (dy - dx)), CONTOUR);
+            }
+        }
+
+        image
+    }
+
+    /// Colors each pixel by the given values in [0, 1], optionally inverted.
+    fn heatmap(&self, values: &[Float], inverted: bool) -> Image {
+        let mut image = Image::new(self.columns, self.rows);
+        for (idx, value) in values.iter().enumerate() {
+            let value = if inverted { 1.0 - value } else { *value };
+            image.set(idx % self.columns, idx / self.columns, image::colormap(value));
+        }
+        image
+    }
+}
+
+/// Maps the logarithms of the given non negative values linearly to [0, 1].
+/// Zeros are mapped to the smallest non zero value.
+fn log_scaled(values: &[Float]) -> Vec<Float> {
+    let min_positive = values.iter().cloned().filter(|value| *value > 0.0).fold(Float::MAX, Float::min);
+    let logs: Vec<Float> = values.iter().map(|value| value.max(min_positive).ln()).collect();
+
+    let min = logs.iter().cloned().fold(Float::MAX, Float::min);
+    let max = logs.iter().cloned().fold(Float::MIN, Float::max);
+    let range = max - min;
+
+    logs.iter()
+        .map(|log| if range > 0.0 { (log - min) / range } else { 0.0 })
+        .collect()
+}
+
+// MapFormat /////////////////////////////////////////////////////////////////
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum MapFormat {
+    /// The cells as lines of text.
+    Csv,
+    /// The cells as a NumPy array.
+    Npy,
+    /// Heatmaps of the potential and of the acceleration.
+    Png,
+}
+
+impl FromStr for MapFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(MapFormat::Csv),
+            "npy" => Ok(MapFormat::Npy),
+            "png" => Ok(MapFormat::Png),
+            _ => Err(format!("Unknown map format '{}'. Expected csv, npy or png.", s)),
+        }
+    }
+}
+
+// MapWriter /////////////////////////////////////////////////////////////////
+//
+// Writes a map of the fields of an environment every n-th frame, in each
+// of the chosen formats. Files are named after the frame, so a map written
+// after the first update is numbered like the first frame of a `DataWriter`.
+// Unless a viewport is given, the viewport is fitted to the bodies of the
+// first frame and kept, so that the maps of a run can be compared.
+
+/// The margin of a fitted viewport, as a fraction of its size.
+const MARGIN: Float = 0.1;
+
+pub const DEFAULT_MAP_SIZE: usize = 256;
+
+pub struct MapWriter {
+    directory: PathBuf,
+    every: usize,
+    columns: usize,
+    formats: Vec<MapFormat>,
+    viewport: Option<Viewport>,
+    frame: usize,
+}
+
+impl MapWriter {
+    /// Writes rendered maps into the given directory every n-th frame,
+    /// creating the directory if needed.
+    pub fn new<P: AsRef<Path>>(directory: P, every: usize) -> io::Result<MapWriter> {
+        assert!(every > 0, "Maps must be written at least every frame.");
+        fs::create_dir_all(&directory)?;
+        Ok(MapWriter {
+            directory: directory.as_ref().to_owned(),
+            every,
+            columns: DEFAULT_MAP_SIZE,
+            formats: vec![MapFormat::Png],
+            viewport: None,
+            frame: 0,
+        })
+    }
+
+    /// The number of columns of the maps.
+    pub fn with_columns(mut self, columns: usize) -> MapWriter {
+        assert!(columns > 0, "A map must have at least one column.");
+        self.columns = columns;
+        self
+    }
+
+    pub fn with_formats(mut self, formats: Vec<MapFormat>) -> MapWriter {
+        self.formats = formats;
+        self
+    }
+
+    pub fn with_viewport(mut self, viewport: Viewport) -> MapWriter {
+        self.viewport = Some(viewport);
+        self
+    }
+
+    /// Counts a frame and writes the map of the given fields and bodies if
+    /// it is due.
+    pub fn update(&mut self, fields: &[Box<dyn Field>], bodies: &[Body]) -> io::Result<()> {
+        let frame = self.frame;
+        self.frame += 1;
+        if !frame.is_multiple_of(self.every) {
+            return Ok(());
+        }
+
+        let viewport = self.viewport
+            .get_or_insert_with(|| Viewport::fitting(bodies.iter().map(|body| &body.position), MARGIN))
+            .clone();
+        let map = FieldMap::new(fields, bodies, viewport, self.columns);
+
+        for format in self.formats.iter() {
+            match format {
+                MapFormat::Csv => self.create(&format!("map-{}.csv", frame), |out| map.write_csv(out))?,
+                MapFormat::Npy => self.create(&format!("map-{}.npy", frame), |out| map.write_npy(out))?,
+                MapFormat::Png => {
+                    self.create(&format!("potential-{}.png", frame), |out| map.potential_image().write_png(out))?;
+                    self.create(&format!("acceleration-{}.png", frame), |out| map.acceleration_image().write_png(out))?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Creates the named file, writes it with `write` and flushes it.
+    fn create<F>(&self, name: &str, write: F) -> io::Result<()>
+        where F: FnOnce(&mut BufWriter<fs::File>) -> io::Result<()> {
+        let mut file = BufWriter::new(fs::File::create(self.directory.join(name))?);
+        write(&mut file)?;
+        file.flush()
+    }
+}
+
+// Tests /////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use crate::physics::field::BruteForceField;
+
+    use super::*;
+
+    fn solar_system() -> (Vec<Box<dyn Field>>, Vec<Body>) {
+        let fields: Vec<Box<dyn Field>> = vec![Box::from(BruteForceField::new())];
+        let bodies = vec![
+            Body::new(100.0, Point::zero(), Vector::zero()),
+            Body::new(1.0, Point::new(6.0, 0.0), Vector::new(0.0, 4.0)),
+        ];
+        (fields, bodies)
+    }
+
+    #[test]
+    fn field_map_samples_cell_centers() {
+        // given
+        let (fields, bodies) = solar_system();
+        let viewport = Viewport::new(Point::new(-10.0, -5.0), Point::new(10.0, 5.0));
+
+        // when
+        let sut = FieldMap::new(&fields, &bodies, viewport, 4);
+
+        // then
+        assert_eq!((4, 2), (sut.columns, sut.rows));
+        assert_eq!(Point::new(-7.5, 2.5), sut.point(0, 0));
+        assert_eq!(Point::new(2.5, -2.5), sut.point(2, 1));
+
+        let expected = fields[0].sample(&bodies, &[Point::new(2.5, -2.5)]);
+        assert_eq!(expected[0], sut.samples[6]);
+    }
+
+    #[test]
+    fn field_map_sums_fields() {
+        // given
+        let (mut fields, bodies) = solar_system();
+        fields.push(Box::from(BruteForceField::new()));
+        let viewport = Viewport::new(Point::new(-10.0, -10.0), Point::new(10.0, 10.0));
+
+        // when
+        let single = FieldMap::new(&fields[..1], &bodies, viewport.clone(), 3);
+        let double = FieldMap::new(&fields, &bodies, viewport, 3);
+
+        // then
+        for (single, double) in single.samples.iter().zip(double.samples.iter()) {
+            assert_close!(2.0 * single.potential, double.potential);
+            assert_close!(2.0 * single.acceleration.dx, double.acceleration.dx);
+        }
+    }
+
+    #[test]
+    fn field_map_writes_csv_and_npy() {
+        // given
+        let (fields, bodies) = solar_system();
+        let viewport = Viewport::new(Point::new(-10.0, -10.0), Point::new(10.0, 10.0));
+        let sut = FieldMap::new(&fields, &bodies, viewport, 2);
+        let mut csv = vec![];
+        let mut npy = vec![];
+
+        // when
+        sut.write_csv(&mut csv).unwrap();
+        sut.write_npy(&mut npy).unwrap();
+
+        // then
+        let csv = String::from_utf8(csv).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(5, lines.len());
+        assert_eq!("x,y,potential,ax,ay", lines[0]);
+
+        let s = &sut.samples[0];
+        let expected = format!("-5,5,{},{},{}", s.potential, s.acceleration.dx, s.acceleration.dy);
+        assert_eq!(expected, lines[1]);
+
+        assert_eq!(128 + 12 * std::mem::size_of::<Float>(), npy.len());
+    }
+
+    #[test]
+    fn field_map_renders_images() {
+        // given
+        let (fields, bodies) = solar_system();
+        let viewport = Viewport::new(Point::new(-50.0, -50.0), Point::new(50.0, 50.0));
+        let sut = FieldMap::new(&fields, &bodies, viewport, 64);
+
+        // when
+        let potential = sut.potential_image();
+        let acceleration = sut.acceleration_image();
+
+        // then
+        assert_eq!((64, 64), (potential.width, potential.height));
+        assert_eq!((64, 64), (acceleration.width, acceleration.height));
+
+        // the far corners are the shallowest, and contours surround the sun
+        assert_eq!(image::colormap(1.0), potential.get(0, 0));
+        assert!((0..64).any(|x| potential.get(x, 32) == CONTOUR));
+        assert!((0..64).any(|x| acceleration.get(x, 8) == CONTOUR));
+    }
+
+    #[test]
+    fn map_format_parses() {
+        // then
+        assert_eq!(Ok(MapFormat::Npy), "npy".parse());
+        assert!("gif".parse::<MapFormat>().is_err());
+    }
+
+    #[test]
+    fn map_writer_writes_every_nth_frame() {
+        // given
+        let (fields, bodies) = solar_system();
+        let directory = "temp-maps";
+        let mut sut = MapWriter::new(directory, 2).unwrap()
+            .with_columns(8)
+            .with_formats(vec![MapFormat::Csv, MapFormat::Png]);
+
+        // when
+        for _ in 0..3 {
+            sut.update(&fields, &bodies).unwrap();
+        }
+
+        // then
+        let mut files: Vec<String> = fs::read_dir(directory).unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        files.sort();
+        assert_eq!(vec!["acceleration-0.png", "acceleration-2.png", "map-0.csv", "map-2.csv",
+                        "potential-0.png", "potential-2.png"], files);
+
+        // after
+        fs::remove_dir_all(directory).expect("Error cleaning up test.");
+    }
+}
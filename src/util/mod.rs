@@ -1,3 +1,13 @@
+pub mod binary;
 pub mod distribution;
+pub mod gadget;
 pub mod gens;
+pub mod image;
+pub mod map;
+pub mod npy;
+pub mod render;
+pub mod sink;
+pub mod tipsy;
+pub mod trajectory;
+pub mod vtk;
 pub mod write;
@@ -0,0 +1,447 @@
+use std::fs;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+use crate::Float;
+
+use super::sink::{Sink, Snapshot};
+
+// NumPy Arrays //////////////////////////////////////////////////////////////
+//
+// Writes arrays in the `.npy` format, so that `numpy.load` reads them
+// directly. A file starts with a magic string and a version, followed by the
+// length of a header and the header itself, a python dict literal holding
+// the type, the order and the shape of the array. The header is padded with
+// spaces so that the data starts at a multiple of 64 bytes. The data follows
+// in C order, as little endian values.
+
+const MAGIC: &[u8] = b"\x93NUMPY";
+
+const ALIGNMENT: usize = 64;
+
+/// A type of the elements of an array.
+pub trait Element: Copy {
+    /// The NumPy type descriptor.
+    const DESCR: &'static str;
+
+    fn write_le<W: Write>(self, out: &mut W) -> io::Result<()>;
+}
+
+impl Element for f32 {
+    const DESCR: &'static str = "<f4";
+
+    fn write_le<W: Write>(self, out: &mut W) -> io::Result<()> {
+        out.write_all(&self.to_le_bytes())
+    }
+}
+
+impl Element for f64 {
+    const DESCR: &'static str = "<f8";
+
+    fn write_le<W: Write>(self, out: &mut W) -> io::Result<()> {
+        out.write_all(&self.to_le_bytes())
+    }
+}
+
+impl Element for u64 {
+    const DESCR: &'static str = "<u8";
+
+    fn write_le<W: Write>(self, out: &mut W) -> io::Result<()> {
+        out.write_all(&self.to_le_bytes())
+    }
+}
+
+/// Writes an array of the given shape. The number of values must match the
+/// shape.
+pub fn write<W: Write, T: Element>(out: &mut W, shape: &[usize], values: &[T]) -> io::Result<()> {
+    assert_eq!(shape.iter().product::<usize>(), values.len(),
+               "The number of values must match the shape {:?}.", shape);
+
+    write_header(out, T::DESCR, shape, 0)?;
+    for value in values {
+        value.write_le(out)?;
+    }
+    Ok(())
+}
+
+/// Writes the magic string, version and header for an array of the given
+/// type and shape, taking up at least the given number of bytes. The values
+/// can then be written one by one.
+fn write_header<W: Write>(out: &mut W, descr: &str, shape: &[usize], size: usize) -> io::Result<()> {
+    let header = header(descr, shape, size);
+    out.write_all(MAGIC)?;
+    out.write_all(&[1, 0])?;
+    out.write_all(&(header.len() as u16).to_le_bytes())?;
+    out.write_all(header.as_bytes())
+}
+
+/// Returns the header for an array of the given type and shape, padded so
+/// that the magic string, version, length and header take up a multiple of
+/// 64 bytes, and at least the given number of bytes.
+fn header(descr: &str, shape: &[usize], size: usize) -> String {
+    // a tuple of one element needs a trailing comma
+    let dims: Vec<String> = shape.iter().map(|n| n.to_string()).collect();
+    let shape = if dims.len() == 1 { format!("({},)", dims[0]) } else { format!("({})", dims.join(", ")) };
+
+    let mut header = format!("{{'descr': '{}', 'fortran_order': False, 'shape': {}, }}", descr, shape);
+
+    // magic, version and length take 10 bytes, and the header ends in a newline
+    let len = MAGIC.len() + 4 + header.len() + 1;
+    let padded = len.div_ceil(ALIGNMENT).max(size.div_ceil(ALIGNMENT)) * ALIGNMENT;
+    header.push_str(&" ".repeat(padded - len));
+    header.push('\n');
+    header
+}
+
+// NpyWriter /////////////////////////////////////////////////////////////////
+//
+// A sink writing all snapshots into a single array of shape (frames, bodies,
+// 4), holding x, y, vx and vy of each body. Room is left for the header,
+// which is rewritten in place after each frame, so that the file holds a
+// valid array of the frames so far even if the run stops early. Every
+// snapshot must hold the same bodies.
+
+/// The room left for the header, enough for any shape.
+const RESERVED_HEADER: usize = 256;
+
+/// The values stored per body and frame.
+const STATE: usize = 4;
+
+pub struct NpyWriter<W: Write + Seek = BufWriter<fs::File>> {
+    out: W,
+    bodies: Option<usize>,
+    frames: usize,
+}
+
+impl NpyWriter {
+    /// Creates the array file at the given path, replacing any existing file.
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<NpyWriter> {
+        Ok(NpyWriter::new(BufWriter::new(fs::File::create(path)?)))
+    }
+}
+
+impl<W: Write + Seek> NpyWriter<W> {
+    pub fn new(out: W) -> NpyWriter<W> {
+        NpyWriter { out, bodies: None, frames: 0 }
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        let shape = [self.frames, self.bodies.unwrap_or(0), STATE];
+        write_header(&mut self.out, Float::DESCR, &shape, RESERVED_HEADER)
+    }
+}
+
+impl<W: Write + Seek> Sink for NpyWriter<W> {
+    fn write(&mut self, snapshot: &Snapshot) -> io::Result<()> {
+        match self.bodies {
+            None => {
+                self.bodies = Some(snapshot.len());
+                self.write_header()?;
+            }
+            Some(bodies) if bodies != snapshot.len() => {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, format!(
+                    "Expected {} bodies in frame {}, got {}.", bodies, snapshot.frame, snapshot.len())));
+            }
+            Some(_) => (),
+        }
+
+        write_state(&mut self.out, snapshot)?;
+        self.frames += 1;
+
+        // update the number of frames
+        self.out.seek(SeekFrom::Start(0))?;
+        self.write_header()?;
+        self.out.seek(SeekFrom::End(0))?;
+        Ok(())
+    }
+
+    /// Writes the header of an empty array if there was no frame.
+    fn finish(&mut self) -> io::Result<()> {
+        if self.bodies.is_none() {
+            self.write_header()?;
+        }
+        self.out.flush()
+    }
+}
+
+/// Writes x, y, vx and vy of each body of the given snapshot.
+fn write_state<W: Write>(out: &mut W, snapshot: &Snapshot) -> io::Result<()> {
+    for (position, velocity) in snapshot.positions.iter().zip(snapshot.velocities.iter()) {
+        for value in [position.x, position.y, velocity.dx, velocity.dy] {
+            value.write_le(out)?;
+        }
+    }
+    Ok(())
+}
+
+// NpzWriter /////////////////////////////////////////////////////////////////
+//
+// A sink writing an `.npz` archive, as `numpy.savez` does, with an array
+// `frame_N` of shape (bodies, 4) for each frame N, holding x, y, vx and vy
+// of each body. The archive also holds the `ids`, `groups` and `masses` of
+// the bodies of the first frame, and the `times` of all frames.
+
+pub struct NpzWriter<W: Write + Seek = BufWriter<fs::File>> {
+    archive: ZipWriter<W>,
+    times: Vec<Float>,
+}
+
+impl NpzWriter {
+    /// Creates the archive at the given path, replacing any existing file.
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<NpzWriter> {
+        Ok(NpzWriter::new(BufWriter::new(fs::File::create(path)?)))
+    }
+}
+
+impl<W: Write + Seek> NpzWriter<W> {
+    pub fn new(out: W) -> NpzWriter<W> {
+        NpzWriter { archive: ZipWriter::new(out), times: vec![] }
+    }
+
+    /// Starts a new array in the archive.
+    fn start(&mut self, name: &str) -> io::Result<()> {
+        let options = FileOptions::default()
+            .compression_method(CompressionMethod::Stored)
+            .large_file(true);
+        self.archive.start_file(format!("{}.npy", name), options)?;
+        Ok(())
+    }
+}
+
+impl<W: Write + Seek> Sink for NpzWriter<W> {
+    fn write(&mut self, snapshot: &Snapshot) -> io::Result<()> {
+        if self.times.is_empty() {
+            let ids: Vec<u64> = snapshot.ids.iter().map(|id| *id as u64).collect();
+            let groups: Vec<u64> = snapshot.groups.iter().map(|group| *group as u64).collect();
+            self.start("ids")?;
+            write(&mut self.archive, &[ids.len()], &ids)?;
+            self.start("groups")?;
+            write(&mut self.archive, &[groups.len()], &groups)?;
+            self.start("masses")?;
+            write(&mut self.archive, &[snapshot.len()], &snapshot.masses)?;
+        }
+
+        self.start(&format!("frame_{}", snapshot.frame))?;
+        write_header(&mut self.archive, Float::DESCR, &[snapshot.len(), STATE], 0)?;
+        write_state(&mut self.archive, snapshot)?;
+        self.times.push(snapshot.time);
+        Ok(())
+    }
+
+    /// Writes the times and completes the archive.
+    fn finish(&mut self) -> io::Result<()> {
+        self.start("times")?;
+        write(&mut self.archive, &[self.times.len()], &self.times)?;
+        self.archive.finish()?.flush()
+    }
+}
+
+// Tests /////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Read};
+    use std::mem;
+
+    use zip::ZipArchive;
+
+    use crate::geometry::{Point, Vector};
+    use crate::physics::Body;
+
+    use super::*;
+
+    fn snapshots() -> Vec<Snapshot> {
+        let mut bodies = vec![
+            Body::new(2.0, Point::new(1.5, -2.0), Vector::new(0.25, 3.0)),
+            Body::new(0.5, Point::new(4.0, 8.0), Vector::new(-1.0, 0.0)),
+        ];
+        (0..3).map(|frame| {
+            for body in bodies.iter_mut() {
+                body.apply_velocity();
+            }
+            Snapshot::new(frame, (frame + 1) as Float, &bodies).with_groups(&[0, 1])
+        }).collect()
+    }
+
+    /// Returns the header and the values of the given array.
+    fn parse(data: &[u8]) -> (String, Vec<Float>) {
+        let len = u16::from_le_bytes([data[8], data[9]]) as usize;
+        let header = String::from_utf8(data[10..10 + len].to_vec()).unwrap();
+        let size = mem::size_of::<Float>();
+        let values = data[10 + len..].chunks(size).map(|bytes| {
+            let mut value = [0; 8];
+            value[..size].copy_from_slice(bytes);
+            if size == 4 {
+                f32::from_le_bytes([value[0], value[1], value[2], value[3]]) as Float
+            } else {
+                f64::from_le_bytes(value) as Float
+            }
+        }).collect();
+        (header, values)
+    }
+
+    #[test]
+    fn npy_writes_array() {
+        // given
+        let mut result = vec![];
+
+        // when
+        let values: [Float; 6] = [1.0, 2.0, 3.0, 4.0, 5.0, 6.0];
+        write(&mut result, &[2, 3], &values).unwrap();
+
+        // then
+        let size = mem::size_of::<Float>();
+        assert_eq!(128 + 6 * size, result.len());
+        assert_eq!(b"\x93NUMPY\x01\x00", &result[..8]);
+        assert_eq!(118, u16::from_le_bytes([result[8], result[9]]));
+
+        let header = String::from_utf8(result[10..128].to_vec()).unwrap();
+        assert!(header.starts_with("{'descr': '<f"));
+        assert!(header.contains("'fortran_order': False, 'shape': (2, 3), }"));
+        assert!(header.ends_with(" \n"));
+
+        assert_eq!(&(4.0 as Float).to_le_bytes(), &result[128 + 3 * size..128 + 4 * size]);
+    }
+
+    #[test]
+    fn npy_writes_one_dimensional_shape() {
+        // then
+        assert!(header("<f4", &[5], 0).contains("'shape': (5,), }"));
+        assert!(header("<f4", &[], 0).contains("'shape': (), }"));
+    }
+
+    #[test]
+    fn npy_writes_integers() {
+        // given
+        let mut result = vec![];
+
+        // when
+        write(&mut result, &[2], &[7u64, 9]).unwrap();
+
+        // then
+        assert!(String::from_utf8_lossy(&result).contains("'descr': '<u8'"));
+        assert_eq!(&7u64.to_le_bytes(), &result[result.len() - 16..result.len() - 8]);
+    }
+
+    #[test]
+    fn npy_header_takes_reserved_size() {
+        // then
+        assert_eq!(256, 10 + header("<f8", &[1, 2, 3], 256).len());
+        assert_eq!(128, 10 + header("<f8", &[1, 2, 3], 100).len());
+    }
+
+    #[test]
+    fn npy_writer_writes_all_frames() {
+        // given
+        let snapshots = snapshots();
+        let mut sut = NpyWriter::new(Cursor::new(vec![]));
+
+        // when
+        for snapshot in snapshots.iter() {
+            sut.write(snapshot).unwrap();
+        }
+        sut.finish().unwrap();
+
+        // then
+        let (header, values) = parse(&sut.out.into_inner());
+        assert_eq!(246, header.len());
+        assert!(header.contains("'shape': (3, 2, 4), }"));
+        assert_eq!(3 * 2 * 4, values.len());
+
+        // the second body of the last frame
+        let last = &snapshots[2];
+        assert_eq!(vec![last.positions[1].x, last.positions[1].y, last.velocities[1].dx, last.velocities[1].dy],
+                   values[20..24].to_vec());
+    }
+
+    #[test]
+    fn npy_writer_updates_header_after_each_frame() {
+        // given
+        let snapshots = snapshots();
+        let mut sut = NpyWriter::new(Cursor::new(vec![]));
+
+        // when
+        sut.write(&snapshots[0]).unwrap();
+        sut.write(&snapshots[1]).unwrap();
+
+        // then the frames so far form an array without finishing
+        let (header, values) = parse(sut.out.get_ref());
+        assert!(header.contains("'shape': (2, 2, 4), }"));
+        assert_eq!(2 * 2 * 4, values.len());
+        assert_eq!(256 + 2 * 2 * 4 * mem::size_of::<Float>() as u64, sut.out.position());
+    }
+
+    #[test]
+    fn npy_writer_writes_empty_array_without_frames() {
+        // given
+        let mut sut = NpyWriter::new(Cursor::new(vec![]));
+
+        // when
+        sut.finish().unwrap();
+
+        // then
+        let (header, values) = parse(&sut.out.into_inner());
+        assert!(header.contains("'shape': (0, 0, 4), }"));
+        assert!(values.is_empty());
+    }
+
+    #[test]
+    fn npy_writer_rejects_changing_bodies() {
+        // given
+        let mut sut = NpyWriter::new(Cursor::new(vec![]));
+        sut.write(&snapshots()[0]).unwrap();
+
+        // when
+        let result = sut.write(&Snapshot::new(1, 2.0, &[]));
+
+        // then
+        assert_eq!(io::ErrorKind::InvalidInput, result.unwrap_err().kind());
+    }
+
+    #[test]
+    fn npz_writer_writes_frames_as_arrays() {
+        // given
+        let snapshots = snapshots();
+        let mut sut = NpzWriter::create("temp-frames.npz").unwrap();
+
+        // when
+        for snapshot in snapshots.iter() {
+            sut.write(snapshot).unwrap();
+        }
+        sut.finish().unwrap();
+
+        // then
+        let mut archive = ZipArchive::new(fs::File::open("temp-frames.npz").unwrap()).unwrap();
+        let mut names: Vec<&str> = archive.file_names().collect();
+        names.sort_unstable();
+        assert_eq!(vec!["frame_0.npy", "frame_1.npy", "frame_2.npy", "groups.npy", "ids.npy", "masses.npy", "times.npy"],
+                   names);
+
+        let array = |archive: &mut ZipArchive<fs::File>, name: &str| {
+            let mut data = vec![];
+            archive.by_name(name).unwrap().read_to_end(&mut data).unwrap();
+            parse(&data)
+        };
+
+        let (header, values) = array(&mut archive, "frame_1.npy");
+        assert!(header.contains("'shape': (2, 4), }"));
+        assert_eq!(snapshots[1].positions[0].x, values[0]);
+        assert_eq!(snapshots[1].velocities[1].dx, values[6]);
+
+        assert_eq!(vec![1.0, 2.0, 3.0], array(&mut archive, "times.npy").1);
+        assert_eq!(vec![2.0, 0.5], array(&mut archive, "masses.npy").1);
+
+        // after
+        fs::remove_file("temp-frames.npz").expect("Error cleaning up test.");
+    }
+
+    #[test]
+    #[should_panic(expected = "The number of values must match the shape")]
+    fn npy_rejects_mismatched_shape() {
+        // given
+        write(&mut vec![], &[2, 2], &[1.0]).unwrap();
+    }
+}
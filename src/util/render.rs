@@ -0,0 +1,381 @@
+use std::fs;
+use std::io::{self, BufWriter};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::Float;
+
+use super::image::{self, Color, Image, Viewport};
+use super::sink::{Sink, Snapshot};
+
+// Coloring //////////////////////////////////////////////////////////////////
+//
+// How the bodies of a rendered frame are colored. Groups get distinct colors
+// from a palette, repeating after its last one. Speeds and masses are mapped
+// onto the color scale of the maps, speeds linearly from zero and masses on
+// a log scale, as they tend to span orders of magnitude. Their range is that
+// of the first frame, so that colors mean the same in every frame. Masses
+// that are not positive, such as those of an imported file, are left out of
+// the range and get the lowest color.
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Coloring {
+    Group,
+    Speed,
+    Mass,
+}
+
+impl FromStr for Coloring {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "group" => Ok(Coloring::Group),
+            "speed" => Ok(Coloring::Speed),
+            "mass" => Ok(Coloring::Mass),
+            _ => Err(format!("Unknown coloring '{}'. Expected group, speed or mass.", s)),
+        }
+    }
+}
+
+/// The colors of the groups, bright enough to stand out on black.
+const PALETTE: [Color; 8] = [
+    [255, 255, 255], [255, 179, 71], [102, 194, 255], [255, 102, 128],
+    [140, 230, 120], [200, 150, 255], [255, 230, 90], [90, 230, 220],
+];
+
+impl Coloring {
+    /// The value of a body that is mapped onto the color scale.
+    fn value(self, snapshot: &Snapshot, idx: usize) -> Float {
+        match self {
+            Coloring::Group => snapshot.groups[idx] as Float,
+            Coloring::Speed => snapshot.velocities[idx].magnitude(),
+            Coloring::Mass => log_mass(snapshot.masses[idx]),
+        }
+    }
+
+    /// The range of the values of the given snapshot.
+    fn range(self, snapshot: &Snapshot) -> (Float, Float) {
+        let values = (0..snapshot.len()).map(|idx| self.value(snapshot, idx)).filter(|v| v.is_finite());
+        let (min, max) = values.fold((Float::MAX, Float::MIN), |(min, max), v| (min.min(v), max.max(v)));
+        match self {
+            Coloring::Speed => (0.0, max.max(0.0)),
+            _ if min > max => (0.0, 0.0),
+            _ => (min, max),
+        }
+    }
+}
+
+/// Returns the log of the given mass, or negative infinity unless it is
+/// positive, which also covers NaN.
+#[allow(clippy::neg_cmp_op_on_partial_ord)]
+fn log_mass(mass: Float) -> Float {
+    if !(mass > 0.0) {
+        return Float::NEG_INFINITY;
+    }
+    mass.ln()
+}
+
+// RenderWriter //////////////////////////////////////////////////////////////
+//
+// A sink rendering each snapshot as a PNG image of the bodies, on a black
+// background, named like the frames of a `DataWriter`. Bodies are drawn as
+// discs of the given diameter in pixels. They are drawn over each other, or
+// with additive blending, where each body adds a fraction of its color so
+// that dense regions glow brighter.
+//
+// As with maps, unless a viewport is given, the viewport is fitted to the
+// bodies of the first frame and kept, and the height of the images follows
+// from the width and the viewport.
+
+/// The fraction of its color a body adds with additive blending.
+const BLEND: Float = 0.25;
+
+/// The margin of a fitted viewport, as a fraction of its size.
+const MARGIN: Float = 0.1;
+
+pub const DEFAULT_RENDER_SIZE: usize = 512;
+
+pub struct RenderWriter {
+    directory: PathBuf,
+    width: usize,
+    point_size: usize,
+    coloring: Coloring,
+    blending: bool,
+    viewport: Option<Viewport>,
+    range: Option<(Float, Float)>,
+}
+
+impl RenderWriter {
+    /// Renders into the given directory, which is created with the first
+    /// image.
+    pub fn new<P: AsRef<Path>>(directory: P) -> RenderWriter {
+        RenderWriter {
+            directory: directory.as_ref().to_owned(),
+            width: DEFAULT_RENDER_SIZE,
+            point_size: 1,
+            coloring: Coloring::Group,
+            blending: false,
+            viewport: None,
+            range: None,
+        }
+    }
+
+    /// The width of the images in pixels.
+    pub fn with_width(mut self, width: usize) -> RenderWriter {
+        assert!(width > 0, "An image must be at least one pixel wide.");
+        self.width = width;
+        self
+    }
+
+    /// The diameter of the bodies in pixels.
+    pub fn with_point_size(mut self, point_size: usize) -> RenderWriter {
+        assert!(point_size > 0, "Bodies must be at least one pixel wide.");
+        self.point_size = point_size;
+        self
+    }
+
+    pub fn with_coloring(mut self, coloring: Coloring) -> RenderWriter {
+        self.coloring = coloring;
+        self
+    }
+
+    pub fn with_blending(mut self, blending: bool) -> RenderWriter {
+        self.blending = blending;
+        self
+    }
+
+    pub fn with_viewport(mut self, viewport: Viewport) -> RenderWriter {
+        self.viewport = Some(viewport);
+        self
+    }
+
+    fn render(&mut self, snapshot: &Snapshot) -> Image {
+        let viewport = self.viewport
+            .get_or_insert_with(|| Viewport::fitting(&snapshot.positions, MARGIN))
+            .clone();
+        let coloring = self.coloring;
+        let (min, max) = *self.range.get_or_insert_with(|| coloring.range(snapshot));
+
+        let height = ((self.width as Float * viewport.height() / viewport.width()).round() as usize).max(1);
+        let mut image = Image::new(self.width, height);
+
+        for idx in 0..snapshot.len() {
+            let color = match coloring {
+                Coloring::Group => PALETTE[snapshot.groups[idx] % PALETTE.len()],
+                _ => {
+                    let value = coloring.value(snapshot, idx);
+                    if max > min {
+                        image::colormap((value - min) / (max - min))
+                    } else {
+                        // the range is a single value, unless the body was
+                        // left out of it
+                        image::colormap(if value.is_finite() { 1.0 } else { 0.0 })
+                    }
+                }
+            };
+            let pixel = viewport.to_pixel(&snapshot.positions[idx], self.width, height);
+            self.draw(&mut image, pixel, color);
+        }
+        image
+    }
+
+    /// Draws a disc of the point size centered on the given pixel
+    /// coordinates, which may lie outside of the image.
+    fn draw(&self, image: &mut Image, (x, y): (Float, Float), color: Color) {
+        let radius = self.point_size as Float / 2.0;
+        if x + radius < 0.0 || y + radius < 0.0
+            || x - radius > image.width as Float || y - radius > image.height as Float {
+            return;
+        }
+
+        let color = if self.blending {
+            let mut dimmed = [0; 3];
+            for (channel, value) in dimmed.iter_mut().zip(color.iter()) {
+                *channel = (*value as Float * BLEND).round() as u8;
+            }
+            dimmed
+        } else {
+            color
+        };
+
+        let (left, top) = ((x - radius).floor() as isize, (y - radius).floor() as isize);
+        let (right, bottom) = ((x + radius).floor() as isize, (y + radius).floor() as isize);
+        for row in top..=bottom {
+            for column in left..=right {
+                let (dx, dy) = (column as Float + 0.5 - x, row as Float + 0.5 - y);
+                let covered = if self.point_size == 1 {
+                    column == x.floor() as isize && row == y.floor() as isize
+                } else {
+                    dx * dx + dy * dy <= radius * radius
+                };
+                if covered && column >= 0 && row >= 0 {
+                    if self.blending {
+                        image.add(column as usize, row as usize, color);
+                    } else {
+                        image.set(column as usize, row as usize, color);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Sink for RenderWriter {
+    fn write(&mut self, snapshot: &Snapshot) -> io::Result<()> {
+        let image = self.render(snapshot);
+        fs::create_dir_all(&self.directory)?;
+        let path = self.directory.join(format!("frame-{}.png", snapshot.frame));
+        image.write_png(BufWriter::new(fs::File::create(path)?))
+    }
+}
+
+// Tests /////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use crate::geometry::{Point, Vector};
+    use crate::physics::Body;
+
+    use super::*;
+
+    fn snapshot() -> Snapshot {
+        let bodies = vec![
+            Body::new(100.0, Point::new(-5.0, 5.0), Vector::new(2.0, 0.0)),
+            Body::new(1.0, Point::new(5.0, -5.0), Vector::new(0.0, 1.0)),
+        ];
+        Snapshot::new(3, 4.0, &bodies).with_groups(&[0, 1])
+    }
+
+    fn viewport() -> Viewport {
+        Viewport::new(Point::new(-10.0, -10.0), Point::new(10.0, 10.0))
+    }
+
+    #[test]
+    fn render_colors_groups() {
+        // given
+        let mut sut = RenderWriter::new("unused").with_width(20).with_viewport(viewport());
+
+        // when
+        let result = sut.render(&snapshot());
+
+        // then
+        assert_eq!((20, 20), (result.width, result.height));
+        assert_eq!(PALETTE[0], result.get(5, 5));
+        assert_eq!(PALETTE[1], result.get(15, 15));
+        assert_eq!(image::BLACK, result.get(10, 10));
+    }
+
+    #[test]
+    fn render_colors_speeds_and_masses() {
+        // given
+        let mut speeds = RenderWriter::new("unused").with_width(20).with_viewport(viewport())
+            .with_coloring(Coloring::Speed);
+        let mut masses = RenderWriter::new("unused").with_width(20).with_viewport(viewport())
+            .with_coloring(Coloring::Mass);
+
+        // when
+        let speeds = speeds.render(&snapshot());
+        let masses = masses.render(&snapshot());
+
+        // then
+        assert_eq!(image::colormap(1.0), speeds.get(5, 5));
+        assert_eq!(image::colormap(0.5), speeds.get(15, 15));
+        assert_eq!(image::colormap(1.0), masses.get(5, 5));
+        assert_eq!(image::colormap(0.0), masses.get(15, 15));
+    }
+
+    #[test]
+    fn render_leaves_masses_that_are_not_positive_out_of_range() {
+        for mass in [0.0, -1.0, Float::NAN].iter() {
+            // given
+            let mut snapshot = snapshot();
+            snapshot.masses[1] = *mass;
+            let mut sut = RenderWriter::new("unused").with_width(20).with_viewport(viewport())
+                .with_coloring(Coloring::Mass);
+
+            // when
+            let result = sut.render(&snapshot);
+
+            // then
+            let log = (100.0 as Float).ln();
+            assert_eq!(Some((log, log)), sut.range);
+            assert_eq!(image::colormap(1.0), result.get(5, 5));
+            assert_eq!(image::colormap(0.0), result.get(15, 15));
+        }
+    }
+
+    #[test]
+    fn render_keeps_range_of_first_frame() {
+        // given
+        let mut sut = RenderWriter::new("unused").with_width(20).with_viewport(viewport())
+            .with_coloring(Coloring::Speed);
+        sut.render(&snapshot());
+        let mut faster = snapshot();
+        faster.velocities[1] = Vector::new(0.0, 4.0);
+
+        // when
+        let result = sut.render(&faster);
+
+        // then
+        assert_eq!(image::colormap(1.0), result.get(15, 15));
+    }
+
+    #[test]
+    fn render_draws_discs() {
+        // given a body at the center of a pixel
+        let mut snapshot = snapshot();
+        snapshot.positions[0] = Point::new(-4.5, 4.5);
+        let mut sut = RenderWriter::new("unused").with_width(20).with_viewport(viewport())
+            .with_point_size(3);
+
+        // when
+        let result = sut.render(&snapshot);
+
+        // then it covers the pixels around it
+        for (x, y) in [(5, 5), (4, 5), (6, 5), (5, 4), (5, 6), (4, 4)].iter() {
+            assert_eq!(PALETTE[0], result.get(*x, *y));
+        }
+        assert_eq!(image::BLACK, result.get(3, 5));
+        assert_eq!(image::BLACK, result.get(5, 7));
+    }
+
+    #[test]
+    fn render_blends_additively() {
+        // given two bodies at the same position
+        let mut snapshot = snapshot();
+        snapshot.positions[1] = snapshot.positions[0].clone();
+        snapshot.groups = vec![0, 0];
+        let mut sut = RenderWriter::new("unused").with_width(20).with_viewport(viewport())
+            .with_blending(true);
+
+        // when
+        let result = sut.render(&snapshot);
+
+        // then
+        assert_eq!([128, 128, 128], result.get(5, 5));
+    }
+
+    #[test]
+    fn render_fits_viewport_to_first_frame() {
+        // given
+        let mut sut = RenderWriter::new("temp-render").with_width(16);
+
+        // when
+        sut.write(&snapshot()).unwrap();
+
+        // then
+        assert!(Path::new("temp-render/frame-3.png").exists());
+        assert_eq!(Some(Viewport::fitting(&snapshot().positions, MARGIN)), sut.viewport);
+
+        // after
+        fs::remove_dir_all("temp-render").expect("Error cleaning up test.");
+    }
+
+    #[test]
+    fn coloring_parses() {
+        // then
+        assert_eq!(Ok(Coloring::Mass), "mass".parse());
+        assert!("color".parse::<Coloring>().is_err());
+    }
+}
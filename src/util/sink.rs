@@ -0,0 +1,166 @@
+use std::io;
+
+use crate::geometry::three::{Point3, Vector3};
+use crate::geometry::{Point, Vector};
+use crate::physics::three::Body3;
+use crate::physics::Body;
+use crate::Float;
+
+// Sink //////////////////////////////////////////////////////////////////////
+//
+// A destination for the states of a simulation, such as a directory of text
+// frames. An environment hands each of its sinks a snapshot after every
+// update, and finishes them once the simulation is over, so that sinks
+// writing a single file can complete it. Failures are returned to the
+// caller, which decides whether to stop the simulation.
+
+pub trait Sink {
+    fn write(&mut self, snapshot: &Snapshot) -> io::Result<()>;
+
+    /// Completes the output after the last snapshot.
+    fn finish(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// The 3D counterpart of `Sink`.
+pub trait Sink3 {
+    fn write(&mut self, snapshot: &Snapshot3) -> io::Result<()>;
+
+    /// Completes the output after the last snapshot.
+    fn finish(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Snapshot //////////////////////////////////////////////////////////////////
+//
+// The state of the bodies of an environment after an update. Frames are
+// numbered from zero, the first frame being the state after the first
+// update. A body is identified by its index in the environment, which is
+// the same in every frame, and belongs to a group, see `Loader`.
+//
+// The accelerations are those of the update, so they were evaluated at the
+// positions before it. A snapshot that wasn't taken by an environment, such
+// as one read from a file, may have none.
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Snapshot {
+    pub frame: usize,
+    pub time: Float,
+    pub ids: Vec<usize>,
+    pub groups: Vec<usize>,
+    pub masses: Vec<Float>,
+    pub positions: Vec<Point>,
+    pub velocities: Vec<Vector>,
+    pub accelerations: Option<Vec<Vector>>,
+}
+
+impl Snapshot {
+    /// Takes a snapshot of the given bodies, all in group zero.
+    pub fn new(frame: usize, time: Float, bodies: &[Body]) -> Snapshot {
+        Snapshot {
+            frame,
+            time,
+            ids: (0..bodies.len()).collect(),
+            groups: vec![0; bodies.len()],
+            masses: bodies.iter().map(|body| body.mass.value()).collect(),
+            positions: bodies.iter().map(|body| body.position.clone()).collect(),
+            velocities: bodies.iter().map(|body| body.velocity.clone()).collect(),
+            accelerations: None,
+        }
+    }
+
+    pub fn with_groups(mut self, groups: &[usize]) -> Snapshot {
+        assert_eq!(self.len(), groups.len(), "Each body must have a group.");
+        self.groups = groups.to_vec();
+        self
+    }
+
+    pub fn with_accelerations(mut self, accelerations: Vec<Vector>) -> Snapshot {
+        assert_eq!(self.len(), accelerations.len(), "Each body must have an acceleration.");
+        self.accelerations = Some(accelerations);
+        self
+    }
+
+    /// The number of bodies.
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+}
+
+/// The 3D counterpart of `Snapshot`.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Snapshot3 {
+    pub frame: usize,
+    pub time: Float,
+    pub ids: Vec<usize>,
+    pub masses: Vec<Float>,
+    pub positions: Vec<Point3>,
+    pub velocities: Vec<Vector3>,
+}
+
+impl Snapshot3 {
+    pub fn new(frame: usize, time: Float, bodies: &[Body3]) -> Snapshot3 {
+        Snapshot3 {
+            frame,
+            time,
+            ids: (0..bodies.len()).collect(),
+            masses: bodies.iter().map(|body| body.mass.value()).collect(),
+            positions: bodies.iter().map(|body| body.position.clone()).collect(),
+            velocities: bodies.iter().map(|body| body.velocity.clone()).collect(),
+        }
+    }
+
+    /// The number of bodies.
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+}
+
+// Tests /////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snapshot_of_bodies() {
+        // given
+        let bodies = vec![
+            Body::new(2.0, Point::new(1.0, 2.0), Vector::new(0.5, 0.0)),
+            Body::new(3.0, Point::new(-1.0, 4.0), Vector::new(0.0, -1.5)),
+        ];
+
+        // when
+        let result = Snapshot::new(4, 5.0, &bodies);
+
+        // then
+        assert_eq!((4, 5.0), (result.frame, result.time));
+        assert_eq!(2, result.len());
+        assert_eq!(vec![0, 1], result.ids);
+        assert_eq!(vec![0, 0], result.groups);
+        assert_eq!(vec![2.0, 3.0], result.masses);
+        assert_eq!(vec![Point::new(1.0, 2.0), Point::new(-1.0, 4.0)], result.positions);
+        assert_eq!(vec![Vector::new(0.5, 0.0), Vector::new(0.0, -1.5)], result.velocities);
+        assert_eq!(None, result.accelerations);
+    }
+
+    #[test]
+    #[should_panic(expected = "Each body must have a group.")]
+    fn snapshot_with_missing_groups() {
+        // given
+        let bodies = vec![Body::new(2.0, Point::zero(), Vector::zero())];
+
+        // when
+        let _ = Snapshot::new(0, 1.0, &bodies).with_groups(&[]);
+    }
+}
@@ -0,0 +1,381 @@
+use std::convert::TryFrom;
+use std::io::{self, Read, Write};
+
+use crate::geometry::three::{Point3, Vector3};
+use crate::physics::force::MIN_DISTANCE;
+use crate::Float;
+
+use super::binary::{self, invalid, narrow, wide, widen, Order, ParticleFile, ParticleWriter};
+
+// Tipsy /////////////////////////////////////////////////////////////////////
+//
+// A snapshot in the binary TIPSY format, as read by pynbody and written by
+// codes such as ChaNGa and Gasoline. A header is followed by the gas, dark
+// and star particles, in that order, each a record of single precision
+// floats:
+//
+//   header  time as f64, then the number of particles, dimensions, gas,
+//           dark and star particles and padding, as 4 byte integers
+//   gas     mass, x, y, z, vx, vy, vz, density, temperature, softening,
+//           metals, potential
+//   dark    mass, x, y, z, vx, vy, vz, softening, potential
+//   star    mass, x, y, z, vx, vy, vz, metals, formation time, softening,
+//           potential
+//
+// Files are big endian by convention. Native little endian files are read
+// as well, telling the byte order by the number of dimensions.
+//
+// Newton's bodies interact only by gravity, so they are written as dark
+// particles, with the minimum distance of the fields as their softening.
+// Softenings are read, but bodies interact with the fields' own. Values
+// that Newton doesn't have are written as zero, and 2D bodies lie in the
+// plane z = 0.
+
+/// The size of the header, including its padding.
+const HEADER_SIZE: usize = 32;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Kind {
+    Gas,
+    Dark,
+    Star,
+}
+
+impl Kind {
+    /// The number of floats of a particle.
+    fn fields(self) -> usize {
+        match self {
+            Kind::Gas => 12,
+            Kind::Dark => 9,
+            Kind::Star => 11,
+        }
+    }
+
+    /// The index of the softening among the floats of a particle.
+    fn softening(self) -> usize {
+        match self {
+            Kind::Gas => 9,
+            Kind::Dark => 7,
+            Kind::Star => 9,
+        }
+    }
+}
+
+const KINDS: [Kind; 3] = [Kind::Gas, Kind::Dark, Kind::Star];
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Tipsy {
+    pub time: Float,
+    pub kinds: Vec<Kind>,
+    pub masses: Vec<Float>,
+    pub positions: Vec<Point3>,
+    pub velocities: Vec<Vector3>,
+    pub softenings: Vec<Float>,
+}
+
+impl ParticleFile for Tipsy {
+    /// Creates dark particles, softened by the minimum distance of the
+    /// fields. TIPSY has no ids.
+    fn from_particles(time: Float, _: &[usize], masses: &[Float],
+                      positions: Vec<Point3>, velocities: Vec<Vector3>) -> Tipsy {
+        Tipsy {
+            time,
+            kinds: vec![Kind::Dark; masses.len()],
+            masses: masses.to_vec(),
+            positions,
+            velocities,
+            softenings: vec![MIN_DISTANCE; masses.len()],
+        }
+    }
+
+    fn masses(&self) -> &[Float] {
+        &self.masses
+    }
+
+    fn positions(&self) -> &[Point3] {
+        &self.positions
+    }
+
+    fn velocities(&self) -> &[Vector3] {
+        &self.velocities
+    }
+
+    fn read<R: Read>(mut input: R) -> io::Result<Tipsy> {
+        let mut header = [0; HEADER_SIZE];
+        input.read_exact(&mut header)?;
+        let order = if (1..=3).contains(&Order::Big.u32(&header[12..])) {
+            Order::Big
+        } else if (1..=3).contains(&Order::Little.u32(&header[12..])) {
+            Order::Little
+        } else {
+            return Err(invalid("Not a TIPSY snapshot."));
+        };
+
+        let time = order.f64(&header);
+        let total = order.u32(&header[8..]) as usize;
+        let counts = [order.u32(&header[16..]), order.u32(&header[20..]), order.u32(&header[24..])];
+        if counts.iter().map(|&count| count as usize).sum::<usize>() != total {
+            return Err(invalid("The particle counts don't add up."));
+        }
+
+        // grown as the particles are read, as the counts may be corrupt
+        let mut tipsy = Tipsy {
+            time: narrow(time),
+            kinds: vec![],
+            masses: vec![],
+            positions: vec![],
+            velocities: vec![],
+            softenings: vec![],
+        };
+        for (kind, &count) in KINDS.iter().zip(counts.iter()) {
+            let mut bytes = vec![0; 4 * kind.fields()];
+            for _ in 0..count {
+                input.read_exact(&mut bytes)?;
+                let values: Vec<Float> = bytes.chunks(4).map(|value| widen(order.f32(value))).collect();
+                tipsy.kinds.push(*kind);
+                tipsy.masses.push(binary::positive(values[0])?);
+                tipsy.positions.push(Point3::new(values[1], values[2], values[3]));
+                tipsy.velocities.push(Vector3::new(values[4], values[5], values[6]));
+                tipsy.softenings.push(values[kind.softening()]);
+            }
+        }
+        Ok(tipsy)
+    }
+
+    fn write<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        self.write_ordered(out, Order::Big)
+    }
+
+    /// Named with five digits, as ChaNGa names its snapshots.
+    fn file_name(frame: usize) -> String {
+        format!("snapshot.{:05}", frame)
+    }
+}
+
+impl Tipsy {
+    /// The number of particles.
+    pub fn len(&self) -> usize {
+        self.kinds.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.kinds.is_empty()
+    }
+
+    fn write_ordered<W: Write>(&self, out: &mut W, order: Order) -> io::Result<()> {
+        let total = header_count(self.len())?;
+        let counts = KINDS.iter()
+            .map(|kind| header_count(self.kinds.iter().filter(|&k| k == kind).count()))
+            .collect::<io::Result<Vec<u32>>>()?;
+
+        out.write_all(&order.put_f64(wide(self.time)))?;
+        out.write_all(&order.put_u32(total))?;
+        out.write_all(&order.put_u32(3))?;
+        for count in counts {
+            out.write_all(&order.put_u32(count))?;
+        }
+        out.write_all(&[0; 4])?;
+
+        for kind in KINDS.iter() {
+            for idx in (0..self.len()).filter(|&idx| self.kinds[idx] == *kind) {
+                let (p, v) = (&self.positions[idx], &self.velocities[idx]);
+                let mut values = vec![0.0; kind.fields()];
+                values[..7].copy_from_slice(&[self.masses[idx], p.x, p.y, p.z, v.dx, v.dy, v.dz]);
+                values[kind.softening()] = self.softenings[idx];
+                for value in values {
+                    out.write_all(&order.put_f32(value))?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Returns the given number of particles as a count of the header, or an
+/// error if it doesn't fit into 4 bytes.
+fn header_count(count: usize) -> io::Result<u32> {
+    u32::try_from(count).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput,
+                                                    "Too many particles for 4 byte counts."))
+}
+
+// TipsyWriter ///////////////////////////////////////////////////////////////
+//
+// A sink writing each snapshot as a TIPSY snapshot in the specified
+// directory, named `snapshot.N` after the frame with five digits. The
+// directory is created with the first file.
+
+pub type TipsyWriter = ParticleWriter<Tipsy>;
+
+// Tests /////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io::Cursor;
+
+    use crate::geometry::{Point, Vector};
+    use crate::physics::Body;
+    use crate::util::binary::load_from_path;
+    use crate::util::sink::{Sink, Snapshot};
+
+    use super::*;
+
+    fn tipsy() -> Tipsy {
+        Tipsy {
+            time: 2.5,
+            kinds: vec![Kind::Gas, Kind::Dark, Kind::Dark, Kind::Star],
+            masses: vec![2.0, 0.5, 1.0, 3.0],
+            positions: vec![
+                Point3::new(1.5, -2.0, 0.25),
+                Point3::new(4.0, 8.0, 0.0),
+                Point3::new(0.0, 1.0, 2.0),
+                Point3::new(-3.0, 0.5, 1.0),
+            ],
+            velocities: vec![
+                Vector3::new(0.25, 3.0, -1.0),
+                Vector3::new(-1.0, 0.0, 0.5),
+                Vector3::new(0.0, 0.0, 0.0),
+                Vector3::new(2.0, 1.0, 0.0),
+            ],
+            softenings: vec![0.5, 4.0, 4.0, 1.0],
+        }
+    }
+
+    #[test]
+    fn tipsy_writes_big_endian_records() {
+        // given
+        let sut = tipsy();
+        let mut result = vec![];
+
+        // when
+        sut.write(&mut result).unwrap();
+
+        // then the header
+        assert_eq!(32 + 4 * (12 + 2 * 9 + 11), result.len());
+        assert_eq!(&2.5f64.to_be_bytes(), &result[..8]);
+        assert_eq!(&4u32.to_be_bytes(), &result[8..12]);
+        assert_eq!(&3u32.to_be_bytes(), &result[12..16]);
+        assert_eq!(&1u32.to_be_bytes(), &result[16..20]);
+        assert_eq!(&2u32.to_be_bytes(), &result[20..24]);
+        assert_eq!(&1u32.to_be_bytes(), &result[24..28]);
+
+        // and the softening of the gas particle
+        assert_eq!(&0.5f32.to_be_bytes(), &result[32 + 4 * 9..32 + 4 * 10]);
+
+        // and the mass of the first dark particle
+        assert_eq!(&0.5f32.to_be_bytes(), &result[32 + 4 * 12..32 + 4 * 13]);
+    }
+
+    #[test]
+    fn tipsy_round_trip() {
+        // given
+        let mut bytes = vec![];
+        tipsy().write(&mut bytes).unwrap();
+
+        // when
+        let result = Tipsy::read(Cursor::new(bytes)).unwrap();
+
+        // then
+        assert_eq!(tipsy(), result);
+    }
+
+    #[test]
+    fn tipsy_reads_little_endian() {
+        // given
+        let mut bytes = vec![];
+        tipsy().write_ordered(&mut bytes, Order::Little).unwrap();
+
+        // when
+        let result = Tipsy::read(Cursor::new(bytes)).unwrap();
+
+        // then
+        assert_eq!(tipsy(), result);
+    }
+
+    #[test]
+    fn tipsy_writes_particles_by_kind() {
+        // given a star before a gas particle
+        let mut sut = tipsy();
+        sut.kinds = vec![Kind::Star, Kind::Dark, Kind::Dark, Kind::Gas];
+        let mut bytes = vec![];
+
+        // when
+        sut.write(&mut bytes).unwrap();
+        let result = Tipsy::read(Cursor::new(bytes)).unwrap();
+
+        // then
+        assert_eq!(vec![Kind::Gas, Kind::Dark, Kind::Dark, Kind::Star], result.kinds);
+        assert_eq!(vec![3.0, 0.5, 1.0, 2.0], result.masses);
+    }
+
+    #[test]
+    fn tipsy_rejects_inconsistent_counts() {
+        // given
+        let mut bytes = vec![];
+        tipsy().write(&mut bytes).unwrap();
+        bytes[8..12].copy_from_slice(&5u32.to_be_bytes());
+
+        // when
+        let result = Tipsy::read(Cursor::new(bytes));
+
+        // then
+        assert_eq!(io::ErrorKind::InvalidData, result.unwrap_err().kind());
+    }
+
+    #[test]
+    fn tipsy_rejects_nan_masses() {
+        // given
+        let mut sut = tipsy();
+        sut.masses[2] = Float::NAN;
+        let mut bytes = vec![];
+        sut.write(&mut bytes).unwrap();
+
+        // when
+        let result = Tipsy::read(Cursor::new(bytes));
+
+        // then
+        assert_eq!(io::ErrorKind::InvalidData, result.unwrap_err().kind());
+    }
+
+    #[test]
+    fn tipsy_rejects_counts_beyond_4_bytes() {
+        // then
+        assert_eq!(7, header_count(7).unwrap());
+        assert_eq!(u32::MAX, header_count(u32::MAX as usize).unwrap());
+        let result = header_count(u32::MAX as usize + 1);
+        assert_eq!(io::ErrorKind::InvalidInput, result.unwrap_err().kind());
+    }
+
+    #[test]
+    fn tipsy_of_snapshot() {
+        // given
+        let bodies = vec![Body::new(2.0, Point::new(1.0, 2.0), Vector::new(0.5, 0.0))];
+
+        // when
+        let result = Tipsy::from_snapshot(&Snapshot::new(0, 1.0, &bodies));
+
+        // then
+        assert_eq!(vec![Kind::Dark], result.kinds);
+        assert_eq!(vec![Point3::new(1.0, 2.0, 0.0)], result.positions);
+        assert_eq!(vec![MIN_DISTANCE], result.softenings);
+        assert_eq!(bodies[0].position, result.bodies()[0].position);
+    }
+
+    #[test]
+    fn tipsy_writer_writes_snapshots() {
+        // given
+        let bodies = tipsy().bodies();
+        let mut sut = TipsyWriter::new("temp-tipsy");
+
+        // when
+        Sink::write(&mut sut, &Snapshot::new(12, 5.0, &bodies)).unwrap();
+
+        // then
+        let result = load_from_path::<Tipsy>("temp-tipsy/snapshot.00012").unwrap();
+        assert_eq!(bodies.len(), result.len());
+        assert_eq!(bodies[3].velocity, result[3].velocity);
+
+        // after
+        fs::remove_dir_all("temp-tipsy").expect("Error cleaning up test.");
+    }
+}
@@ -0,0 +1,511 @@
+use std::fs;
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::mem;
+use std::path::Path;
+
+use crate::geometry::{Point, Vector};
+use crate::Float;
+
+use super::binary::{invalid, narrow, wide, widen};
+use super::sink::{Sink, Snapshot};
+use super::write::{self, DataWriter, StateWriter};
+
+// Trajectory ////////////////////////////////////////////////////////////////
+//
+// A whole run in a single binary file. A header describes the bodies, and is
+// followed by the frames, which all have the same size so that any frame can
+// be read without reading the ones before it. Masses, ids and groups don't
+// change during a run, so they are stored once in the header.
+//
+// All values are little endian. Floats have the precision of the build that
+// wrote the file, given in the header, and are converted when read by the
+// other build.
+//
+//   magic       8 bytes, "NEWTRAJ" followed by a zero byte
+//   version     u32
+//   float size  u32, 4 or 8 bytes
+//   bodies      u64
+//   time step   f64
+//   metadata    u32 length followed by as many bytes of UTF-8
+//   ids         u64 per body
+//   groups      u64 per body
+//   masses      float per body
+//
+// Each frame then holds:
+//
+//   frame       u64
+//   time        f64
+//   state       x, y, vx, vy as floats per body
+
+const MAGIC: &[u8; 8] = b"NEWTRAJ\0";
+
+const VERSION: u32 = 1;
+
+/// The size of the frame number and time of a frame.
+const FRAME_PREFIX: u64 = 16;
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Header {
+    pub time_step: Float,
+    /// Free text describing the run, such as its configuration.
+    pub metadata: String,
+    pub ids: Vec<usize>,
+    pub groups: Vec<usize>,
+    pub masses: Vec<Float>,
+    float_size: usize,
+}
+
+impl Header {
+    /// The number of bodies in each frame.
+    pub fn bodies(&self) -> usize {
+        self.ids.len()
+    }
+
+    /// The size in bytes of a frame.
+    fn frame_size(&self) -> u64 {
+        FRAME_PREFIX + (4 * self.bodies() * self.float_size) as u64
+    }
+
+    fn write<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        out.write_all(MAGIC)?;
+        out.write_all(&VERSION.to_le_bytes())?;
+        out.write_all(&(self.float_size as u32).to_le_bytes())?;
+        out.write_all(&(self.bodies() as u64).to_le_bytes())?;
+        out.write_all(&wide(self.time_step).to_le_bytes())?;
+        out.write_all(&(self.metadata.len() as u32).to_le_bytes())?;
+        out.write_all(self.metadata.as_bytes())?;
+        for id in self.ids.iter() {
+            out.write_all(&(*id as u64).to_le_bytes())?;
+        }
+        for group in self.groups.iter() {
+            out.write_all(&(*group as u64).to_le_bytes())?;
+        }
+        for mass in self.masses.iter() {
+            out.write_all(&mass.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    fn read<R: Read>(input: &mut R) -> io::Result<Header> {
+        let mut magic = [0; 8];
+        input.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid("Not a trajectory file."));
+        }
+        let version = read_u32(input)?;
+        if version != VERSION {
+            return Err(invalid(&format!("Unsupported trajectory version {}.", version)));
+        }
+        let float_size = read_u32(input)? as usize;
+        if float_size != 4 && float_size != 8 {
+            return Err(invalid(&format!("Unsupported float size {}.", float_size)));
+        }
+
+        let bodies = read_u64(input)? as usize;
+        let time_step = narrow(read_f64(input)?);
+
+        let len = read_u32(input)? as usize;
+        let mut metadata = Vec::new();
+        input.take(len as u64).read_to_end(&mut metadata)?;
+        if metadata.len() != len {
+            return Err(invalid("The metadata is truncated."));
+        }
+        let metadata = String::from_utf8(metadata).map_err(|_| invalid("The metadata is not UTF-8."))?;
+
+        let ids = (0..bodies).map(|_| read_u64(input).map(|id| id as usize)).collect::<io::Result<_>>()?;
+        let groups = (0..bodies).map(|_| read_u64(input).map(|group| group as usize)).collect::<io::Result<_>>()?;
+        let masses = (0..bodies).map(|_| read_float(input, float_size)).collect::<io::Result<_>>()?;
+
+        Ok(Header { time_step, metadata, ids, groups, masses, float_size })
+    }
+
+    /// The size in bytes of the header.
+    fn size(&self) -> u64 {
+        (8 + 4 + 4 + 8 + 8 + 4 + self.metadata.len() + self.bodies() * (16 + self.float_size)) as u64
+    }
+}
+
+// TrajectoryWriter //////////////////////////////////////////////////////////
+//
+// A sink streaming snapshots into a trajectory file. The header is written
+// with the first snapshot, and every later snapshot must hold the same
+// bodies. Accelerations are not stored.
+
+pub struct TrajectoryWriter<W: Write = BufWriter<fs::File>> {
+    out: W,
+    time_step: Float,
+    metadata: String,
+    header: Option<Header>,
+}
+
+impl TrajectoryWriter {
+    /// Creates the trajectory file at the given path, replacing any existing
+    /// file.
+    pub fn create<P: AsRef<Path>>(path: P, time_step: Float) -> io::Result<TrajectoryWriter> {
+        Ok(TrajectoryWriter::new(BufWriter::new(fs::File::create(path)?), time_step))
+    }
+}
+
+impl<W: Write> TrajectoryWriter<W> {
+    pub fn new(out: W, time_step: Float) -> TrajectoryWriter<W> {
+        TrajectoryWriter { out, time_step, metadata: String::new(), header: None }
+    }
+
+    pub fn with_metadata(mut self, metadata: &str) -> TrajectoryWriter<W> {
+        self.metadata = metadata.to_owned();
+        self
+    }
+
+    /// Writes the header for the bodies of the given snapshot.
+    fn write_header(&mut self, snapshot: &Snapshot) -> io::Result<()> {
+        let header = Header {
+            time_step: self.time_step,
+            metadata: self.metadata.clone(),
+            ids: snapshot.ids.clone(),
+            groups: snapshot.groups.clone(),
+            masses: snapshot.masses.clone(),
+            float_size: mem::size_of::<Float>(),
+        };
+        header.write(&mut self.out)?;
+        self.header = Some(header);
+        Ok(())
+    }
+}
+
+impl<W: Write> Sink for TrajectoryWriter<W> {
+    fn write(&mut self, snapshot: &Snapshot) -> io::Result<()> {
+        match &self.header {
+            None => self.write_header(snapshot)?,
+            Some(header) if header.bodies() != snapshot.len() => {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, format!(
+                    "Expected {} bodies in frame {}, got {}.", header.bodies(), snapshot.frame, snapshot.len())));
+            }
+            Some(_) => (),
+        }
+
+        self.out.write_all(&(snapshot.frame as u64).to_le_bytes())?;
+        self.out.write_all(&wide(snapshot.time).to_le_bytes())?;
+        for (position, velocity) in snapshot.positions.iter().zip(snapshot.velocities.iter()) {
+            for value in [position.x, position.y, velocity.dx, velocity.dy] {
+                self.out.write_all(&value.to_le_bytes())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the header of an empty trajectory if there were no snapshots,
+    /// and flushes the file.
+    fn finish(&mut self) -> io::Result<()> {
+        if self.header.is_none() {
+            self.write_header(&Snapshot::new(0, 0.0, &[]))?;
+        }
+        self.out.flush()
+    }
+}
+
+// TrajectoryReader //////////////////////////////////////////////////////////
+
+pub struct TrajectoryReader<R: Read + Seek = BufReader<fs::File>> {
+    input: R,
+    header: Header,
+    frames: usize,
+}
+
+impl TrajectoryReader {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<TrajectoryReader> {
+        TrajectoryReader::new(BufReader::new(fs::File::open(path)?))
+    }
+}
+
+impl<R: Read + Seek> TrajectoryReader<R> {
+    /// Reads the header of the given trajectory. A trailing partial frame,
+    /// such as that of an interrupted run, is ignored.
+    pub fn new(mut input: R) -> io::Result<TrajectoryReader<R>> {
+        input.seek(SeekFrom::Start(0))?;
+        let header = Header::read(&mut input)?;
+        let len = input.seek(SeekFrom::End(0))?;
+        let frames = ((len - header.size()) / header.frame_size()) as usize;
+        Ok(TrajectoryReader { input, header, frames })
+    }
+
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    /// The number of frames.
+    pub fn len(&self) -> usize {
+        self.frames
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames == 0
+    }
+
+    /// Reads the frame at the given index, counting from zero.
+    pub fn frame(&mut self, idx: usize) -> io::Result<Snapshot> {
+        if idx >= self.frames {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, format!(
+                "The trajectory has {} frames, there is no frame {}.", self.frames, idx)));
+        }
+
+        let header = &self.header;
+        let offset = header.size() + idx as u64 * header.frame_size();
+        self.input.seek(SeekFrom::Start(offset))?;
+
+        let mut data = vec![0; header.frame_size() as usize];
+        self.input.read_exact(&mut data)?;
+        let mut data = &data[..];
+
+        let frame = read_u64(&mut data)? as usize;
+        let time = narrow(read_f64(&mut data)?);
+        let mut positions = Vec::with_capacity(header.bodies());
+        let mut velocities = Vec::with_capacity(header.bodies());
+        for _ in 0..header.bodies() {
+            let mut values = [0.0; 4];
+            for value in values.iter_mut() {
+                *value = read_float(&mut data, header.float_size)?;
+            }
+            positions.push(Point::new(values[0], values[1]));
+            velocities.push(Vector::new(values[2], values[3]));
+        }
+
+        Ok(Snapshot {
+            frame,
+            time,
+            ids: header.ids.clone(),
+            groups: header.groups.clone(),
+            masses: header.masses.clone(),
+            positions,
+            velocities,
+            accelerations: None,
+        })
+    }
+}
+
+// Conversion ////////////////////////////////////////////////////////////////
+
+/// Converts the consecutive text frames in the given directory, starting at
+/// frame zero, into a trajectory file. Returns the number of frames.
+pub fn from_frames<P: AsRef<Path>>(directory: &str, path: P, time_step: Float) -> io::Result<usize> {
+    let mut writer = TrajectoryWriter::create(path, time_step)?
+        .with_metadata(&format!("Converted from {}", directory));
+
+    let mut frames = 0;
+    while Path::new(directory).join(format!("frame-{}.txt", frames)).exists() {
+        writer.write(&write::read_frame(directory, frames)?)?;
+        frames += 1;
+    }
+    writer.finish()?;
+    Ok(frames)
+}
+
+/// Converts a trajectory file into text frames in the given directory, with
+/// the default columns of a `StateWriter`, or only positions if `positions`
+/// is set. Returns the number of frames.
+pub fn to_frames<P: AsRef<Path>>(path: P, directory: &str, positions: bool) -> io::Result<usize> {
+    let mut reader = TrajectoryReader::open(path)?;
+    let mut sink: Box<dyn Sink> = if positions {
+        Box::new(DataWriter::new(directory))
+    } else {
+        Box::new(StateWriter::new(directory))
+    };
+
+    for idx in 0..reader.len() {
+        sink.write(&reader.frame(idx)?)?;
+    }
+    sink.finish()?;
+    Ok(reader.len())
+}
+
+// Reading ///////////////////////////////////////////////////////////////////
+
+fn read_u32<R: Read>(input: &mut R) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    input.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64<R: Read>(input: &mut R) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    input.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn read_f64<R: Read>(input: &mut R) -> io::Result<f64> {
+    let mut bytes = [0; 8];
+    input.read_exact(&mut bytes)?;
+    Ok(f64::from_le_bytes(bytes))
+}
+
+/// Reads a float of the given size, converting it to a `Float`.
+fn read_float<R: Read>(input: &mut R, size: usize) -> io::Result<Float> {
+    if size == 4 {
+        let mut bytes = [0; 4];
+        input.read_exact(&mut bytes)?;
+        Ok(widen(f32::from_le_bytes(bytes)))
+    } else {
+        Ok(narrow(read_f64(input)?))
+    }
+}
+
+// Tests /////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use crate::physics::Body;
+
+    use super::*;
+
+    fn snapshots() -> Vec<Snapshot> {
+        let mut bodies = vec![
+            Body::new(2.0, Point::new(1.5, -2.0), Vector::new(0.25, 3.0)),
+            Body::new(0.5, Point::new(4.0, 8.0), Vector::new(-1.0, 0.0)),
+            Body::new(1.0, Point::new(-3.0, 0.5), Vector::zero()),
+        ];
+        (0..4).map(|frame| {
+            for body in bodies.iter_mut() {
+                body.apply_velocity();
+            }
+            Snapshot::new(frame, (frame + 1) as Float, &bodies).with_groups(&[0, 1, 1])
+        }).collect()
+    }
+
+    fn written(snapshots: &[Snapshot]) -> Vec<u8> {
+        let mut writer = TrajectoryWriter::new(vec![], 1.0).with_metadata("solar system");
+        for snapshot in snapshots {
+            writer.write(snapshot).unwrap();
+        }
+        writer.finish().unwrap();
+        writer.out
+    }
+
+    #[test]
+    fn trajectory_has_fixed_layout() {
+        // given
+        let snapshots = snapshots();
+
+        // when
+        let result = written(&snapshots);
+
+        // then
+        let size = mem::size_of::<Float>();
+        let header = 8 + 4 + 4 + 8 + 8 + 4 + 12 + 3 * (16 + size);
+        let frame = 16 + 3 * 4 * size;
+        assert_eq!(header + 4 * frame, result.len());
+        assert_eq!(b"NEWTRAJ\0", &result[..8]);
+        assert_eq!(3, read_u64(&mut &result[16..]).unwrap());
+
+        // the second frame starts with its number and time
+        let second = header + frame;
+        assert_eq!(1, read_u64(&mut &result[second..]).unwrap());
+        assert_eq!(2.0, read_f64(&mut &result[second + 8..]).unwrap());
+    }
+
+    #[test]
+    fn trajectory_reads_frames_in_any_order() {
+        // given
+        let snapshots = snapshots();
+        let mut sut = TrajectoryReader::new(Cursor::new(written(&snapshots))).unwrap();
+
+        // then
+        assert_eq!(4, sut.len());
+        assert_eq!(1.0, sut.header().time_step);
+        assert_eq!("solar system", sut.header().metadata);
+        assert_eq!(vec![0, 1, 1], sut.header().groups);
+        assert_eq!(snapshots[3], sut.frame(3).unwrap());
+        assert_eq!(snapshots[0], sut.frame(0).unwrap());
+        assert_eq!(snapshots[2], sut.frame(2).unwrap());
+        assert_eq!(io::ErrorKind::UnexpectedEof, sut.frame(4).unwrap_err().kind());
+    }
+
+    #[test]
+    fn trajectory_ignores_partial_frame() {
+        // given
+        let mut data = written(&snapshots());
+        data.truncate(data.len() - 5);
+
+        // when
+        let sut = TrajectoryReader::new(Cursor::new(data)).unwrap();
+
+        // then
+        assert_eq!(3, sut.len());
+    }
+
+    #[test]
+    fn trajectory_without_frames() {
+        // given
+        let data = written(&[]);
+
+        // when
+        let sut = TrajectoryReader::new(Cursor::new(data)).unwrap();
+
+        // then
+        assert!(sut.is_empty());
+        assert_eq!(0, sut.header().bodies());
+    }
+
+    #[test]
+    fn trajectory_rejects_other_files() {
+        // given
+        let data = b"x,y\n1,2\n".to_vec();
+
+        // when
+        let result = TrajectoryReader::new(Cursor::new(data));
+
+        // then
+        assert_eq!(io::ErrorKind::InvalidData, result.err().unwrap().kind());
+    }
+
+    #[test]
+    fn trajectory_rejects_truncated_metadata() {
+        // given a metadata length larger than the file
+        let mut data = written(&snapshots());
+        data[32..36].copy_from_slice(&u32::MAX.to_le_bytes());
+
+        // when
+        let result = TrajectoryReader::new(Cursor::new(data));
+
+        // then
+        assert_eq!(io::ErrorKind::InvalidData, result.err().unwrap().kind());
+    }
+
+    #[test]
+    fn trajectory_writer_rejects_changing_bodies() {
+        // given
+        let snapshots = snapshots();
+        let mut sut = TrajectoryWriter::new(vec![], 1.0);
+        sut.write(&snapshots[0]).unwrap();
+
+        // when
+        let result = sut.write(&Snapshot::new(1, 2.0, &[]));
+
+        // then
+        assert_eq!(io::ErrorKind::InvalidInput, result.unwrap_err().kind());
+    }
+
+    #[test]
+    fn trajectory_converts_to_and_from_frames() {
+        // given
+        let snapshots = snapshots();
+        fs::write("temp-trajectory.bin", written(&snapshots)).expect("Error writing file.");
+
+        // when
+        let exported = to_frames("temp-trajectory.bin", "temp-trajectory", false).unwrap();
+        let imported = from_frames("temp-trajectory", "temp-trajectory-2.bin", 1.0).unwrap();
+
+        // then
+        assert_eq!((4, 4), (exported, imported));
+        let mut result = TrajectoryReader::open("temp-trajectory-2.bin").unwrap();
+        assert_eq!("Converted from temp-trajectory", result.header().metadata);
+        for (idx, snapshot) in snapshots.iter().enumerate() {
+            assert_eq!(*snapshot, result.frame(idx).unwrap());
+        }
+
+        // after
+        fs::remove_file("temp-trajectory.bin").expect("Error cleaning up test.");
+        fs::remove_file("temp-trajectory-2.bin").expect("Error cleaning up test.");
+        fs::remove_dir_all("temp-trajectory").expect("Error cleaning up test.");
+    }
+}
@@ -0,0 +1,274 @@
+use std::fs;
+use std::io::{self, BufWriter, Write};
+use std::mem;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::Float;
+
+use super::sink::{Sink, Snapshot};
+
+// VtkWriter /////////////////////////////////////////////////////////////////
+//
+// A sink writing each snapshot as a legacy VTK polydata file, to inspect
+// runs in ParaView. Bodies are points in the plane z = 0, each with a
+// vertex so that they are rendered, and carry their mass, velocity and
+// group as point attributes. Once finished, a ParaView collection file
+// lists the frames with their times, so the whole run can be opened as a
+// time series.
+//
+// Frames are written as ASCII or binary. Binary legacy files are big
+// endian, and hold floats of the precision of the build.
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum VtkFormat {
+    Ascii,
+    Binary,
+}
+
+impl FromStr for VtkFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ascii" => Ok(VtkFormat::Ascii),
+            "binary" => Ok(VtkFormat::Binary),
+            _ => Err(format!("Unknown VTK format '{}'. Expected ascii or binary.", s)),
+        }
+    }
+}
+
+/// The name of the collection file.
+pub const COLLECTION: &str = "frames.pvd";
+
+pub struct VtkWriter {
+    directory: String,
+    format: VtkFormat,
+    /// The time and file name of each written frame.
+    frames: Vec<(Float, String)>,
+}
+
+impl VtkWriter {
+    /// Writes ASCII frames into the given directory.
+    pub fn new(directory: &str) -> VtkWriter {
+        VtkWriter { directory: directory.to_owned(), format: VtkFormat::Ascii, frames: vec![] }
+    }
+
+    pub fn with_format(mut self, format: VtkFormat) -> VtkWriter {
+        self.format = format;
+        self
+    }
+
+    fn write_frame<W: Write>(&self, out: &mut W, snapshot: &Snapshot) -> io::Result<()> {
+        let n = snapshot.len();
+        let float = if mem::size_of::<Float>() == 4 { "float" } else { "double" };
+        let format = match self.format {
+            VtkFormat::Ascii => "ASCII",
+            VtkFormat::Binary => "BINARY",
+        };
+
+        writeln!(out, "# vtk DataFile Version 3.0")?;
+        writeln!(out, "Newton frame {}, time {}", snapshot.frame, snapshot.time)?;
+        writeln!(out, "{}", format)?;
+        writeln!(out, "DATASET POLYDATA")?;
+
+        writeln!(out, "POINTS {} {}", n, float)?;
+        self.write_floats(out, snapshot.positions.iter().map(|p| [p.x, p.y, 0.0]))?;
+
+        writeln!(out, "VERTICES {} {}", n, 2 * n)?;
+        self.write_ints(out, (0..n).map(|idx| [1, idx as i32]))?;
+
+        writeln!(out, "POINT_DATA {}", n)?;
+        writeln!(out, "SCALARS mass {} 1", float)?;
+        writeln!(out, "LOOKUP_TABLE default")?;
+        self.write_floats(out, snapshot.masses.iter().map(|mass| [*mass]))?;
+
+        writeln!(out, "VECTORS velocity {}", float)?;
+        self.write_floats(out, snapshot.velocities.iter().map(|v| [v.dx, v.dy, 0.0]))?;
+
+        writeln!(out, "SCALARS group int 1")?;
+        writeln!(out, "LOOKUP_TABLE default")?;
+        self.write_ints(out, snapshot.groups.iter().map(|group| [*group as i32]))
+    }
+
+    /// Writes the given rows of floats, each on a line in ASCII.
+    fn write_floats<W, I, const N: usize>(&self, out: &mut W, rows: I) -> io::Result<()>
+        where W: Write, I: Iterator<Item=[Float; N]> {
+        for row in rows {
+            match self.format {
+                VtkFormat::Ascii => writeln!(out, "{}", join(&row))?,
+                VtkFormat::Binary => for value in row.iter() {
+                    out.write_all(&value.to_be_bytes())?;
+                },
+            }
+        }
+        self.end_binary(out)
+    }
+
+    /// Writes the given rows of integers, each on a line in ASCII.
+    fn write_ints<W, I, const N: usize>(&self, out: &mut W, rows: I) -> io::Result<()>
+        where W: Write, I: Iterator<Item=[i32; N]> {
+        for row in rows {
+            match self.format {
+                VtkFormat::Ascii => writeln!(out, "{}", join(&row))?,
+                VtkFormat::Binary => for value in row.iter() {
+                    out.write_all(&value.to_be_bytes())?;
+                },
+            }
+        }
+        self.end_binary(out)
+    }
+
+    /// Ends a block of binary data with a newline, before the next keyword.
+    fn end_binary<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        if self.format == VtkFormat::Binary {
+            writeln!(out)?;
+        }
+        Ok(())
+    }
+
+    fn write_collection<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        writeln!(out, r#"<?xml version="1.0"?>"#)?;
+        writeln!(out, r#"<VTKFile type="Collection" version="0.1" byte_order="LittleEndian">"#)?;
+        writeln!(out, "  <Collection>")?;
+        for (time, file) in self.frames.iter() {
+            writeln!(out, r#"    <DataSet timestep="{}" group="" part="0" file="{}"/>"#, time, file)?;
+        }
+        writeln!(out, "  </Collection>")?;
+        writeln!(out, "</VTKFile>")
+    }
+}
+
+fn join<T: ToString>(values: &[T]) -> String {
+    values.iter().map(|value| value.to_string()).collect::<Vec<String>>().join(" ")
+}
+
+impl Sink for VtkWriter {
+    fn write(&mut self, snapshot: &Snapshot) -> io::Result<()> {
+        fs::create_dir_all(&self.directory)?;
+        let name = format!("frame-{}.vtk", snapshot.frame);
+        let mut file = BufWriter::new(fs::File::create(Path::new(&self.directory).join(&name))?);
+        self.write_frame(&mut file, snapshot)?;
+        file.flush()?;
+        self.frames.push((snapshot.time, name));
+        Ok(())
+    }
+
+    /// Writes the collection of the written frames.
+    fn finish(&mut self) -> io::Result<()> {
+        fs::create_dir_all(&self.directory)?;
+        let mut file = BufWriter::new(fs::File::create(Path::new(&self.directory).join(COLLECTION))?);
+        self.write_collection(&mut file)?;
+        file.flush()
+    }
+}
+
+// Tests /////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use crate::geometry::{Point, Vector};
+    use crate::physics::Body;
+
+    use super::*;
+
+    fn snapshot(frame: usize) -> Snapshot {
+        let bodies = vec![
+            Body::new(2.0, Point::new(1.5, -2.0), Vector::new(0.25, 3.0)),
+            Body::new(0.5, Point::new(4.0, 8.0), Vector::new(-1.0, 0.0)),
+        ];
+        Snapshot::new(frame, (frame + 1) as Float * 0.5, &bodies).with_groups(&[0, 3])
+    }
+
+    #[test]
+    fn vtk_writes_ascii_polydata() {
+        // given
+        let sut = VtkWriter::new("unused");
+        let mut result = vec![];
+
+        // when
+        sut.write_frame(&mut result, &snapshot(2)).unwrap();
+
+        // then
+        let float = if mem::size_of::<Float>() == 4 { "float" } else { "double" };
+        let expected = format!("\
+# vtk DataFile Version 3.0
+Newton frame 2, time 1.5
+ASCII
+DATASET POLYDATA
+POINTS 2 {0}
+1.5 -2 0
+4 8 0
+VERTICES 2 4
+1 0
+1 1
+POINT_DATA 2
+SCALARS mass {0} 1
+LOOKUP_TABLE default
+2
+0.5
+VECTORS velocity {0}
+0.25 3 0
+-1 0 0
+SCALARS group int 1
+LOOKUP_TABLE default
+0
+3
+", float);
+        assert_eq!(expected, String::from_utf8(result).unwrap());
+    }
+
+    #[test]
+    fn vtk_writes_big_endian_binary() {
+        // given
+        let sut = VtkWriter::new("unused").with_format(VtkFormat::Binary);
+        let mut result = vec![];
+
+        // when
+        sut.write_frame(&mut result, &snapshot(0)).unwrap();
+
+        // then the points follow their keyword
+        let size = mem::size_of::<Float>();
+        let start = result.windows(5).position(|bytes| bytes == b"POINT").unwrap();
+        let start = start + result[start..].iter().position(|byte| *byte == b'\n').unwrap() + 1;
+        assert_eq!(&(1.5 as Float).to_be_bytes(), &result[start..start + size]);
+        assert_eq!(b'\n', result[start + 6 * size]);
+        assert_eq!(b"VERTICES 2 4\n", &result[start + 6 * size + 1..start + 6 * size + 14]);
+
+        // and the groups end the file
+        let end = result.len();
+        assert_eq!(&3i32.to_be_bytes(), &result[end - 5..end - 1]);
+    }
+
+    #[test]
+    fn vtk_writes_frames_and_collection() {
+        // given
+        let mut sut = VtkWriter::new("temp-vtk");
+
+        // when
+        sut.write(&snapshot(0)).unwrap();
+        sut.write(&snapshot(1)).unwrap();
+        sut.finish().unwrap();
+
+        // then
+        assert!(Path::new("temp-vtk/frame-0.vtk").exists());
+        assert!(Path::new("temp-vtk/frame-1.vtk").exists());
+
+        let collection = fs::read_to_string("temp-vtk/frames.pvd").expect("Error reading file.");
+        let datasets: Vec<&str> = collection.lines().filter(|line| line.contains("<DataSet")).collect();
+        assert_eq!(vec![
+            r#"    <DataSet timestep="0.5" group="" part="0" file="frame-0.vtk"/>"#,
+            r#"    <DataSet timestep="1" group="" part="0" file="frame-1.vtk"/>"#,
+        ], datasets);
+
+        // after
+        fs::remove_dir_all("temp-vtk").expect("Error cleaning up test.");
+    }
+
+    #[test]
+    fn vtk_format_parses() {
+        // then
+        assert_eq!(Ok(VtkFormat::Binary), "binary".parse());
+        assert!("xml".parse::<VtkFormat>().is_err());
+    }
+}
@@ -1,48 +1,249 @@
 use std::fs;
-use std::io::Write;
+use std::io::{self, BufWriter, Write};
 use std::path::Path;
+use std::str::FromStr;
 
-use crate::geometry::Point;
+use crate::geometry::{Point, Vector};
+use crate::physics::TIME_STEP;
+use crate::Float;
+
+use super::sink::{Sink, Sink3, Snapshot, Snapshot3};
 
 // DataWriter ////////////////////////////////////////////////////////////////
 //
-// A utility object to simplify persistence of point data. Each call to
-// write will generate a new file in the specified directory. Files are
-// sequentially numbered.
+// A sink writing the positions of each snapshot to a new file in the
+// specified directory, with each point on a separate line. Files are
+// numbered by frame. The directory is created with the first file.
 
 pub struct DataWriter {
     directory: String,
-    counter: u32,
 }
 
 impl DataWriter {
-    /// Creates a new directory in the current working path.
     pub fn new(directory: &str) -> DataWriter {
-        if !Path::new(directory).exists() {
-            fs::create_dir(directory)
-                .expect("Couldn't create dir.");
+        DataWriter { directory: directory.to_owned() }
+    }
+}
+
+/// Creates the file of the given frame in the given directory.
+fn create_frame(directory: &str, frame: usize) -> io::Result<BufWriter<fs::File>> {
+    fs::create_dir_all(directory)?;
+    let path = Path::new(directory).join(format!("frame-{}.txt", frame));
+    Ok(BufWriter::new(fs::File::create(path)?))
+}
+
+impl Sink for DataWriter {
+    fn write(&mut self, snapshot: &Snapshot) -> io::Result<()> {
+        let mut file = create_frame(&self.directory, snapshot.frame)?;
+        for point in snapshot.positions.iter() {
+            writeln!(file, "{},{}", point.x, point.y)?;
+        }
+        file.flush()
+    }
+}
+
+impl Sink3 for DataWriter {
+    fn write(&mut self, snapshot: &Snapshot3) -> io::Result<()> {
+        let mut file = create_frame(&self.directory, snapshot.frame)?;
+        for point in snapshot.positions.iter() {
+            writeln!(file, "{},{},{}", point.x, point.y, point.z)?;
+        }
+        file.flush()
+    }
+}
+
+// StateWriter ///////////////////////////////////////////////////////////////
+//
+// A sink writing the full state of each snapshot as a frame file like those
+// of a `DataWriter`, with a header row naming the columns followed by a line
+// per body. The columns are chosen from those of `Column`, in any order.
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Column {
+    Id,
+    Group,
+    Mass,
+    X,
+    Y,
+    Vx,
+    Vy,
+    Ax,
+    Ay,
+}
+
+/// The columns written by default. Accelerations are left out.
+pub const DEFAULT_COLUMNS: [Column; 7] = [
+    Column::Id, Column::Group, Column::Mass, Column::X, Column::Y, Column::Vx, Column::Vy,
+];
+
+impl Column {
+    pub fn name(self) -> &'static str {
+        match self {
+            Column::Id => "id",
+            Column::Group => "group",
+            Column::Mass => "mass",
+            Column::X => "x",
+            Column::Y => "y",
+            Column::Vx => "vx",
+            Column::Vy => "vy",
+            Column::Ax => "ax",
+            Column::Ay => "ay",
+        }
+    }
+
+    /// Writes the value of this column for the body at the given index.
+    fn write<W: Write>(self, out: &mut W, snapshot: &Snapshot, idx: usize) -> io::Result<()> {
+        match self {
+            Column::Id => write!(out, "{}", snapshot.ids[idx]),
+            Column::Group => write!(out, "{}", snapshot.groups[idx]),
+            Column::Mass => write!(out, "{}", snapshot.masses[idx]),
+            Column::X => write!(out, "{}", snapshot.positions[idx].x),
+            Column::Y => write!(out, "{}", snapshot.positions[idx].y),
+            Column::Vx => write!(out, "{}", snapshot.velocities[idx].dx),
+            Column::Vy => write!(out, "{}", snapshot.velocities[idx].dy),
+            Column::Ax => write!(out, "{}", accelerations(snapshot)?[idx].dx),
+            Column::Ay => write!(out, "{}", accelerations(snapshot)?[idx].dy),
         }
-        DataWriter {
-            directory: directory.to_owned(),
-            counter: 0
+    }
+}
+
+impl FromStr for Column {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let columns = [Column::Id, Column::Group, Column::Mass, Column::X, Column::Y,
+                       Column::Vx, Column::Vy, Column::Ax, Column::Ay];
+        columns.iter()
+            .find(|column| column.name() == s)
+            .cloned()
+            .ok_or_else(|| format!("Unknown column '{}'.", s))
+    }
+}
+
+fn accelerations(snapshot: &Snapshot) -> io::Result<&[Vector]> {
+    snapshot.accelerations.as_deref()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "The snapshot has no accelerations."))
+}
+
+pub struct StateWriter {
+    directory: String,
+    columns: Vec<Column>,
+}
+
+impl StateWriter {
+    /// Writes the default columns into the given directory.
+    pub fn new(directory: &str) -> StateWriter {
+        StateWriter { directory: directory.to_owned(), columns: DEFAULT_COLUMNS.to_vec() }
+    }
+
+    pub fn with_columns(mut self, columns: Vec<Column>) -> StateWriter {
+        assert!(!columns.is_empty(), "At least one column must be written.");
+        self.columns = columns;
+        self
+    }
+}
+
+impl Sink for StateWriter {
+    fn write(&mut self, snapshot: &Snapshot) -> io::Result<()> {
+        let mut file = create_frame(&self.directory, snapshot.frame)?;
+
+        let names: Vec<&str> = self.columns.iter().map(|column| column.name()).collect();
+        writeln!(file, "{}", names.join(","))?;
+
+        for idx in 0..snapshot.len() {
+            for (i, column) in self.columns.iter().enumerate() {
+                if i > 0 {
+                    write!(file, ",")?;
+                }
+                column.write(&mut file, snapshot, idx)?;
+            }
+            writeln!(file)?;
         }
+        file.flush()
     }
+}
+
+// Reading ///////////////////////////////////////////////////////////////////
+//
+// Frames written by either writer can be read back. A frame starting with a
+// header row is read by its columns, and any other frame as lines of `x,y`.
+// Columns missing from a frame take defaults: a body is identified by its
+// line, and has group zero, unit mass and no velocity. The time of a frame
+// is that of the update which produced it.
+
+/// Reads the frame file of the given frame from the given directory.
+pub fn read_frame(directory: &str, frame: usize) -> io::Result<Snapshot> {
+    let path = Path::new(directory).join(format!("frame-{}.txt", frame));
+    let contents = fs::read_to_string(path)?;
+    let mut lines = contents.lines().peekable();
+
+    let header = lines.peek()
+        .and_then(|line| line.split(',').map(|name| name.parse()).collect::<Result<Vec<Column>, _>>().ok());
+    let columns = match header {
+        Some(columns) => {
+            lines.next();
+            columns
+        }
+        None => vec![Column::X, Column::Y],
+    };
+
+    let mut snapshot = Snapshot {
+        frame,
+        time: (frame + 1) as Float * TIME_STEP,
+        ids: vec![],
+        groups: vec![],
+        masses: vec![],
+        positions: vec![],
+        velocities: vec![],
+        accelerations: None,
+    };
+    let mut accelerations = vec![];
+
+    for (idx, line) in lines.enumerate() {
+        let values: Vec<&str> = line.split(',').collect();
+        if values.len() != columns.len() {
+            return Err(invalid(format!("Expected {} values in line {} of frame {}.", columns.len(), idx + 1, frame)));
+        }
+
+        let (mut id, mut group, mut mass) = (idx, 0, 1.0);
+        let mut position = Point::zero();
+        let mut velocity = Vector::zero();
+        let mut acceleration = Vector::zero();
 
-    /// Creates a new file in the writers directory with each point written
-    /// on a separate line.
-    pub fn write(&mut self, points: Vec<Point>) {
-        let path = format!("{}/frame-{}.txt", self.directory, self.counter);
-        if let Err(e) = self.write_points(points, path) {
-            panic!("Error writing data. {}", e)
+        for (column, value) in columns.iter().zip(values) {
+            match column {
+                Column::Id => id = parse(value)?,
+                Column::Group => group = parse(value)?,
+                Column::Mass => mass = parse(value)?,
+                Column::X => position.x = parse(value)?,
+                Column::Y => position.y = parse(value)?,
+                Column::Vx => velocity.dx = parse(value)?,
+                Column::Vy => velocity.dy = parse(value)?,
+                Column::Ax => acceleration.dx = parse(value)?,
+                Column::Ay => acceleration.dy = parse(value)?,
+            }
         }
-        self.counter += 1;
+
+        snapshot.ids.push(id);
+        snapshot.groups.push(group);
+        snapshot.masses.push(mass);
+        snapshot.positions.push(position);
+        snapshot.velocities.push(velocity);
+        accelerations.push(acceleration);
     }
 
-    fn write_points(&self, points: Vec<Point>, path: String) -> std::io::Result<()> {
-        let mut file = fs::File::create(path)?;
-        for point in points { writeln!(file, "{},{}", point.x, point.y)?; }
-        Ok(())
+    if columns.contains(&Column::Ax) && columns.contains(&Column::Ay) {
+        snapshot.accelerations = Some(accelerations);
     }
+    Ok(snapshot)
+}
+
+fn parse<T: FromStr>(value: &str) -> io::Result<T> {
+    value.trim().parse().map_err(|_| invalid(format!("Invalid value '{}'.", value)))
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
 }
 
 // Tests /////////////////////////////////////////////////////////////////////
@@ -50,16 +251,26 @@ impl DataWriter {
 #[cfg(test)]
 mod tests {
     use std::io::Read;
+
+    use crate::geometry::three::{Point3, Vector3};
+    use crate::geometry::Point;
+    use crate::physics::three::Body3;
+    use crate::physics::Body;
+
     use super::*;
 
+    fn snapshot(frame: usize, point: Point) -> Snapshot {
+        Snapshot::new(frame, frame as crate::Float, &[Body::new(1.0, point, Vector::zero())])
+    }
+
     #[test]
     fn data_writer_writes() {
         // given
         let mut writer = DataWriter::new("temp");
 
         // when
-        writer.write(vec![Point::new(3.4, 6.7)]);
-        writer.write(vec![Point::new(6.4, 6.785)]);
+        Sink::write(&mut writer, &snapshot(0, Point::new(3.4, 6.7))).unwrap();
+        Sink::write(&mut writer, &snapshot(1, Point::new(6.4, 6.785))).unwrap();
 
         // then
         let mut file = fs::File::open("temp/frame-0.txt").expect("Error opening file.");
@@ -75,4 +286,148 @@ mod tests {
         // after
         fs::remove_dir_all("temp").expect("Error cleaning up test.");
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn data_writer_writes_3d_points() {
+        // given
+        let mut writer = DataWriter::new("temp3");
+
+        // when
+        let body = Body3::new(1.0, Point3::new(3.4, 6.7, -1.5), Vector3::zero());
+        Sink3::write(&mut writer, &Snapshot3::new(0, 1.0, &[body])).unwrap();
+
+        // then
+        let mut file = fs::File::open("temp3/frame-0.txt").expect("Error opening file.");
+        let mut contents = String::new();
+        let _ = file.read_to_string(&mut contents);
+        assert_eq!(contents, "3.4,6.7,-1.5\n".to_owned());
+
+        // after
+        fs::remove_dir_all("temp3").expect("Error cleaning up test.");
+    }
+
+    #[test]
+    fn data_writer_returns_io_errors() {
+        // given a file in place of the directory
+        fs::write("temp-file", "").expect("Error creating file.");
+        let mut writer = DataWriter::new("temp-file");
+
+        // when
+        let result = Sink::write(&mut writer, &snapshot(0, Point::zero()));
+
+        // then
+        assert!(result.is_err());
+
+        // after
+        fs::remove_file("temp-file").expect("Error cleaning up test.");
+    }
+
+    #[test]
+    fn state_writer_writes_default_columns() {
+        // given
+        let bodies = vec![
+            Body::new(2.0, Point::new(1.5, -2.0), Vector::new(0.25, 3.0)),
+            Body::new(0.5, Point::new(4.0, 8.0), Vector::zero()),
+        ];
+        let snapshot = Snapshot::new(3, 4.0, &bodies).with_groups(&[1, 0]);
+        let mut writer = StateWriter::new("temp-state");
+
+        // when
+        writer.write(&snapshot).unwrap();
+
+        // then
+        let contents = fs::read_to_string("temp-state/frame-3.txt").expect("Error reading file.");
+        assert_eq!("id,group,mass,x,y,vx,vy\n0,1,2,1.5,-2,0.25,3\n1,0,0.5,4,8,0,0\n", contents);
+
+        // after
+        fs::remove_dir_all("temp-state").expect("Error cleaning up test.");
+    }
+
+    #[test]
+    fn state_writer_writes_selected_columns() {
+        // given
+        let bodies = vec![Body::new(2.0, Point::new(1.5, -2.0), Vector::new(0.25, 3.0))];
+        let snapshot = Snapshot::new(0, 1.0, &bodies).with_accelerations(vec![Vector::new(-0.5, 0.75)]);
+        let columns = "ay,x,id".split(',').map(|name| name.parse().unwrap()).collect();
+        let mut writer = StateWriter::new("temp-columns").with_columns(columns);
+
+        // when
+        writer.write(&snapshot).unwrap();
+
+        // then
+        let contents = fs::read_to_string("temp-columns/frame-0.txt").expect("Error reading file.");
+        assert_eq!("ay,x,id\n0.75,1.5,0\n", contents);
+
+        // after
+        fs::remove_dir_all("temp-columns").expect("Error cleaning up test.");
+    }
+
+    #[test]
+    fn state_writer_requires_accelerations() {
+        // given
+        let bodies = vec![Body::new(2.0, Point::zero(), Vector::zero())];
+        let mut writer = StateWriter::new("temp-accelerations").with_columns(vec![Column::Ax]);
+
+        // when
+        let result = writer.write(&Snapshot::new(0, 1.0, &bodies));
+
+        // then
+        assert_eq!(io::ErrorKind::InvalidInput, result.unwrap_err().kind());
+
+        // after
+        fs::remove_dir_all("temp-accelerations").expect("Error cleaning up test.");
+    }
+
+    #[test]
+    fn column_parses_names() {
+        // then
+        assert_eq!(Ok(Column::Vy), "vy".parse());
+        assert!("z".parse::<Column>().is_err());
+    }
+
+    #[test]
+    fn frames_are_read_back() {
+        // given
+        let bodies = vec![
+            Body::new(2.0, Point::new(1.5, -2.0), Vector::new(0.25, 3.0)),
+            Body::new(0.5, Point::new(4.0, 8.0), Vector::zero()),
+        ];
+        let snapshot = Snapshot::new(2, 3.0, &bodies)
+            .with_groups(&[1, 0])
+            .with_accelerations(vec![Vector::new(1.0, 2.0), Vector::new(-3.0, 0.5)]);
+        let columns = "id,group,mass,x,y,vx,vy,ax,ay".split(',').map(|name| name.parse().unwrap()).collect();
+        StateWriter::new("temp-read").with_columns(columns).write(&snapshot).unwrap();
+        Sink::write(&mut DataWriter::new("temp-read-points"), &snapshot).unwrap();
+
+        // when
+        let state = read_frame("temp-read", 2).unwrap();
+        let points = read_frame("temp-read-points", 2).unwrap();
+
+        // then
+        assert_eq!(snapshot, state);
+        assert_eq!(snapshot.positions, points.positions);
+        assert_eq!(vec![1.0, 1.0], points.masses);
+        assert_eq!(vec![Vector::zero(), Vector::zero()], points.velocities);
+        assert_eq!(None, points.accelerations);
+
+        // after
+        fs::remove_dir_all("temp-read").expect("Error cleaning up test.");
+        fs::remove_dir_all("temp-read-points").expect("Error cleaning up test.");
+    }
+
+    #[test]
+    fn frames_with_missing_values_are_invalid() {
+        // given
+        fs::create_dir_all("temp-invalid").expect("Error creating dir.");
+        fs::write("temp-invalid/frame-0.txt", "id,x,y\n0,1.5\n").expect("Error creating file.");
+
+        // when
+        let result = read_frame("temp-invalid", 0);
+
+        // then
+        assert_eq!(io::ErrorKind::InvalidData, result.unwrap_err().kind());
+
+        // after
+        fs::remove_dir_all("temp-invalid").expect("Error cleaning up test.");
+    }
+}